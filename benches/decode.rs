@@ -0,0 +1,95 @@
+//! Decoding benchmarks over large generated documents.
+//!
+//! Each group decodes the same shape of document at several sizes and
+//! reports throughput in bytes, so linear scaling shows up as a flat
+//! throughput across sizes. Run with `cargo bench --bench decode`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use toml_rust::{loads, loads_borrowed, loads_with_diagnostics, EventReader};
+
+/// The approximate document sizes, in bytes.
+const SIZES: [usize; 3] = [10_000, 100_000, 1_000_000];
+
+/// A PEM-style certificate body: base64 lines in a multi-line string.
+fn certificate(size: usize) -> String {
+    let line = "MIIDdzCCAl+gAwIBAgIEAgAAuTANBgkqhkiG9w0BAQUFADBaMQswCQYDVQQGEwJJ\n";
+    format!("cert = \"\"\"\n{}\"\"\"\n", line.repeat(size / line.len()))
+}
+
+/// A basic string dense with escapes, so it must be unescaped into a copy.
+fn escaped(size: usize) -> String {
+    let chunk = "tab\\there \\\"quoted\\\" \\u00e9\\U0001F600\\n";
+    format!("s = \"{}\"\n", chunk.repeat(size / chunk.len()))
+}
+
+/// A single long array of mixed strings and nested arrays.
+fn array(size: usize) -> String {
+    let item = "\"item\", [1, 2.5, true], ";
+    format!("a = [{}]\n", item.repeat(size / item.len()))
+}
+
+/// Many tables, each with a few keys.
+fn tables(size: usize) -> String {
+    let mut doc = String::with_capacity(size + 64);
+    let mut i = 0;
+    while doc.len() < size {
+        doc.push_str(&format!("[server.node{i}]\nhost = \"10.0.0.{}\"\nport = {}\ntags.role = 'worker'\n\n", i % 256, 8000 + i % 1000));
+        i += 1;
+    }
+    doc
+}
+
+/// Lines that are errors every other line, either a missing value or a
+/// key redefining the first line's, for `loads_with_diagnostics`.
+fn broken(size: usize) -> String {
+    let mut doc = String::with_capacity(size + 64);
+    let mut i = 0;
+    while doc.len() < size {
+        doc.push_str(&match i % 4 {
+            1 => format!("k{i} = \n"),
+            3 => "k0 = 2\n".to_string(),
+            _ => format!("k{i} = {i}\n"),
+        });
+        i += 1;
+    }
+    doc
+}
+
+type Generator = fn(usize) -> String;
+
+const DOCUMENTS: [(&str, Generator); 4] = [("certificate", certificate), ("escaped", escaped), ("array", array), ("tables", tables)];
+
+fn decode(c: &mut Criterion) {
+    for (name, generate) in DOCUMENTS {
+        let mut group = c.benchmark_group(name);
+        for size in SIZES {
+            let doc = generate(size);
+            group.throughput(Throughput::Bytes(doc.len() as u64));
+            group.bench_with_input(BenchmarkId::new("loads", size), &doc, |b, doc| b.iter(|| loads(black_box(doc), None, None).unwrap()));
+            group.bench_with_input(BenchmarkId::new("loads_borrowed", size), &doc, |b, doc| {
+                b.iter(|| loads_borrowed(black_box(doc)).unwrap())
+            });
+            group.bench_with_input(BenchmarkId::new("events", size), &doc, |b, doc| {
+                b.iter(|| EventReader::new(black_box(doc.as_bytes())).count())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn diagnostics(c: &mut Criterion) {
+    let mut group = c.benchmark_group("broken");
+    for size in SIZES {
+        let doc = broken(size);
+        group.throughput(Throughput::Bytes(doc.len() as u64));
+        group.bench_with_input(BenchmarkId::new("loads_with_diagnostics", size), &doc, |b, doc| {
+            b.iter(|| loads_with_diagnostics(black_box(doc), None, None))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode, diagnostics);
+criterion_main!(benches);
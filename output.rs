@@ -1,2498 +1,6032 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{BufRead, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref TIME_RE: Regex = Regex::new(r"^([0-9]{2}):([0-9]{2}):([0-9]{2})(\.([0-9]+))?$").unwrap();
+    /// An RFC 3339 date, optionally followed by a time and an offset
+    static ref DATETIME_RE: Regex = Regex::new(
+        r"^([0-9]{4})-([0-9]{2})-([0-9]{2})([Tt ]([0-9]{2}):([0-9]{2}):([0-9]{2})(\.([0-9]+))?([Zz]|[+-][0-9]{2}:[0-9]{2})?)?$"
+    )
+    .unwrap();
+    /// Numbers allow single underscores between digits for readability
+    static ref DEC_INT_RE: Regex = Regex::new(r"^[+-]?(0|[1-9](_?[0-9])*)$").unwrap();
+    static ref PREFIXED_INT_RE: Regex = Regex::new(r"^0(x[0-9A-Fa-f](_?[0-9A-Fa-f])*|o[0-7](_?[0-7])*|b[01](_?[01])*)$").unwrap();
+    static ref FLOAT_RE: Regex = Regex::new(r"^[+-]?(0|[1-9](_?[0-9])*)(\.[0-9](_?[0-9])*)?([eE][+-]?[0-9](_?[0-9])*)?$").unwrap();
+    static ref GROUPNAME_RE: Regex = Regex::new(r"^[A-Za-z0-9_-]+$").unwrap();
+    /// A line that starts a table header or key/value pair, where decoding
+    /// can resume after an error
+    static ref STATEMENT_RE: Regex = {
+        let key = r#"([A-Za-z0-9_-]+|"([^"\\]|\\.)*"|'[^']*')"#;
+        let dotted = format!(r"{key}([ \t]*\.[ \t]*{key})*");
+        Regex::new(&format!(r"^[ \t]*((?P<header>\[\[?[ \t]*{dotted}[ \t]*\]\]?[ \t]*(#.*)?\r?$)|{dotted}[ \t]*=)")).unwrap()
+    };
+}
 
+/// The category of a `TomlDecodeError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A key was assigned twice, or a table header reuses a key holding a
+    /// non-table value.
+    DuplicateKey,
+    /// A table or array of tables was defined twice.
+    DuplicateTable,
+    /// A bare key contains invalid characters or is empty.
+    InvalidKey,
+    /// A `[table]` or `[[array]]` header is malformed.
+    InvalidTableHeader,
+    /// A key is not followed by `=` and a value.
+    KeyWithoutValue,
+    /// A value is missing or of no recognisable type.
+    InvalidValue,
+    /// A basic string contains a reserved or malformed escape sequence.
+    InvalidEscape,
+    /// A string is not closed before the end of its line or the document.
+    UnterminatedString,
+    /// A string is followed by unexpected characters.
+    InvalidString,
+    /// An integer or float is malformed or out of range.
+    InvalidNumber,
+    /// A date, time or datetime is malformed.
+    InvalidDatetime,
+    /// An array is malformed or not terminated.
+    InvalidArray,
+    /// An inline table is malformed.
+    InvalidInlineTable,
+    /// The input could not be read.
+    Io,
+    /// `load` was given an input it does not accept.
+    InvalidInput,
+    /// A token appeared where the grammar does not allow it.
+    UnexpectedToken,
+    /// A comment or string contains a control character.
+    InvalidCharacter,
+}
 
+/// Base toml Exception / Error.
+#[derive(Debug, Clone)]
 pub struct TomlDecodeError {
+    kind: ErrorKind,
     msg: String,
-    doc: String,
-    pos: usize,
+    doc: Arc<str>,
+    span: Range<usize>,
     lineno: usize,
     colno: usize,
+    /// The byte offset of `doc` in the input, when `doc` is only the part
+    /// of it being read, as for `EventReader`.
+    doc_offset: usize,
+    /// The first definition of a key or table the error redefines.
+    original: Option<Box<Span>>,
+}
+
+impl TomlDecodeError {
+    /// Creates a new TomlDecodeError.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The category of the error.
+    /// * `msg` - A message describing the error.
+    /// * `doc` - The document where the error occurred.
+    /// * `span` - The byte range in the document the error refers to.
+    pub fn new(kind: ErrorKind, msg: String, doc: impl Into<Arc<str>>, span: Range<usize>) -> TomlDecodeError {
+        TomlDecodeError::located(kind, msg, doc.into(), span, _line_col)
+    }
+
+    /// Returns the category of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the error message without position information.
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+
+    /// Returns the document the error was found in.
+    pub fn doc(&self) -> &str {
+        &self.doc
+    }
+
+    /// Returns the byte offset of `doc()` in the input. It is zero unless
+    /// the input was read a piece at a time, as by `EventReader`.
+    pub fn doc_offset(&self) -> usize {
+        self.doc_offset
+    }
+
+    /// Returns the byte range in `doc()` the error refers to.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// Returns the byte offset in the input the error starts at.
+    pub fn pos(&self) -> usize {
+        self.doc_offset + self.span.start
+    }
+
+    /// Returns the 1-based line the error starts on.
+    pub fn lineno(&self) -> usize {
+        self.lineno
+    }
+
+    /// Returns the 1-based column (in characters) the error starts at.
+    pub fn colno(&self) -> usize {
+        self.colno
+    }
+
+    /// Returns where the key or table that the error redefines was first
+    /// defined, for duplicate keys and tables.
+    pub fn original(&self) -> Option<&Span> {
+        self.original.as_deref()
+    }
+
+    /// Renders the offending source line with the error span underlined,
+    /// e.g.
+    ///
+    /// ```text
+    /// error: Duplicate keys!
+    ///  --> line 2, column 1
+    ///   |
+    /// 2 | a = 2
+    ///   | ^
+    /// ```
+    ///
+    /// A redefinition also shows the first definition:
+    ///
+    /// ```text
+    /// error: Duplicate key a
+    ///  --> line 2, column 1
+    ///   |
+    /// 2 | a = 2
+    ///   | ^
+    /// note: first defined here
+    ///  --> line 1, column 1
+    ///   |
+    /// 1 | a = 1
+    ///   | ^
+    /// ```
+    pub fn snippet(&self) -> String {
+        let lineno = self.original.as_ref().map_or(self.lineno, |original| original.lineno.max(self.lineno));
+        let gutter = " ".repeat(lineno.to_string().len());
+        let mut snippet = format!("error: {}\n{}", self.msg, self.underline(&self.span, self.lineno, self.colno, &gutter));
+        if let Some(original) = &self.original {
+            snippet.push_str("note: first defined here\n");
+            snippet.push_str(&self.underline(&original.bytes, original.lineno, original.colno, &gutter));
+        }
+        snippet
+    }
+
+    /// Renders the line `span` starts on with the span underlined. The
+    /// carets are indented with the line's own tabs so that they line up
+    /// however wide a terminal draws a tab.
+    fn underline(&self, span: &Range<usize>, lineno: usize, colno: usize, gutter: &str) -> String {
+        let line_start = self.doc[..span.start].rfind('\n').map_or(0, |nl| nl + 1);
+        let line_end = self.doc[span.start..].find('\n').map_or(self.doc.len(), |nl| span.start + nl);
+        let line = self.doc[line_start..line_end].trim_end_matches('\r');
+        let underlined_end = span.end.clamp(span.start, line_start + line.len());
+        let carets = self.doc[span.start..underlined_end].chars().count().max(1);
+        let indent: String = self.doc[line_start..span.start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        format!(
+            "{}--> line {}, column {}\n{} |\n{:>width$} | {}\n{} | {}{}\n",
+            gutter,
+            lineno,
+            colno,
+            gutter,
+            lineno,
+            line,
+            gutter,
+            indent,
+            "^".repeat(carets),
+            width = gutter.len()
+        )
+    }
 }
 
 impl TomlDecodeError {
-    pub fn new(msg: String, doc: String, pos: usize) -> TomlDecodeError {
-        let lineno = doc[..pos].matches('\n').count() + 1;
-        let colno = pos - doc.rfind('\n', pos).unwrap_or(0);
-        let emsg = format!("{} (line {} column {} char {})", msg, lineno, colno, pos);
+    /// As `new`, with `line_col` finding the line and column of a byte
+    /// offset in `doc`.
+    fn located(kind: ErrorKind, msg: String, doc: Arc<str>, span: Range<usize>, line_col: impl FnOnce(&str, usize) -> (usize, usize)) -> TomlDecodeError {
+        let mut start = span.start.min(doc.len());
+        while !doc.is_char_boundary(start) {
+            start -= 1;
+        }
+        let mut end = span.end.clamp(start, doc.len());
+        while !doc.is_char_boundary(end) {
+            end += 1;
+        }
+        let (lineno, colno) = line_col(&doc, start);
         TomlDecodeError {
+            kind,
             msg,
             doc,
-            pos,
+            span: start..end,
             lineno,
             colno,
+            doc_offset: 0,
+            original: None,
         }
     }
-}
 
-pub struct CommentValue {
-    val: String,
-    comment: String,
-    _dict: std::collections::HashMap<String, String>, // Assuming _dict is a dictionary
+    /// Places an error found in a piece of a larger input, which starts at
+    /// byte `offset` after `lines` whole lines.
+    fn within(mut self, offset: usize, lines: usize) -> TomlDecodeError {
+        self.doc_offset = offset;
+        self.lineno += lines;
+        if let Some(original) = &mut self.original {
+            original.lineno += lines;
+        }
+        self
+    }
+
+    /// Records `original` as the first definition of what the error
+    /// redefines.
+    fn with_original(mut self, original: Span) -> TomlDecodeError {
+        self.original = Some(Box::new(original));
+        self
+    }
 }
 
-impl CommentValue {
-    pub fn new(val: String, comment: String, beginline: bool, _dict: std::collections::HashMap<String, String>) -> CommentValue {
-        let separator = if beginline { "\n".to_string() } else { " ".to_string() };
-        let comment = separator + &comment;
-        CommentValue { val, comment, _dict }
+impl std::fmt::Display for TomlDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (line {} column {} char {})", self.msg, self.lineno, self.colno, self.pos())?;
+        if let Some(original) = &self.original {
+            write!(f, ", first defined at line {} column {}", original.lineno, original.colno)?;
+        }
+        Ok(())
     }
 }
 
-pub struct CommentValue {
-    val: Vec<Any>, // Adjust type as necessary
-    comment: String,
-    _dict: HashMap<String, Any>, // Adjust type as necessary
+/// Returns the 1-based line and column (in characters) of byte `pos` in
+/// `doc`, which must be a char boundary.
+fn _line_col(doc: &str, pos: usize) -> (usize, usize) {
+    let before = &doc[..pos];
+    let lineno = before.matches('\n').count() + 1;
+    let colno = match before.rfind('\n') {
+        Some(nl) => before[nl + 1..].chars().count() + 1,
+        None => before.chars().count() + 1,
+    };
+    (lineno, colno)
 }
 
-impl CommentValue {
-    pub fn __getitem__(&self, key: usize) -> &Any { // Adjust return type as necessary
-        &self.val[key]
+impl std::error::Error for TomlDecodeError {}
+
+/// The storage behind a `Table`: a map from (unquoted) key names to
+/// values.
+///
+/// This plays the part of the dict class passed as `_dict` to the Python
+/// decoder. It is implemented for `HashMap`, `BTreeMap` and `OrderedTable`,
+/// and any other map, such as an arena-backed one, can be plugged in by
+/// implementing it and passing `Table::backed::<T>` as the table factory.
+/// `loads` then builds every table of the document in a `T`:
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use toml_rust::{loads, Table, Value};
+///
+/// let table = loads("b = 1\n[t]\nz = 2\ny = 3\n", Some(Table::backed::<BTreeMap<String, Value>>), None)?;
+/// assert_eq!(table.keys().collect::<Vec<_>>(), ["b", "t"]);
+/// assert_eq!(table["t"].as_table().unwrap().keys().collect::<Vec<_>>(), ["y", "z"]);
+/// # Ok::<(), toml_rust::TomlDecodeError>(())
+/// ```
+pub trait TableLike: TableLikeClone + Send + Sync {
+    /// Inserts a value, returning the one it replaced.
+    fn insert(&mut self, key: String, value: Value) -> Option<Value>;
+
+    /// Returns the value for `key`.
+    fn get(&self, key: &str) -> Option<&Value>;
+
+    /// Returns the value for `key` mutably.
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value>;
+
+    /// Returns an iterator over the entries.
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_>;
+
+    /// Returns the number of entries.
+    fn len(&self) -> usize;
+
+    /// Removes the entry for `key`, returning its value.
+    fn remove(&mut self, key: &str) -> Option<Value>;
+
+    /// Returns an iterator over the entries with mutable values.
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (&String, &mut Value)> + '_>;
+
+    /// Returns true if the table has no entries.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
-}
 
-impl CommentValue {
-    pub fn __setitem__(&mut self, key: &str, value: impl std::fmt::Debug) {
-        self.val.insert(key.to_string(), value);
+    /// Returns true if `iter` yields the keys in insertion order.
+    fn is_ordered(&self) -> bool {
+        false
     }
-}
 
-impl CommentValue {
-    pub fn dump(&self, dump_value_func: &dyn Fn(&str) -> String) -> String {
-        let retstr = dump_value_func(&self.val);
-        if self.val.is_instance_of::<self._dict>() {
-            return format!("{}\n{}", self.comment, retstr);
-        } else {
-            return format!("{}{}", retstr, self.comment);
-        }
+    /// Consumes the table, returning its entries in iteration order.
+    fn into_entries(self: Box<Self>) -> Vec<(String, Value)> {
+        self.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
     }
 }
 
-pub fn load(f: &dyn std::io::Read, _dict: Option<Type> = None, decoder: Option<&TomlDecoder>) -> Result<HashMap<String, Value>, TomlDecodeError> {
-    /// Parses named file or files as toml and returns a dictionary
-    ///
-    /// Args:
-    ///     f: Path to the file to open, array of files to read into single dict
-    ///        or a file descriptor
-    ///     _dict: (optional) Specifies the class of the returned toml dictionary
-    ///     decoder: The decoder to use
-    ///
-    /// Returns:
-    ///     Parsed toml file represented as a dictionary
-    ///
-    /// Raises:
-    ///     TypeError -- When f is invalid type
-    ///     TomlDecodeError: Error while decoding toml
-    ///     IOError / FileNotFoundError -- When an array with no valid (existing)
-    ///     (Python 2 / Python 3)          file paths is passed
+/// Lets a boxed `TableLike` be cloned. Implemented for every `TableLike`
+/// that is `Clone`.
+pub trait TableLikeClone {
+    fn clone_box(&self) -> Box<dyn TableLike>;
+}
 
-    if _ispath(f) {
-        let ffile = std::fs::File::open(_getpath(f)?)?;
-        return loads(ffile.read_to_string()?, _dict, decoder);
-    } else if let Some(file_list) = f.downcast_ref::<Vec<String>>() {
-        use std::fs;
-        use std::path::Path;
+impl<T: TableLike + Clone + 'static> TableLikeClone for T {
+    fn clone_box(&self) -> Box<dyn TableLike> {
+        Box::new(self.clone())
+    }
+}
 
-        if !file_list.iter().any(|path| Path::new(path).exists()) {
-            let error_msg = "Load expects a list to contain filenames only.\nThe list needs to contain the path of at least one existing file.";
-            return Err(FNFError(error_msg.to_string()));
-        }
+impl TableLike for HashMap<String, Value> {
+    fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        HashMap::insert(self, key, value)
+    }
 
-        let mut decoder = decoder.unwrap_or_else(|| TomlDecoder::new(_dict));
-        let mut d = decoder.get_empty_table();
+    fn get(&self, key: &str) -> Option<&Value> {
+        HashMap::get(self, key)
+    }
 
-        for l in file_list {
-            if Path::new(l).exists() {
-                d.extend(load(l, _dict, Some(&decoder))?);
-            } else {
-                warn("Non-existent filename in list with at least one valid filename");
-            }
-        }
-        return Ok(d);
-    } else {
-        let mut reader = f;
-        let content = reader.read_to_string()?;
-        return loads(content, _dict, decoder);
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        HashMap::get_mut(self, key)
     }
-}
 
-use std::collections::HashMap;
-use std::io::BufReader;
-use chrono::{NaiveDate, NaiveDateTime};
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        Box::new(HashMap::iter(self))
+    }
 
-/// A decoder for TOML data.
-#[derive(Debug, Clone)]
-pub struct TomlDecoder {
-    // You can add fields here if needed, for example:
-    // dict: HashMap<String, String>,
-}
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
 
-impl TomlDecoder {
-    /// Creates a new instance of TomlDecoder.
-    pub fn new() -> Self {
-        TomlDecoder {
-            // Initialize fields if any
-        }
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        HashMap::remove(self, key)
     }
 
-    /// Splits a line on quotes.
-    pub fn get_split_on_quotes(&self, line: &str) -> Vec<String> {
-        // Implementation goes here
-        vec![]
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (&String, &mut Value)> + '_> {
+        Box::new(HashMap::iter_mut(self))
     }
 
-    /// Loads an array as a string array.
-    pub fn load_array_isstrarray(&self, a: &str) {
-        // Implementation goes here
+    fn into_entries(self: Box<Self>) -> Vec<(String, Value)> {
+        self.into_iter().collect()
     }
+}
 
-    /// Loads a multiline string from a line.
-    pub fn load_line_multiline_str(&self, p: &str) -> (usize, usize) {
-        // Implementation goes here
-        (0, 0)
+impl TableLike for BTreeMap<String, Value> {
+    fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        BTreeMap::insert(self, key, value)
     }
 
-    /// Checks if a bounded string is valid.
-    pub fn bounded_string(&self, s: &str) -> bool {
-        // Implementation goes here
-        false
+    fn get(&self, key: &str) -> Option<&Value> {
+        BTreeMap::get(self, key)
     }
 
-    /// Embeds comments into the current level.
-    pub fn embed_comments(&self, idx: usize, currentlevel: &mut HashMap<String, String>) {
-        // Implementation goes here
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        BTreeMap::get_mut(self, key)
     }
 
-    /// Returns an empty table.
-    pub fn get_empty_table(&self) -> HashMap<String, String> {
-        HashMap::new()
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        Box::new(BTreeMap::iter(self))
     }
 
-    /// Loads an array from a string.
-    pub fn load_array(&self, a: &str) -> Vec<Box<dyn std::any::Any>> {
-        // Implementation goes here
-        vec![]
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
     }
 
-    /// Loads a value from a line.
-    pub fn load_value(&self, v: &str, strictly_valid: bool) -> Box<dyn std::any::Any> {
-        // Implementation goes here
-        Box::new(())
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        BTreeMap::remove(self, key)
     }
 
-    /// Preserves a comment associated with a key.
-    pub fn preserve_comment(&self, line_no: usize, key: &str, comment: &str, beginline: bool) {
-        // Implementation goes here
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (&String, &mut Value)> + '_> {
+        Box::new(BTreeMap::iter_mut(self))
     }
-}
 
-/// Detects if the given path is a pathlib path.
-fn detect_pathlib_path(p: &BufReader<dyn std::io::Read>) {
-    // Implementation goes here
+    fn into_entries(self: Box<Self>) -> Vec<(String, Value)> {
+        self.into_iter().collect()
+    }
 }
 
-/// Checks if the given path is valid.
-fn is_path(p: &BufReader<dyn std::io::Read>) -> bool {
-    // Implementation goes here
-    false
+/// A map that keeps its keys in insertion order, like Python's
+/// `OrderedDict`.
+#[derive(Debug, Clone, Default)]
+pub struct OrderedTable {
+    entries: Vec<(String, Value)>,
+    /// The position of each key in `entries`.
+    index: HashMap<String, usize>,
 }
 
-/// Loads a date from a string.
-fn load_date(val: &str) -> Result<NaiveDate, NaiveDateTime> {
-    // Implementation goes here
-    Err(NaiveDateTime::from_timestamp(0, 0))
-}
+impl TableLike for OrderedTable {
+    /// A replaced key keeps its position.
+    fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        match self.index.get(&key) {
+            Some(&i) => Some(std::mem::replace(&mut self.entries[i].1, value)),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
 
-/// Loads unicode escapes from a string.
-fn load_unicode_escapes(v: &str, hexbytes: Vec<String>, prefix: &str) -> String {
-    // Implementation goes here
-    String::new()
-}
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
 
-/// Checks if a number is strictly valid.
-fn strictly_valid_num(n: &str) {
-    // Implementation goes here
-}
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.index.get(key).map(|&i| &mut self.entries[i].1)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        Box::new(self.entries.iter().map(|(k, v)| (k, v)))
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.index.get_mut(key).expect("every entry is indexed") -= 1;
+        }
+        Some(value)
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (&String, &mut Value)> + '_> {
+        Box::new(self.entries.iter_mut().map(|(k, v)| (&*k, v)))
+    }
+
+    fn is_ordered(&self) -> bool {
+        true
+    }
 
-/// Unescapes a string.
-fn unescape(v: &str) -> String {
-    // Implementation goes here
-    String::new()
+    fn into_entries(self: Box<Self>) -> Vec<(String, Value)> {
+        self.entries
+    }
 }
 
-pub struct TomlDecoder {
-    _dict: Box<dyn std::any::Any>, // Using Box<dyn Any> to represent Type[dict]
+/// A table of decoded TOML values keyed by their (unquoted) key names.
+///
+/// A table made by `Table::new` is backed by a `HashMap` and iterates in
+/// no particular order. One made by `Table::ordered` keeps its keys in
+/// insertion order, like the `OrderedDict` of `TomlOrderedDecoder`, so
+/// that a document decodes and dumps with its keys where they were. Any
+/// other `TableLike` can back a table through `Table::backed`.
+///
+/// A table decoded from an inline table, `{ ... }`, is marked as inline,
+/// like the `InlineTableDict` of the Python decoder, and encoders write it
+/// back inline.
+pub struct Table {
+    inner: Box<dyn TableLike>,
+    inline: bool,
 }
 
-impl TomlDecoder {
-    pub fn __init__(_dict: Box<dyn std::any::Any>) -> TomlDecoder {
-        TomlDecoder { _dict }
+impl Default for Table {
+    fn default() -> Self {
+        Table::backed::<HashMap<String, Value>>()
     }
 }
 
-impl TomlDecoder {
-    pub fn load_line(
-        &self,
-        line: &str,
-        currentlevel: &mut std::collections::HashMap<String, Box<dyn std::any::Any>>,
-        multikey: Option<&str>,
-        multibackslash: bool,
-    ) -> Result<(String, String, bool), String> {
-        let mut i = 1;
-        let quotesplits = self._get_split_on_quotes(line);
-        let mut quoted = false;
-        for quotesplit in quotesplits {
-            if !quoted && quotesplit.contains('=') {
-                break;
-            }
-            i += quotesplit.matches('=').count();
-            quoted = !quoted;
-        }
-        let pair: Vec<&str> = line.splitn(i, '=').collect();
-        let mut strictly_valid = _strictly_valid_num(pair.last().unwrap_or(&"")).unwrap_or(false);
-        let mut pair_last = pair.last().unwrap_or(&"").to_string();
-        if _number_with_underscores.is_match(&pair_last) {
-            pair_last = pair_last.replace('_', "");
-        }
-        while !pair_last.is_empty() && !matches!(pair_last.chars().next().unwrap(), ' ' | '\t' | '\'' | '"' | '[' | '{') && pair_last.trim() != "true" && pair_last.trim() != "false" {
-            if pair_last.parse::<f64>().is_ok() {
-                break;
-            }
-            if _load_date(&pair_last).is_some() {
-                break;
-            }
-            if TIME_RE.is_match(&pair_last) {
-                break;
-            }
-            i += 1;
-            let prev_val = pair_last.clone();
-            let pair: Vec<&str> = line.splitn(i, '=').collect();
-            pair_last = pair.last().unwrap_or(&"").to_string();
-            if prev_val == pair_last {
-                return Err("Invalid date or number".to_string());
-            }
-            if strictly_valid {
-                strictly_valid = _strictly_valid_num(pair_last.as_str()).unwrap_or(false);
-            }
-        }
-        let pair = vec![
-            pair[..pair.len() - 1].join("=").trim().to_string(),
-            pair_last.trim().to_string(),
-        ];
-        let mut key = pair[0].clone();
-        if key.contains('.') {
-            if key.contains('"') || key.contains('\'') {
-                let quotesplits = self._get_split_on_quotes(&key);
-                let mut quoted = false;
-                let mut levels = Vec::new();
-                for quotesplit in quotesplits {
-                    if quoted {
-                        levels.push(quotesplit);
-                    } else {
-                        levels.extend(quotesplit.split('.').map(|s| s.trim().to_string()));
-                    }
-                    quoted = !quoted;
-                }
-                key = levels.pop().unwrap_or_default();
-            } else {
-                key = key.split('.').last().unwrap_or("").trim().to_string();
-            }
-        } else if (key.chars().next().unwrap() == '"' || key.chars().next().unwrap() == '\'') && (key.chars().last().unwrap() == key.chars().next().unwrap()) {
-            key = _unescape(&key[1..key.len()-1]);
-        }
-        let (k, koffset) = self._load_line_multiline_str(&pair[1])?;
-        let mut multilinestr = String::new();
-        if k > -1 {
-            let mut k = k;
-            while k > -1 && pair[1].chars().nth(k + koffset) == Some('\\') {
-                multibackslash = !multibackslash;
-                k -= 1;
-            }
-            multilinestr = if multibackslash {
-                pair[1][..pair[1].len()-1].to_string()
-            } else {
-                format!("{}\n", pair[1])
-            };
-            multikey = Some(&pair[0]);
-        } else {
-            let (value, vtype) = self.load_value(&pair[1], strictly_valid)?;
+impl Clone for Table {
+    fn clone(&self) -> Self {
+        Table {
+            inner: self.inner.clone_box(),
+            inline: self.inline,
         }
-        if currentlevel.contains_key(&key) {
-            return Err("Duplicate keys!".to_string());
-        } else {
-            if let Some(multikey) = multikey {
-                return Ok((multikey.to_string(), multilinestr, multibackslash));
-            } else {
-                currentlevel.insert(key, value);
-            }
-        }
-        Ok((String::new(), String::new(), false))
     }
 }
 
-impl TomlDecoder {
-    pub fn _load_line_multiline_str(&self, p: &str) -> (i32, usize) {
-        let mut poffset = 0;
-        if p.len() < 3 {
-            return (-1, poffset);
-        }
-        if p.chars().next().unwrap() == '[' && (p.trim().chars().last().unwrap() != ']' && self._load_array_isstrarray(p)) {
-            let mut newp: Vec<&str> = p[1..].trim().split(',').collect();
-            while newp.len() > 1 && !newp.last().unwrap().starts_with('"') && !newp.last().unwrap().starts_with('\'') {
-                let last = newp.pop().unwrap();
-                let second_last = newp.pop().unwrap();
-                newp.push(&format!("{},{}", second_last, last));
-            }
-            let last = newp.pop().unwrap();
-            poffset = p.len() - last.len();
-            p = last;
-        }
-        if !p.starts_with('"') && !p.starts_with('\'') {
-            return (-1, poffset);
-        }
-        if p.chars().nth(1).unwrap() != p.chars().next().unwrap() || p.chars().nth(2).unwrap() != p.chars().next().unwrap() {
-            return (-1, poffset);
-        }
-        if p.len() > 5 && p.chars().last().unwrap() == p.chars().next().unwrap() && p.chars().nth_back(1).unwrap() == p.chars().next().unwrap() && p.chars().nth_back(2).unwrap() == p.chars().next().unwrap() {
-            return (-1, poffset);
-        }
-        (p.len() as i32 - 1, poffset)
+impl Table {
+    /// Creates an empty table that iterates in no particular order.
+    pub fn new() -> Table {
+        Table::default()
     }
-}
 
-impl TomlDecoder {
-    pub fn load_array(&self, a: &str) -> Vec<Option<Box<dyn std::any::Any>>> {
-        let mut retval: Vec<Option<Box<dyn std::any::Any>>> = Vec::new();
-        let a = a.trim();
-        if !a[1..a.len()-1].contains('[') || a[1..a.len()-1].split('[').next().unwrap().trim() != "" {
-            let strarray = self._load_array_isstrarray(a);
-            let mut a = if !a[1..a.len()-1].trim().starts_with('{') {
-                a[1..a.len()-1].split(',').map(|s| s.to_string()).collect::<Vec<String>>()
-            } else {
-                let mut new_a: Vec<String> = Vec::new();
-                let mut start_group_index = 1;
-                let mut end_group_index = 2;
-                let mut open_bracket_count = if a.chars().nth(start_group_index).unwrap() == '{' { 1 } else { 0 };
-                let mut in_str = false;
-
-                while end_group_index < a.len() - 1 {
-                    if a.chars().nth(end_group_index).unwrap() == '"' || a.chars().nth(end_group_index).unwrap() == '\'' {
-                        if in_str {
-                            let mut backslash_index = end_group_index - 1;
-                            while backslash_index > 0 && a.chars().nth(backslash_index).unwrap() == '\\' {
-                                in_str = !in_str;
-                                backslash_index -= 1;
-                            }
-                        }
-                        in_str = !in_str;
-                    }
-                    if !in_str && a.chars().nth(end_group_index).unwrap() == '{' {
-                        open_bracket_count += 1;
-                    }
-                    if in_str || a.chars().nth(end_group_index).unwrap() != '}' {
-                        end_group_index += 1;
-                        continue;
-                    } else if a.chars().nth(end_group_index).unwrap() == '}' && open_bracket_count > 1 {
-                        open_bracket_count -= 1;
-                        end_group_index += 1;
-                        continue;
-                    }
+    /// Creates an empty table that iterates in insertion order.
+    pub fn ordered() -> Table {
+        Table::backed::<OrderedTable>()
+    }
 
-                    end_group_index += 1;
-                    new_a.push(a[start_group_index..end_group_index].to_string());
-                    start_group_index = end_group_index + 1;
-                    while start_group_index < a.len() - 1 && a.chars().nth(start_group_index).unwrap() != '{' {
-                        start_group_index += 1;
-                    }
-                    end_group_index = start_group_index + 1;
-                }
-                new_a
-            };
+    /// Creates an empty table stored in a `T`. `Table::backed::<T>` can be
+    /// passed wherever a `_dict` table factory is expected.
+    pub fn backed<T: TableLike + Default + 'static>() -> Table {
+        Table::from_table_like(T::default())
+    }
 
-            let mut b = 0;
-            if strarray {
-                while b < a.len() - 1 {
-                    let mut ab = a[b].trim().to_string();
-                    while !self.bounded_string(&ab) || (ab.len() > 2 && ab.chars().nth(0).unwrap() == ab.chars().nth(1).unwrap() && ab.chars().nth(2).unwrap() == ab.chars().nth(0).unwrap() && ab.chars().nth(ab.len() - 2).unwrap() != ab.chars().nth(0).unwrap() && ab.chars().nth(ab.len() - 3).unwrap() != ab.chars().nth(0).unwrap()) {
-                        a[b] = format!("{}{}", a[b], a[b + 1]);
-                        ab = a[b].trim().to_string();
-                        if b < a.len() - 2 {
-                            a.remove(b + 1);
-                        } else {
-                            a.pop();
-                        }
-                    }
-                    b += 1;
-                }
-            }
-        } else {
-            let al: Vec<char> = a[1..a.len()-1].chars().collect();
-            let mut a: Vec<String> = Vec::new();
-            let mut openarr = 0;
-            let mut j = 0;
-            for i in 0..al.len() {
-                if al[i] == '[' {
-                    openarr += 1;
-                } else if al[i] == ']' {
-                    openarr -= 1;
-                } else if al[i] == ',' && openarr == 0 {
-                    a.push(al[j..i].iter().collect());
-                    j = i + 1;
-                }
-            }
-            a.push(al[j..].iter().collect());
+    /// Wraps an existing `TableLike` in a table.
+    pub fn from_table_like<T: TableLike + 'static>(inner: T) -> Table {
+        Table {
+            inner: Box::new(inner),
+            inline: false,
         }
-        for i in 0..a.len() {
-            let trimmed = a[i].trim();
-            if !trimmed.is_empty() {
-                let (nval, _ntype) = self.load_value(trimmed);
-                retval.push(nval);
-            }
+    }
+
+    /// Returns the storage behind this table.
+    pub fn inner(&self) -> &dyn TableLike {
+        &*self.inner
+    }
+
+    /// Returns true if the table keeps its keys in insertion order.
+    pub fn is_ordered(&self) -> bool {
+        self.inner.is_ordered()
+    }
+
+    /// Returns true if the table is an inline table.
+    pub fn is_inline(&self) -> bool {
+        self.inline
+    }
+
+    /// Marks the table as an inline table, or as a regular one.
+    pub fn set_inline(&mut self, inline: bool) {
+        self.inline = inline;
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns true if the table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns true if the table has an entry for `key`.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.inner.get(key).is_some()
+    }
+
+    /// Returns the value for `key`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.inner.get(key)
+    }
+
+    /// Returns the value for `key` mutably.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.inner.get_mut(key)
+    }
+
+    /// Inserts a value, returning the one it replaced. A replaced key
+    /// keeps its position in an ordered table.
+    pub fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        self.inner.insert(key, value)
+    }
+
+    /// Removes the entry for `key`, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.inner.remove(key)
+    }
+
+    /// Returns the value for `key`, inserting `default()` first if the key
+    /// is missing.
+    pub fn get_or_insert_with(&mut self, key: &str, default: impl FnOnce() -> Value) -> &mut Value {
+        if !self.contains_key(key) {
+            self.insert(key.to_string(), default());
         }
-        retval
+        self.get_mut(key).expect("the key was just inserted")
     }
-}
 
-impl TomlDecoder {
-    pub fn preserve_comment(&self, line_no: i32, key: &str, comment: &str, beginline: bool) {
-        // ...
+    /// Returns an iterator over the keys.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.iter().map(|(k, _)| k)
     }
-}
 
-impl TomlDecoder {
-    pub fn embed_comments(&self, idx: i32, currentlevel: &mut HashMap<String, Box<dyn Any>>) {
-        // ...
+    /// Returns an iterator over the values.
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.iter().map(|(_, v)| v)
     }
-}
 
-use std::collections::HashMap;
+    /// Returns an iterator over the entries.
+    pub fn iter(&self) -> TableIter<'_> {
+        TableIter(self.inner.iter())
+    }
 
-pub struct TomlPreserveCommentDecoder {
-    saved_comments: HashMap<String, String>,
+    /// Returns an iterator over the entries with mutable values.
+    pub fn iter_mut(&mut self) -> TableIterMut<'_> {
+        TableIterMut(self.inner.iter_mut())
+    }
 }
 
-impl TomlPreserveCommentDecoder {
-    pub fn __init__(_dict: Option<HashMap<String, String>>) -> TomlPreserveCommentDecoder {
-        let saved_comments = HashMap::new();
-        let decoder = TomlPreserveCommentDecoder { saved_comments };
-        // Call to the superclass constructor would go here
-        decoder
+impl std::fmt::Debug for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
     }
 }
 
-impl TomlPreserveCommentDecoder {
-    pub fn preserve_comment(&mut self, line_no: usize, key: String, comment: String, beginline: bool) {
-        self.saved_comments.insert(line_no, (key, comment, beginline));
+/// Tables compare equal when they hold the same entries, in any order,
+/// whether or not they are inline.
+impl PartialEq for Table {
+    fn eq(&self, other: &Table) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
     }
 }
 
-impl TomlPreserveCommentDecoder {
-    pub fn embed_comments(&self, idx: usize, currentlevel: &mut HashMap<String, CommentValue>) {
-        if !self.saved_comments.contains_key(&idx) {
-            return;
-        }
+impl std::ops::Index<&str> for Table {
+    type Output = Value;
 
-        let (key, comment, beginline) = &self.saved_comments[&idx];
-        currentlevel.insert(key.clone(), CommentValue::new(currentlevel[key], comment.clone(), *beginline, self._dict.clone()));
+    fn index(&self, key: &str) -> &Value {
+        self.get(key).unwrap_or_else(|| panic!("no key {:?} in table", key))
     }
 }
 
-pub struct TomlTz {
-    _raw_offset: String,
-    _sign: i32,
-    _hours: i32,
-    _minutes: i32,
+impl FromIterator<(String, Value)> for Table {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> Table {
+        let mut table = Table::new();
+        table.extend(iter);
+        table
+    }
 }
 
-impl TomlTz {
-    pub fn __init__(toml_offset: &str) -> () {
-        if toml_offset == "Z" {
-            self._raw_offset = "+00:00".to_string();
-        } else {
-            self._raw_offset = toml_offset.to_string();
+impl Extend<(String, Value)> for Table {
+    fn extend<I: IntoIterator<Item = (String, Value)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
         }
-        self._sign = if self._raw_offset.chars().next().unwrap() == '-' { -1 } else { 1 };
-        self._hours = self._raw_offset[1..3].parse::<i32>().unwrap();
-        self._minutes = self._raw_offset[4..6].parse::<i32>().unwrap();
     }
 }
 
-impl TomlTz {
-    pub fn __getinitargs__(&self) -> (i32,) { // Assuming _raw_offset is of type i32
-        (self._raw_offset,)
+/// An iterator over the entries of a `Table`.
+pub struct TableIter<'a>(Box<dyn Iterator<Item = (&'a String, &'a Value)> + 'a>);
+
+impl<'a> Iterator for TableIter<'a> {
+    type Item = (&'a String, &'a Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
     }
-}
 
-impl TomlTz {
-    pub fn __deepcopy__(&self, _memo: &mut std::collections::HashMap<*const Self, *const Self>) -> TomlTz {
-        TomlTz {
-            _raw_offset: self._raw_offset,
-            _sign: self._sign,
-            _hours: self._hours,
-            _minutes: self._minutes,
-        }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
     }
 }
 
-impl TomlTz {
-    pub fn tzname(&self, dt: &DateTime<FixedOffset>) -> String {
-        format!("UTC{}", self._raw_offset)
+/// An iterator over the entries of a `Table` with mutable values.
+pub struct TableIterMut<'a>(Box<dyn Iterator<Item = (&'a String, &'a mut Value)> + 'a>);
+
+impl<'a> Iterator for TableIterMut<'a> {
+    type Item = (&'a String, &'a mut Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
     }
 }
 
-use chrono::{Duration, TimeZone, Utc};
-use std::time::SystemTime;
+impl IntoIterator for Table {
+    type Item = (String, Value);
+    type IntoIter = std::vec::IntoIter<(String, Value)>;
 
-pub struct TomlTz {
-    _raw_offset: i32,
-    _sign: i32,
-    _hours: i32,
-    _minutes: i32,
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_entries().into_iter()
+    }
 }
 
-impl TomlTz {
-    pub fn utcoffset(&self, dt: SystemTime) -> Option<Duration> {
-        Some(Duration::hours(self._sign as i64 * self._hours as i64) + Duration::minutes(self._sign as i64 * self._minutes as i64))
+impl<'a> IntoIterator for &'a Table {
+    type Item = (&'a String, &'a Value);
+    type IntoIter = TableIter<'a>;
+
+    fn into_iter(self) -> TableIter<'a> {
+        self.iter()
     }
 }
 
-use chrono::Duration;
+impl<'a> IntoIterator for &'a mut Table {
+    type Item = (&'a String, &'a mut Value);
+    type IntoIter = TableIterMut<'a>;
 
-impl TomlTz {
-    pub fn dst(&self, dt: &chrono::DateTime<chrono::Utc>) -> Duration {
-        Duration::zero()
+    fn into_iter(self) -> TableIterMut<'a> {
+        self.iter_mut()
     }
 }
 
-pub fn _ispath(p: &dyn std::io::Read) -> bool {
-    use std::io::Read;
+/// A TOML date, time or datetime, as one of the four kinds TOML tells
+/// apart. Times keep nanosecond precision.
+///
+/// `FromStr` reads any form TOML accepts and records how the value was
+/// written in a `DatetimeFormat` and in the `TomlTz`, which `Display`
+/// writes back, so the text round-trips exactly. Values converted from
+/// chrono types are written in canonical form. Two datetimes written
+/// differently compare equal if their values are.
+#[derive(Debug, Clone, Copy)]
+pub enum Datetime {
+    OffsetDateTime(DateTime<TomlTz>, DatetimeFormat),
+    LocalDateTime(NaiveDateTime, DatetimeFormat),
+    LocalDate(NaiveDate),
+    LocalTime(NaiveTime, DatetimeFormat),
+}
 
-    let mut buffer = [0; 1];
-    if p.read(&mut buffer).is_ok() {
-        return true;
-    }
-    _detect_pathlib_path(p)
+/// How a datetime's time was written, where that does not change its
+/// value. The default is the canonical form: a `T` between date and time,
+/// and the fraction of a second without trailing zeros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DatetimeFormat {
+    /// The separator between date and time: `T`, `t` or a space.
+    pub separator: char,
+    /// The number of digits in the fraction of a second, at most 9, or
+    /// `None` for as many as the value needs.
+    pub fraction_digits: Option<u8>,
 }
 
-pub fn _detect_pathlib_path(p: &dyn std::io::Read) -> bool {
-    // Assuming we have a way to check if p is a PurePath
-    // This is a placeholder as Rust does not have a direct equivalent to Python's pathlib
-    false
+impl Default for DatetimeFormat {
+    fn default() -> Self {
+        DatetimeFormat {
+            separator: 'T',
+            fraction_digits: None,
+        }
+    }
 }
 
-pub fn _getpath(p: &dyn std::io::Read) -> Result<String, TomlDecodeError> {
-    /// Returns the file system path from the given input.
-    ///
-    /// Args:
-    ///     p: The input which can be a file descriptor or a path-like object.
-    ///
-    /// Returns:
-    ///     A string representation of the file system path.
-    ///
-    /// Raises:
-    ///     TomlDecodeError: If the input is not a valid path.
-    
-    if std::env::var("PYTHON_VERSION").unwrap_or_default() >= "3.6" {
-        use std::os::unix::ffi::OsStrExt;
-        return Ok(std::ffi::OsStr::from_bytes(p).to_string_lossy().into_owned());
+impl Datetime {
+    /// Returns the TOML name of this datetime's kind, e.g. `"local date"`.
+    pub fn type_str(&self) -> &'static str {
+        match self {
+            Datetime::OffsetDateTime(..) => "offset datetime",
+            Datetime::LocalDateTime(..) => "local datetime",
+            Datetime::LocalDate(_) => "local date",
+            Datetime::LocalTime(..) => "local time",
+        }
+    }
+
+    /// Returns the date, unless this is a local time.
+    pub fn date(&self) -> Option<NaiveDate> {
+        match self {
+            Datetime::OffsetDateTime(dt, _) => Some(dt.date_naive()),
+            Datetime::LocalDateTime(dt, _) => Some(dt.date()),
+            Datetime::LocalDate(d) => Some(*d),
+            Datetime::LocalTime(..) => None,
+        }
+    }
+
+    /// Returns the time of day, unless this is a local date.
+    pub fn time(&self) -> Option<NaiveTime> {
+        match self {
+            Datetime::OffsetDateTime(dt, _) => Some(dt.time()),
+            Datetime::LocalDateTime(dt, _) => Some(dt.time()),
+            Datetime::LocalDate(_) => None,
+            Datetime::LocalTime(t, _) => Some(*t),
+        }
+    }
+
+    /// Returns the offset from UTC if this is an offset datetime.
+    pub fn offset(&self) -> Option<TomlTz> {
+        match self {
+            Datetime::OffsetDateTime(dt, _) => Some(*dt.offset()),
+            _ => None,
+        }
     }
-    if _detect_pathlib_path(p)? {
-        return Ok(p.to_string());
+
+    /// Returns how the time was written; a local date has the default.
+    pub fn format(&self) -> DatetimeFormat {
+        match self {
+            Datetime::OffsetDateTime(_, format) | Datetime::LocalDateTime(_, format) | Datetime::LocalTime(_, format) => *format,
+            Datetime::LocalDate(_) => DatetimeFormat::default(),
+        }
     }
-    return Ok(p.to_string());
 }
 
-pub fn loads(s: &str, _dict: Option<Type> = None, decoder: Option<&TomlDecoder>) -> Result<HashMap<String, Value>, TomlDecodeError> {
-    /// Parses string as toml
-    ///
-    /// Args:
-    ///     s: String to be parsed
-    ///     _dict: (optional) Specifies the class of the returned toml dictionary
-    ///
-    /// Returns:
-    ///     Parsed toml file represented as a dictionary
-    ///
-    /// Raises:
-    ///     TypeError: When a non-string is passed
-    ///     TomlDecodeError: Error while decoding toml
-
-    let mut implicitgroups = Vec::new();
-    let mut decoder = decoder.unwrap_or_else(|| TomlDecoder::new(_dict));
-    let mut retval = decoder.get_empty_table();
-    let mut currentlevel = &mut retval;
-
-    if !s.is_string() {
-        return Err(TypeError("Expecting something like a string".to_string()));
-    }
-
-    let original = s.to_string();
-    let sl: Vec<char> = s.chars().collect();
-    let mut openarr = 0;
-    let mut openstring = false;
-    let mut openstrchar = "";
-    let mut multilinestr = false;
-    let mut arrayoftables = false;
-    let mut beginline = true;
-    let mut keygroup = false;
-    let mut dottedkey = false;
-    let mut keyname = 0;
-    let mut key = String::new();
-    let mut prev_key = String::new();
-    let mut line_no = 1;
-
-    for (i, item) in sl.iter().enumerate() {
-        if *item == '\r' && sl.get(i + 1).map_or(false, |&c| c == '\n') {
-            sl[i] = ' ';
-            continue;
-        }
-        if keyname != 0 {
-            key.push(*item);
-            if *item == '\n' {
-                return Err(TomlDecodeError::new("Key name found without value. Reached end of line.", &original, i));
-            }
-            if openstring {
-                if *item == openstrchar {
-                    let mut oddbackslash = false;
-                    let mut k = 1;
-                    while i >= k && sl[i - k] == '\\' {
-                        oddbackslash = !oddbackslash;
-                        k += 1;
-                    }
-                    if !oddbackslash {
-                        keyname = 2;
-                        openstring = false;
-                        openstrchar = "";
-                    }
-                }
-                continue;
-            } else if keyname == 1 {
-                if item.is_whitespace() {
-                    keyname = 2;
-                    continue;
-                } else if *item == '.' {
-                    dottedkey = true;
-                    continue;
-                } else if item.is_alphanumeric() || *item == '_' || *item == '-' {
-                    continue;
-                } else if dottedkey && sl[i - 1] == '.' && (*item == '"' || *item == '\'') {
-                    openstring = true;
-                    openstrchar = *item;
-                    continue;
-                }
-            } else if keyname == 2 {
-                if item.is_whitespace() {
-                    if dottedkey {
-                        if !sl.get(i + 1).map_or(false, |&c| !c.is_whitespace() && c != '.') {
-                            keyname = 1;
-                        }
-                    }
-                    continue;
-                }
-                if *item == '.' {
-                    dottedkey = true;
-                    if !sl.get(i + 1).map_or(false, |&c| !c.is_whitespace() && c != '.') {
-                        keyname = 1;
-                    }
-                    continue;
-                }
-            }
-            if *item == '=' {
-                keyname = 0;
-                prev_key = key.trim_end().to_string();
-                key.clear();
-                dottedkey = false;
-            } else {
-                return Err(TomlDecodeError::new(format!("Found invalid character in key name: '{}'. Try quoting the key name.", item), &original, i));
-            }
+impl PartialEq for Datetime {
+    fn eq(&self, other: &Datetime) -> bool {
+        match (self, other) {
+            (Datetime::OffsetDateTime(a, _), Datetime::OffsetDateTime(b, _)) => a == b,
+            (Datetime::LocalDateTime(a, _), Datetime::LocalDateTime(b, _)) => a == b,
+            (Datetime::LocalDate(a), Datetime::LocalDate(b)) => a == b,
+            (Datetime::LocalTime(a, _), Datetime::LocalTime(b, _)) => a == b,
+            _ => false,
         }
-        if *item == '\'' && openstrchar != '"' {
-            let mut k = 1;
-            while sl.get(i - k).map_or(false, |&c| c == '\'') {
-                k += 1;
-                if k == 3 {
-                    break;
-                }
-            }
-            if k == 3 {
-                multilinestr = !multilinestr;
-                openstring = multilinestr;
-            } else {
-                openstring = !openstring;
-            }
-            openstrchar = if openstring { "'" } else { "" };
-        }
-        if *item == '"' && openstrchar != '\'' {
-            let mut oddbackslash = false;
-            let mut k = 1;
-            let mut tripquote = false;
-            while sl.get(i - k).map_or(false, |&c| c == '"') {
-                k += 1;
-                if k == 3 {
-                    tripquote = true;
-                    break;
-                }
-            }
-            if k == 1 || (k == 3 && tripquote) {
-                while sl.get(i - k).map_or(false, |&c| c == '\\') {
-                    oddbackslash = !oddbackslash;
-                    k += 1;
-                }
-            }
-            if !oddbackslash {
-                if tripquote {
-                    multilinestr = !multilinestr;
-                    openstring = multilinestr;
-                } else {
-                    openstring = !openstring;
-                }
-            }
-            openstrchar = if openstring { "\"" } else { "" };
+    }
+}
+
+impl Eq for Datetime {}
+
+impl std::hash::Hash for Datetime {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Datetime::OffsetDateTime(dt, _) => dt.hash(state),
+            Datetime::LocalDateTime(dt, _) => dt.hash(state),
+            Datetime::LocalDate(d) => d.hash(state),
+            Datetime::LocalTime(t, _) => t.hash(state),
+        }
+    }
+}
+
+impl std::fmt::Display for Datetime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let format = self.format();
+        if let Some(d) = self.date() {
+            write!(f, "{}", d.format("%Y-%m-%d"))?;
         }
-        if *item == '#' && !openstring && !keygroup && !arrayoftables {
-            let mut j = i;
-            let mut comment = String::new();
-            while sl.get(j).map_or(false, |&c| c != '\n') {
-                comment.push(s[j]);
-                sl[j] = ' ';
-                j += 1;
+        if let Some(t) = self.time() {
+            if self.date().is_some() {
+                write!(f, "{}", format.separator)?;
             }
-            if openarr == 0 {
-                decoder.preserve_comment(line_no, &prev_key, &comment, beginline);
+            write!(f, "{}", t.format("%H:%M:%S"))?;
+            let nanos = format!("{:09}", t.nanosecond() % 1_000_000_000);
+            // Digits the value needs are never dropped, whatever the format says.
+            let needed = nanos.trim_end_matches('0').len();
+            let digits = format.fraction_digits.map_or(needed, |digits| usize::from(digits).clamp(needed, 9));
+            if digits > 0 {
+                write!(f, ".{}", &nanos[..digits])?;
             }
         }
-        if *item == '[' && !openstring && !keygroup && !arrayoftables {
-            if beginline {
-                if sl.get(i + 1).map_or(false, |&c| c == '[') {
-                    arrayoftables = true;
-                } else {
-                    keygroup = true;
-                }
-            } else {
-                openarr += 1;
-            }
+        match self.offset() {
+            Some(offset) => write!(f, "{}", offset),
+            None => Ok(()),
         }
-        if *item == ']' && !openstring {
-            if keygroup {
-                keygroup = false;
-            } else if arrayoftables {
-                if sl[i - 1] == ']' {
-                    arrayoftables = false;
-                }
-            } else {
-                openarr -= 1;
+    }
+}
+
+impl std::str::FromStr for Datetime {
+    type Err = TomlDecodeError;
+
+    fn from_str(s: &str) -> Result<Datetime, TomlDecodeError> {
+        _load_date(s).or_else(|| _load_local_time(s)).ok_or_else(|| {
+            let msg = format!("Invalid date or datetime: {}", s);
+            TomlDecodeError::new(ErrorKind::InvalidDatetime, msg, s.to_string(), 0..s.len())
+        })
+    }
+}
+
+/// The struct name under which datetimes pass through serde. A struct
+/// with this name and a single `DATETIME_FIELD` holding the TOML text is
+/// read back as a datetime rather than a table.
+const DATETIME_NAME: &str = "$__toml_private_Datetime";
+const DATETIME_FIELD: &str = "$__toml_private_datetime";
+
+/// Serializes as the private datetime struct, which `ValueSerializer`
+/// turns back into a `Value::Datetime`.
+impl serde::Serialize for Datetime {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct(DATETIME_NAME, 1)?;
+        s.serialize_field(DATETIME_FIELD, &self.to_string())?;
+        s.end()
+    }
+}
+
+/// Deserializes from the private datetime struct, or from a string of
+/// TOML datetime text.
+impl<'de> serde::Deserialize<'de> for Datetime {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Datetime, D::Error> {
+        struct DatetimeVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for DatetimeVisitor {
+            type Value = Datetime;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a TOML datetime")
             }
-        }
-        if *item == '\n' {
-            if openstring || multilinestr {
-                if !multilinestr {
-                    return Err(TomlDecodeError::new("Unbalanced quotes", &original, i));
-                }
-                if (sl[i - 1] == '\'' || sl[i - 1] == '"') && sl[i - 2] == sl[i - 1] {
-                    sl[i] = sl[i - 1];
-                    if sl[i - 3] == sl[i - 1] {
-                        sl[i - 3] = ' ';
-                    }
-                }
-            } else if openarr > 0 {
-                sl[i] = ' ';
-            } else {
-                beginline = true;
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Datetime, E> {
+                v.parse().map_err(E::custom)
             }
-            line_no += 1;
-        } else if beginline && *item != ' ' && *item != '\t' {
-            beginline = false;
-            if !keygroup && !arrayoftables {
-                if *item == '=' {
-                    return Err(TomlDecodeError::new("Found empty keyname.", &original, i));
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Datetime, A::Error> {
+                match map.next_key::<String>()? {
+                    Some(key) if key == DATETIME_FIELD => map.next_value::<String>()?.parse().map_err(serde::de::Error::custom),
+                    _ => Err(serde::de::Error::invalid_type(serde::de::Unexpected::Map, &self)),
                 }
-                keyname = 1;
-                key.push(*item);
             }
         }
+
+        deserializer.deserialize_struct(DATETIME_NAME, &[DATETIME_FIELD], DatetimeVisitor)
     }
-    if keyname != 0 {
-        return Err(TomlDecodeError::new("Key name found without value. Reached end of file.", &original, s.len()));
+}
+
+impl From<DateTime<TomlTz>> for Datetime {
+    fn from(v: DateTime<TomlTz>) -> Datetime {
+        Datetime::OffsetDateTime(v, DatetimeFormat::default())
     }
-    if openstring {
-        return Err(TomlDecodeError::new("Unterminated string found. Reached end of file.", &original, s.len()));
+}
+
+/// Converts a chrono datetime, dropping any seconds from its offset.
+impl From<DateTime<FixedOffset>> for Datetime {
+    fn from(v: DateTime<FixedOffset>) -> Datetime {
+        Datetime::OffsetDateTime(v.with_timezone(&TomlTz::from(*v.offset())), DatetimeFormat::default())
     }
-    let s = sl.iter().collect::<String>();
-    let s: Vec<&str> = s.split('\n').collect();
-    let mut multikey = None;
-    let mut multilinestr = String::new();
-    let mut multibackslash = false;
-    let mut pos = 0;
+}
 
-    for (idx, line) in s.iter().enumerate() {
-        if idx > 0 {
-            pos += s[idx - 1].len() + 1;
-        }
+impl From<NaiveDateTime> for Datetime {
+    fn from(v: NaiveDateTime) -> Datetime {
+        Datetime::LocalDateTime(v, DatetimeFormat::default())
+    }
+}
 
-        decoder.embed_comments(idx, currentlevel);
+impl From<NaiveDate> for Datetime {
+    fn from(v: NaiveDate) -> Datetime {
+        Datetime::LocalDate(v)
+    }
+}
 
-        if !multilinestr.is_empty() && !multibackslash && !multilinestr.contains('\n') {
-            line = line.trim();
-        }
-        if line.is_empty() && (multikey.is_none() || multibackslash) {
-            continue;
+impl From<NaiveTime> for Datetime {
+    fn from(v: NaiveTime) -> Datetime {
+        Datetime::LocalTime(v, DatetimeFormat::default())
+    }
+}
+
+/// Converts an offset datetime; any other kind is handed back as the error.
+impl TryFrom<Datetime> for DateTime<TomlTz> {
+    type Error = Datetime;
+
+    fn try_from(v: Datetime) -> Result<DateTime<TomlTz>, Datetime> {
+        match v {
+            Datetime::OffsetDateTime(dt, _) => Ok(dt),
+            v => Err(v),
         }
-        if let Some(multikey) = multikey {
-            if multibackslash {
-                multilinestr.push_str(line);
-            } else {
-                multilinestr.push_str(line);
-            }
-            multibackslash = false;
-            let closed = if multilinestr.starts_with('[') {
-                line.ends_with(']')
-            } else if multilinestr.len() > 2 {
-                line.ends_with(multilinestr.chars().next().unwrap()) && line.chars().rev().nth(1) == Some(multilinestr.chars().next().unwrap()) && line.chars().rev().nth(2) == Some(multilinestr.chars().next().unwrap())
-            } else {
-                false
-            };
-            if closed {
-                let value = decoder.load_value(&multilinestr)?;
-                currentlevel.insert(multikey, value);
-                multikey = None;
-                multilinestr.clear();
-            } else {
-                let mut k = multilinestr.len() - 1;
-                while k > 0 && multilinestr.chars().nth(k) == Some('\\') {
-                    multibackslash = !multibackslash;
-                    k -= 1;
-                }
-                if multibackslash {
-                    multilinestr.pop();
-                } else {
-                    multilinestr.push('\n');
-                }
-            }
-            continue;
+    }
+}
+
+/// Converts an offset datetime; any other kind is handed back as the error.
+impl TryFrom<Datetime> for DateTime<FixedOffset> {
+    type Error = Datetime;
+
+    fn try_from(v: Datetime) -> Result<DateTime<FixedOffset>, Datetime> {
+        match v {
+            Datetime::OffsetDateTime(dt, _) => Ok(dt.fixed_offset()),
+            v => Err(v),
         }
-        if line.starts_with('[') {
-            arrayoftables = false;
-            if line.len() == 1 {
-                return Err(TomlDecodeError::new("Opening key group bracket on line by itself.", &original, pos));
-            }
-            if line.chars().nth(1) == Some('[') {
-                arrayoftables = true;
-                line = &line[2..];
-                let splitstr = "]]";
-            } else {
-                line = &line[1..];
-                let splitstr = "]";
-            }
-            let mut i = 1;
-            let quotesplits = decoder._get_split_on_quotes(line);
-            let mut quoted = false;
-            for quotesplit in quotesplits {
-                if !quoted && quotesplit.contains(splitstr) {
-                    break;
-                }
-                i += quotesplit.matches(splitstr).count();
-                quoted = !quoted;
-            }
-            line = line.split(splitstr).take(i).collect::<Vec<&str>>().join(splitstr);
-            if line.len() < i + 1 || line.trim() != "" {
-                return Err(TomlDecodeError::new("Key group not on a line by itself.", &original, pos));
-            }
-            let groups: Vec<&str> = line.split('.').collect();
-            let mut i = 0;
-            while i < groups.len() {
-                let group = groups[i].trim();
-                if group.is_empty() {
-                    return Err(TomlDecodeError::new("Can't have a keygroup with an empty name", &original, pos));
-                }
-                if group.starts_with('"') || group.starts_with('\'') {
-                    let groupstr = group.to_string();
-                    let mut j = i + 1;
-                    while !(groupstr.chars().next().unwrap() == groupstr.chars().last().unwrap() && groupstr.len() > 1) {
-                        j += 1;
-                        if j > groups.len() + 2 {
-                            return Err(TomlDecodeError::new(format!("Invalid group name '{}'. Something went wrong.", groupstr), &original, pos));
-                        }
-                    }
-                    groups[i] = &groupstr[1..groupstr.len() - 1];
-                    groups.splice(i + 1..j, std::iter::empty());
-                } else {
-                    if !_groupname_re.is_match(group) {
-                        return Err(TomlDecodeError::new(format!("Invalid group name '{}'. Try quoting it.", group), &original, pos));
-                    }
-                }
-                i += 1;
-            }
-            currentlevel = &mut retval;
-            for i in 0..groups.len() {
-                let group = groups[i];
-                if group.is_empty() {
-                    return Err(TomlDecodeError::new("Can't have a keygroup with an empty name", &original, pos));
-                }
-                if let Some(existing) = currentlevel.get(group) {
-                    if i == groups.len() - 1 {
-                        if implicitgroups.contains(&group) {
-                            implicitgroups.retain(|g| g != group);
-                            if arrayoftables {
-                                return Err(TomlDecodeError::new("An implicitly defined table can't be an array", &original, pos));
-                            }
-                        } else if arrayoftables {
-                            currentlevel.insert(group.to_string(), vec![decoder.get_empty_table()]);
-                        } else {
-                            return Err(TomlDecodeError::new(format!("What? {} already exists? {}", group, currentlevel), &original, pos));
-                        }
-                    }
-                } else {
-                    currentlevel.insert(group.to_string(), decoder.get_empty_table());
-                    if i == groups.len() - 1 && arrayoftables {
-                        currentlevel.insert(group.to_string(), vec![decoder.get_empty_table()]);
-                    }
-                }
-                currentlevel = currentlevel.get_mut(group).unwrap();
-                if arrayoftables {
-                    currentlevel = currentlevel.last_mut().unwrap();
-                }
-            }
-        } else if line.starts_with("{") {
-            if !line.ends_with("}") {
-                return Err(TomlDecodeError::new("Line breaks are not allowed in inline objects", &original, pos));
-            }
-            decoder.load_inline_object(line, currentlevel, multikey, multibackslash)?;
-        } else if line.contains('=') {
-            let ret = decoder.load_line(line, currentlevel, multikey, multibackslash)?;
-            if let Some((mk, mls, mb)) = ret {
-                multikey = Some(mk);
-                multilinestr = mls;
-                multibackslash = mb;
-            }
+    }
+}
+
+/// Converts a local datetime; any other kind is handed back as the error.
+impl TryFrom<Datetime> for NaiveDateTime {
+    type Error = Datetime;
+
+    fn try_from(v: Datetime) -> Result<NaiveDateTime, Datetime> {
+        match v {
+            Datetime::LocalDateTime(dt, _) => Ok(dt),
+            v => Err(v),
         }
     }
-    Ok(retval)
 }
 
-impl TomlDecoder {
-    pub fn _get_split_on_quotes(&self, line: &str) -> Vec<String> {
-        let doublequotesplits: Vec<&str> = line.split('"').collect();
-        let mut quoted = false;
-        let mut quotesplits = Vec::new();
-        
-        if doublequotesplits.len() > 1 && doublequotesplits[0].contains('\'') {
-            let mut singlequotesplits: Vec<&str> = doublequotesplits[0].split('\'').collect();
-            let mut doublequotesplits = &doublequotesplits[1..];
-            while singlequotesplits.len() % 2 == 0 && !doublequotesplits.is_empty() {
-                singlequotesplits.last_mut().unwrap().to_string() += &format!("\"{}", doublequotesplits[0]);
-                doublequotesplits = &doublequotesplits[1..];
-                if singlequotesplits.last().unwrap().contains('\'') {
-                    singlequotesplits = singlequotesplits[..singlequotesplits.len()-1]
-                        .iter()
-                        .chain(singlequotesplits.last().unwrap().split('\''))
-                        .map(|s| s.trim())
-                        .collect();
-                }
-            }
-            quotesplits.extend(singlequotesplits);
-        }
-        
-        for doublequotesplit in doublequotesplits {
-            if quoted {
-                quotesplits.push(doublequotesplit.to_string());
-            } else {
-                quotesplits.extend(doublequotesplit.split('\'').map(|s| s.trim().to_string()));
-            }
-            quoted = !quoted;
+/// Converts a local date; any other kind is handed back as the error.
+impl TryFrom<Datetime> for NaiveDate {
+    type Error = Datetime;
+
+    fn try_from(v: Datetime) -> Result<NaiveDate, Datetime> {
+        match v {
+            Datetime::LocalDate(d) => Ok(d),
+            v => Err(v),
         }
-        quotesplits
     }
 }
 
-impl TomlDecoder {
-    pub fn get_empty_table(&self) -> std::collections::HashMap<Box<dyn std::any::Any>> {
-        (self._dict)()
+/// Converts a local time; any other kind is handed back as the error.
+impl TryFrom<Datetime> for NaiveTime {
+    type Error = Datetime;
+
+    fn try_from(v: Datetime) -> Result<NaiveTime, Datetime> {
+        match v {
+            Datetime::LocalTime(t, _) => Ok(t),
+            v => Err(v),
+        }
     }
 }
 
-pub fn _strictly_valid_num(n: &str) -> bool {
-    let n = n.trim();
-    if n.is_empty() {
-        return false;
+/// A decoded TOML value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Datetime(Datetime),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    /// Returns the TOML name of this value's type, e.g. `"integer"`.
+    pub fn type_str(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Datetime(dt) => dt.type_str(),
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
     }
-    if n.starts_with('_') {
-        return false;
+
+    /// Returns the string if this is a `Value::String`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
     }
-    if n.ends_with('_') {
-        return false;
+
+    /// Returns the integer if this is a `Value::Integer`.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
     }
-    if n.contains("._") || n.contains("_." ) {
-        return false;
+
+    /// Returns the float if this is a `Value::Float`.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
     }
-    if n.len() == 1 {
-        return true;
+
+    /// Returns the boolean if this is a `Value::Boolean`.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
     }
-    if n.starts_with('0') && !n.starts_with("0.") && !n.starts_with("0o") && !n.starts_with("0b") && !n.starts_with("0x") {
-        return false;
+
+    /// Returns the datetime if this is a `Value::Datetime`.
+    pub fn as_datetime(&self) -> Option<&Datetime> {
+        match self {
+            Value::Datetime(dt) => Some(dt),
+            _ => None,
+        }
     }
-    let mut n = n.to_string();
-    if n.starts_with('+') || n.starts_with('-') {
-        n.remove(0);
-        if n.len() > 1 && n.starts_with('0') && !n.starts_with("0.") {
-            return false;
+
+    /// Returns the datetime if this is an offset datetime.
+    pub fn as_offset_datetime(&self) -> Option<&DateTime<TomlTz>> {
+        match self {
+            Value::Datetime(Datetime::OffsetDateTime(dt, _)) => Some(dt),
+            _ => None,
         }
     }
-    if n.contains("__") {
-        return false;
+
+    /// Returns the datetime if this is a local datetime.
+    pub fn as_local_datetime(&self) -> Option<&NaiveDateTime> {
+        match self {
+            Value::Datetime(Datetime::LocalDateTime(dt, _)) => Some(dt),
+            _ => None,
+        }
     }
-    true
-}
 
-impl TomlDecoder {
-    pub fn _load_array_isstrarray(&self, a: &str) -> bool {
-        let a = a[1..a.len()-1].trim();
-        if !a.is_empty() && (a.chars().next().unwrap() == '"' || a.chars().next().unwrap() == '\'') {
-            return true;
+    /// Returns the date if this is a local date.
+    pub fn as_local_date(&self) -> Option<&NaiveDate> {
+        match self {
+            Value::Datetime(Datetime::LocalDate(d)) => Some(d),
+            _ => None,
         }
-        false
     }
-}
 
-impl TomlDecoder {
-    pub fn bounded_string(&self, s: &str) -> bool {
-        if s.is_empty() {
-            return true;
-        }
-        if s.chars().last().unwrap() != s.chars().next().unwrap() {
-            return false;
-        }
-        let mut i = -2;
-        let mut backslash = false;
-        while s.len() as isize + i > 0 {
-            if s.chars().nth((s.len() as isize + i) as usize).unwrap() == '\\' {
-                backslash = !backslash;
-                i -= 1;
-            } else {
-                break;
-            }
+    /// Returns the time if this is a local time.
+    pub fn as_local_time(&self) -> Option<&NaiveTime> {
+        match self {
+            Value::Datetime(Datetime::LocalTime(t, _)) => Some(t),
+            _ => None,
         }
-        !backslash
     }
-}
 
-impl TomlDecoder {
-    pub fn load_value(&self, v: &str, strictly_valid: bool) -> Result<Box<dyn std::any::Any>, String> {
-        if v.is_empty() {
-            return Err("Empty value is invalid".to_string());
-        }
-        if v == "true" {
-            return Ok(Box::new(true));
-        } else if v.to_lowercase() == "true" {
-            return Err("Only all lowercase booleans allowed".to_string());
-        } else if v == "false" {
-            return Ok(Box::new(false));
-        } else if v.to_lowercase() == "false" {
-            return Err("Only all lowercase booleans allowed".to_string());
-        } else if v.starts_with('"') || v.starts_with('\'') {
-            let quotechar = v.chars().next().unwrap();
-            let testv: Vec<&str> = v[1..].split(quotechar).collect();
-            let mut triplequote = false;
-            let mut triplequotecount = 0;
-            let mut closed = false;
-
-            let mut i = 0;
-            while i < testv.len() {
-                if testv[i].is_empty() {
-                    if triplequote {
-                        triplequotecount += 1;
-                    } else {
-                        closed = true;
-                    }
-                } else {
-                    let mut oddbackslash = false;
-                    let mut j = testv[i].len() as isize - 1;
-                    while j >= 0 {
-                        if testv[i].chars().nth(j as usize).unwrap() == '\\' {
-                            oddbackslash = !oddbackslash;
-                        }
-                        j -= 1;
-                    }
-                    if !oddbackslash {
-                        if closed {
-                            return Err("Found tokens after a closed string. Invalid TOML.".to_string());
-                        } else {
-                            if !triplequote || triplequotecount > 1 {
-                                closed = true;
-                            } else {
-                                triplequotecount = 0;
-                            }
-                        }
-                    }
-                }
-                i += 1;
-            }
+    /// Returns the elements if this is a `Value::Array`.
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
 
-            let mut v = v.to_string();
-            if quotechar == '"' {
-                let escapeseqs: Vec<&str> = v.split('\\').collect();
-                let mut backslash = false;
-                for i in escapeseqs.iter().skip(1) {
-                    if i.is_empty() {
-                        backslash = !backslash;
-                    } else {
-                        if !_escapes.contains(&i.chars().next().unwrap()) && (i.chars().next().unwrap() != 'u' && i.chars().next().unwrap() != 'U' && !backslash) {
-                            return Err("Reserved escape sequence used".to_string());
-                        }
-                        if backslash {
-                            backslash = false;
-                        }
-                    }
-                }
-                for prefix in ["\\u", "\\U"].iter() {
-                    if v.contains(*prefix) {
-                        let hexbytes: Vec<&str> = v.split(*prefix).collect();
-                        v = _load_unicode_escapes(hexbytes[0], hexbytes[1..].to_vec(), *prefix);
-                    }
-                }
-                v = _unescape(&v);
-            }
-            if v.len() > 1 && v.chars().nth(1).unwrap() == quotechar && (v.len() < 3 || v.chars().nth(1).unwrap() == v.chars().nth(2).unwrap()) {
-                v = v[2..v.len()-2].to_string();
-            }
-            return Ok(Box::new(v[1..v.len()-1].to_string()));
-        } else if v.starts_with('[') {
-            return Ok(Box::new(self.load_array(v)?));
-        } else if v.starts_with('{') {
-            let inline_object = self.get_empty_inline_table();
-            self.load_inline_object(v, inline_object);
-            return Ok(Box::new(inline_object));
-        } else if let Some(captures) = TIME_RE.captures(v) {
-            let h: i32 = captures[1].parse().unwrap();
-            let m: i32 = captures[2].parse().unwrap();
-            let s: i32 = captures[3].parse().unwrap();
-            let ms: i32 = captures[4].parse().unwrap_or(0);
-            let time = chrono::NaiveTime::from_hms_milli(h, m, s, ms);
-            return Ok(Box::new(time));
-        } else {
-            if let Some(parsed_date) = _load_date(v) {
-                return Ok(Box::new(parsed_date));
-            }
-            if !strictly_valid {
-                return Err("Weirdness with leading zeroes or underscores in your number.".to_string());
-            }
-            let mut itype = "int";
-            let mut neg = false;
-            let mut v = v.to_string();
-            if v.starts_with('-') {
-                neg = true;
-                v = v[1..].to_string();
-            } else if v.starts_with('+') {
-                v = v[1..].to_string();
-            }
-            v = v.replace('_', "");
-            let lowerv = v.to_lowercase();
-            if v.contains('.') || (!v.contains('x') && (v.contains('e') || v.contains('E'))) {
-                if v.contains('.') && v.split('.').nth(1).unwrap().is_empty() {
-                    return Err("This float is missing digits after the point".to_string());
-                }
-                if !v.chars().next().unwrap().is_digit(10) {
-                    return Err("This float doesn't have a leading digit".to_string());
-                }
-                let v = v.parse::<f64>().unwrap();
-                itype = "float";
-            } else if lowerv.len() == 3 && (lowerv == "inf" || lowerv == "nan") {
-                let v = v.parse::<f64>().unwrap();
-                itype = "float";
-            }
-            if itype == "int" {
-                let v = i64::from_str_radix(&v, 0).unwrap();
-                if neg {
-                    return Ok(Box::new(-v));
-                }
-                return Ok(Box::new(v));
-            }
+    /// Returns the elements mutably if this is a `Value::Array`.
+    pub fn as_array_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Returns the table if this is a `Value::Table`.
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Returns the table mutably if this is a `Value::Table`.
+    pub fn as_table_mut(&mut self) -> Option<&mut Table> {
+        match self {
+            Value::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Looks up `key` if this is a table.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_table().and_then(|t| t.get(key))
+    }
+
+    /// Looks up a dotted path such as `"a.b.c"`.
+    ///
+    /// Each segment indexes into a table by key, or into an array by a
+    /// numeric index (`"fruit.0.name"`). Keys that themselves contain a
+    /// `.` cannot be reached through this method.
+    pub fn get_path(&self, path: &str) -> Option<&Value> {
+        let mut current = self;
+        for segment in path.split('.') {
+            current = match current {
+                Value::Table(t) => t.get(segment)?,
+                Value::Array(a) => a.get(segment.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
         }
-        Err("Invalid value".to_string())
+        Some(current)
     }
 }
 
-use std::collections::HashMap;
+impl From<String> for Value {
+    fn from(v: String) -> Value {
+        Value::String(v)
+    }
+}
 
-/// A decoder for TOML files that preserves comments.
-#[derive(Debug, Clone)]
-pub struct TomlPreserveCommentDecoder {
-    /// A map to store saved comments associated with line numbers.
-    saved_comments: HashMap<usize, (String, String, bool)>,
+impl From<&str> for Value {
+    fn from(v: &str) -> Value {
+        Value::String(v.to_string())
+    }
 }
 
-impl TomlPreserveCommentDecoder {
-    /// Creates a new instance of `TomlPreserveCommentDecoder`.
-    pub fn new(_dict: Option<HashMap<String, String>>) -> TomlPreserveCommentDecoder {
-        let saved_comments = HashMap::new();
-        TomlPreserveCommentDecoder { saved_comments }
+impl From<i64> for Value {
+    fn from(v: i64) -> Value {
+        Value::Integer(v)
     }
+}
 
-    /// Preserves a comment associated with a specific line number and key.
-    pub fn preserve_comment(&mut self, line_no: usize, key: String, comment: String, beginline: bool) {
-        self.saved_comments.insert(line_no, (key, comment, beginline));
+impl From<f64> for Value {
+    fn from(v: f64) -> Value {
+        Value::Float(v)
     }
+}
 
-    /// Embeds comments into the current level based on the saved comments.
-    pub fn embed_comments(&self, idx: usize, currentlevel: &mut HashMap<String, CommentValue>) {
-        if !self.saved_comments.contains_key(&idx) {
-            return;
-        }
+impl From<bool> for Value {
+    fn from(v: bool) -> Value {
+        Value::Boolean(v)
+    }
+}
 
-        let (key, comment, beginline) = &self.saved_comments[&idx];
-        currentlevel.insert(key.clone(), CommentValue::new(currentlevel[key], comment.clone(), *beginline, _dict.clone()));
+impl From<Datetime> for Value {
+    fn from(v: Datetime) -> Value {
+        Value::Datetime(v)
     }
 }
 
-use std::collections::HashMap;
+impl From<Vec<Value>> for Value {
+    fn from(v: Vec<Value>) -> Value {
+        Value::Array(v)
+    }
+}
 
-/// A struct representing a value with an associated comment and a dictionary.
-#[derive(Debug, Clone)]
-pub struct CommentValue {
-    val: Vec<String>, // Adjust type as necessary
-    comment: String,
-    _dict: HashMap<String, String>, // Adjust type as necessary
+impl From<Table> for Value {
+    fn from(v: Table) -> Value {
+        Value::Table(v)
+    }
 }
 
-impl CommentValue {
-    /// Creates a new `CommentValue` instance.
-    pub fn new(val: Vec<String>, comment: String, beginline: bool, _dict: HashMap<String, String>) -> CommentValue {
-        let separator = if beginline { "\n".to_string() } else { " ".to_string() };
-        let comment = separator + &comment;
-        CommentValue { val, comment, _dict }
+/// A decoded TOML value that borrows from the document it was decoded
+/// from, as returned by `loads_borrowed`.
+///
+/// Keys and strings are `Cow::Borrowed` slices of the document, except
+/// those that had escape sequences or line endings to normalize.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BorrowedValue<'a> {
+    String(Cow<'a, str>),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Datetime(Datetime),
+    Array(Vec<BorrowedValue<'a>>),
+    Table(BorrowedTable<'a>),
+}
+
+/// A table of `BorrowedValue`s.
+pub type BorrowedTable<'a> = HashMap<Cow<'a, str>, BorrowedValue<'a>>;
+
+impl<'a> BorrowedValue<'a> {
+    /// Returns the TOML name of this value's type, e.g. `"integer"`.
+    pub fn type_str(&self) -> &'static str {
+        match self {
+            BorrowedValue::String(_) => "string",
+            BorrowedValue::Integer(_) => "integer",
+            BorrowedValue::Float(_) => "float",
+            BorrowedValue::Boolean(_) => "boolean",
+            BorrowedValue::Datetime(dt) => dt.type_str(),
+            BorrowedValue::Array(_) => "array",
+            BorrowedValue::Table(_) => "table",
+        }
     }
 
-    /// Gets the value at the specified index.
-    pub fn get_item(&self, key: usize) -> &String { // Adjust return type as necessary
-        &self.val[key]
+    /// Returns the string if this is a `BorrowedValue::String`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            BorrowedValue::String(s) => Some(s),
+            _ => None,
+        }
     }
 
-    /// Sets the value at the specified key.
-    pub fn set_item(&mut self, key: usize, value: String) { // Adjust parameter type as necessary
-        self.val[key] = value;
+    /// Returns the integer if this is a `BorrowedValue::Integer`.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            BorrowedValue::Integer(i) => Some(*i),
+            _ => None,
+        }
     }
 
-    /// Dumps the value and comment as a formatted string.
-    pub fn dump(&self, dump_value_func: &dyn Fn(&Vec<String>) -> String) -> String {
-        let retstr = dump_value_func(&self.val);
-        if self._dict.contains_key(&self.val[0]) { // Adjust condition as necessary
-            format!("{}\n{}", self.comment, retstr)
-        } else {
-            format!("{}{}", retstr, self.comment)
+    /// Returns the float if this is a `BorrowedValue::Float`.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            BorrowedValue::Float(f) => Some(*f),
+            _ => None,
         }
     }
-}
 
-/// A struct representing a TOML decoder that preserves comments.
-pub struct TomlPreserveCommentDecoder {
-    saved_comments: HashMap<usize, (String, String, bool)>, // Adjust types as necessary
-    _dict: HashMap<String, String>, // Adjust type as necessary
-}
+    /// Returns the boolean if this is a `BorrowedValue::Boolean`.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            BorrowedValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
 
-impl TomlPreserveCommentDecoder {
-    /// Embeds comments into the current level based on the index.
-    pub fn embed_comments(&self, idx: usize, currentlevel: &mut HashMap<String, CommentValue>) {
-        if !self.saved_comments.contains_key(&idx) {
-            return;
+    /// Returns the datetime if this is a `BorrowedValue::Datetime`.
+    pub fn as_datetime(&self) -> Option<&Datetime> {
+        match self {
+            BorrowedValue::Datetime(dt) => Some(dt),
+            _ => None,
         }
+    }
 
-        let (key, comment, beginline) = &self.saved_comments[&idx];
-        currentlevel.insert(key.clone(), CommentValue::new(currentlevel[key].val.clone(), comment.clone(), *beginline, self._dict.clone()));
+    /// Returns the elements if this is a `BorrowedValue::Array`.
+    pub fn as_array(&self) -> Option<&Vec<BorrowedValue<'a>>> {
+        match self {
+            BorrowedValue::Array(a) => Some(a),
+            _ => None,
+        }
     }
-}
 
-pub fn _detect_pathlib_path(p: &dyn std::io::Read) -> bool {
-    // Check if the Python version is 3.4 or higher
-    if std::env::var("PYTHON_VERSION").unwrap_or_default() >= "3.4" {
-        // Assuming we have a way to check if p is a PurePath
-        // This is a placeholder as Rust does not have a direct equivalent to Python's pathlib
-        // You would need to implement the logic to check for PurePath here
-        return false; // Replace with actual check
+    /// Returns the table if this is a `BorrowedValue::Table`.
+    pub fn as_table(&self) -> Option<&BorrowedTable<'a>> {
+        match self {
+            BorrowedValue::Table(t) => Some(t),
+            _ => None,
+        }
     }
-    false
-}
 
-#[derive(Debug, Clone)]
-pub struct TomlDecodeError {
-    msg: String,
-    doc: String,
-    pos: usize,
-    lineno: usize,
-    colno: usize,
+    /// Looks up `key` if this is a table.
+    pub fn get(&self, key: &str) -> Option<&BorrowedValue<'a>> {
+        self.as_table().and_then(|t| t.get(key))
+    }
+
+    /// Copies the borrowed strings and keys into a `Value`.
+    pub fn into_owned(self) -> Value {
+        match self {
+            BorrowedValue::String(s) => Value::String(s.into_owned()),
+            BorrowedValue::Integer(i) => Value::Integer(i),
+            BorrowedValue::Float(f) => Value::Float(f),
+            BorrowedValue::Boolean(b) => Value::Boolean(b),
+            BorrowedValue::Datetime(dt) => Value::Datetime(dt),
+            BorrowedValue::Array(a) => Value::Array(a.into_iter().map(BorrowedValue::into_owned).collect()),
+            BorrowedValue::Table(t) => {
+                let mut table = Table::new();
+                for (k, v) in t {
+                    table.insert(k.into_owned(), v.into_owned());
+                }
+                Value::Table(table)
+            }
+        }
+    }
 }
 
-impl TomlDecodeError {
-    /// Creates a new TomlDecodeError.
-    ///
-    /// # Arguments
-    ///
-    /// * `msg` - A message describing the error.
-    /// * `doc` - The document where the error occurred.
-    /// * `pos` - The position in the document where the error occurred.
-    pub fn new(msg: String, doc: String, pos: usize) -> TomlDecodeError {
-        let lineno = doc[..pos].matches('\n').count() + 1;
-        let colno = pos - doc.rfind('\n', pos).unwrap_or(0);
-        let emsg = format!("{} (line {} column {} char {})", msg, lineno, colno, pos);
-        TomlDecodeError {
-            msg,
-            doc,
-            pos,
-            lineno,
-            colno,
+impl From<Value> for BorrowedValue<'_> {
+    fn from(v: Value) -> Self {
+        match v {
+            Value::String(s) => BorrowedValue::String(Cow::Owned(s)),
+            Value::Integer(i) => BorrowedValue::Integer(i),
+            Value::Float(f) => BorrowedValue::Float(f),
+            Value::Boolean(b) => BorrowedValue::Boolean(b),
+            Value::Datetime(dt) => BorrowedValue::Datetime(dt),
+            Value::Array(a) => BorrowedValue::Array(a.into_iter().map(BorrowedValue::from).collect()),
+            Value::Table(t) => BorrowedValue::Table(t.into_iter().map(|(k, v)| (Cow::Owned(k), BorrowedValue::from(v))).collect()),
         }
     }
 }
 
-pub fn loads(s: &str, _dict: Option<Type>, decoder: Option<&TomlDecoder>) -> Result<HashMap<String, Value>, TomlDecodeError> {
-    /// Parses string as toml
-    ///
-    /// # Arguments
-    ///
-    /// * `s` - String to be parsed.
-    /// * `_dict` - (optional) Specifies the class of the returned toml dictionary.
-    ///
-    /// # Returns
-    ///
-    /// Parsed toml file represented as a dictionary.
+/// Where a comment sits relative to the key, table header or array
+/// element it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommentPlacement {
+    /// On a line of its own above it.
+    Leading,
+    /// At the end of its line.
+    Trailing,
+    /// After the last element of an array, or at the end of the document.
+    Closing,
+}
+
+/// A struct representing a value with its associated comments.
+///
+/// Superseded by `Document`, which keeps comments, blank lines, quoting
+/// and key order without a separate comment map to carry around.
+#[deprecated(note = "use `Document`, which keeps comments along with the rest of the formatting")]
+#[derive(Debug, Clone)]
+pub struct CommentValue {
+    val: Value,
+    leading: Vec<String>,
+    trailing: Option<String>,
+    closing: Vec<String>,
+}
+
+#[allow(deprecated)]
+impl CommentValue {
+    /// Creates a new `CommentValue` instance. The comment goes on its own
+    /// line above the value if `beginline` is set, and after it otherwise.
+    pub fn new(val: Value, comment: String, beginline: bool) -> CommentValue {
+        let mut cv = CommentValue {
+            val,
+            leading: Vec::new(),
+            trailing: None,
+            closing: Vec::new(),
+        };
+        let placement = if beginline { CommentPlacement::Leading } else { CommentPlacement::Trailing };
+        cv.add_comment(&comment, placement);
+        cv
+    }
+
+    /// Returns the value the comments belong to.
+    pub fn value(&self) -> &Value {
+        &self.val
+    }
+
+    /// Returns the comments on the lines above the value.
+    pub fn leading(&self) -> &[String] {
+        &self.leading
+    }
+
+    /// Returns the comment at the end of the value's line.
+    pub fn trailing(&self) -> Option<&str> {
+        self.trailing.as_deref()
+    }
+
+    /// Returns the comments after the last element of an array, or at the
+    /// end of the document.
+    pub fn closing(&self) -> &[String] {
+        &self.closing
+    }
+
+    /// Adds a comment, prefixing it with `#` if it lacks one. A trailing
+    /// comment replaces the one already there.
+    pub fn add_comment(&mut self, comment: &str, placement: CommentPlacement) {
+        let comment = if comment.starts_with('#') { comment.to_string() } else { format!("# {}", comment) };
+        match placement {
+            CommentPlacement::Leading => self.leading.push(comment),
+            CommentPlacement::Trailing => self.trailing = Some(comment),
+            CommentPlacement::Closing => self.closing.push(comment),
+        }
+    }
+
+    /// Gets the value stored under `key` when the wrapped value is a table.
+    pub fn get_item(&self, key: &str) -> Option<&Value> {
+        self.val.get(key)
+    }
+
+    /// Sets the value stored under `key` when the wrapped value is a table.
+    pub fn set_item(&mut self, key: String, value: Value) {
+        if let Value::Table(t) = &mut self.val {
+            t.insert(key, value);
+        }
+    }
+
+    /// Dumps the value with its leading comments above it and its trailing
+    /// comment after it.
+    pub fn dump(&self, dump_value_func: &dyn Fn(&Value) -> String) -> String {
+        self.surround(&dump_value_func(&self.val))
+    }
+
+    /// Puts the leading comments above `line` and the trailing one after it.
+    fn surround(&self, line: &str) -> String {
+        let mut retstr = String::new();
+        for comment in &self.leading {
+            retstr.push_str(comment);
+            retstr.push('\n');
+        }
+        retstr.push_str(line);
+        if let Some(comment) = &self.trailing {
+            retstr.push(' ');
+            retstr.push_str(comment);
+        }
+        retstr
+    }
+}
+
+/// Decodes the TOML file at `f` into a table, as `loads` does for a
+/// string. `_dict` and `decoder` are passed on to `loads`. The file is
+/// read as bytes, so a byte order mark is skipped and invalid UTF-8 is a
+/// decode error with its line and column.
+///
+/// Errors are `DeError::File`, naming the file and wrapping either the
+/// `DeError::Io` from reading it or the `DeError::Decode` from decoding it.
+pub fn load(f: impl AsRef<Path>, _dict: Option<fn() -> Table>, decoder: Option<&TomlDecoder>) -> Result<Table, DeError> {
+    let path = f.as_ref();
+    let parse = || -> Result<Table, DeError> {
+        let bytes = std::fs::read(path)?;
+        Ok(loads(_decode_bytes(&bytes)?, _dict, decoder)?)
+    };
+    parse().map_err(|e| e.in_file(path))
+}
+
+/// Checks raw TOML input and returns it as text, without its UTF-8 byte
+/// order mark if it has one.
+///
+/// UTF-16 input, invalid UTF-8, and control characters other than tab
+/// and the newlines `\n` and `\r\n` are errors, with the line and column
+/// of the offending byte. Byte offsets count from after the byte order
+/// mark.
+fn _decode_bytes(bytes: &[u8]) -> Result<&str, TomlDecodeError> {
+    _check_utf16(bytes)?;
+    _decode_utf8(bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes))
+}
+
+/// Rejects input that looks like UTF-16, from its byte order mark or from
+/// the zero bytes between ASCII characters.
+fn _check_utf16(bytes: &[u8]) -> Result<(), TomlDecodeError> {
+    let utf16 = match bytes {
+        [0xff, 0xfe, ..] => Some("little"),
+        [0xfe, 0xff, ..] => Some("big"),
+        [a, 0, b, 0, ..] if *a != 0 && *b != 0 => Some("little"),
+        [0, a, 0, b, ..] if *a != 0 && *b != 0 => Some("big"),
+        _ => None,
+    };
+    if let Some(endian) = utf16 {
+        let msg = format!("Found UTF-16 ({}-endian) input. TOML documents must be UTF-8", endian);
+        return Err(TomlDecodeError::new(ErrorKind::InvalidCharacter, msg, String::new(), 0..0));
+    }
+    Ok(())
+}
+
+/// Checks that `bytes` are UTF-8 without stray control characters, as
+/// for `_decode_bytes`.
+fn _decode_utf8(bytes: &[u8]) -> Result<&str, TomlDecodeError> {
+    let s = std::str::from_utf8(bytes).map_err(|e| {
+        let pos = e.valid_up_to();
+        let msg = match e.error_len() {
+            Some(len) => format!("Invalid UTF-8 sequence {:02x?}", &bytes[pos..pos + len]),
+            None => "Incomplete UTF-8 sequence at the end of the input".to_string(),
+        };
+        let doc = String::from_utf8_lossy(bytes).into_owned();
+        TomlDecodeError::new(ErrorKind::InvalidCharacter, msg, doc, pos..pos + 1)
+    })?;
+    let bytes = s.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        let msg = match b {
+            b'\r' if bytes.get(i + 1) != Some(&b'\n') => "Carriage return is not followed by a newline".to_string(),
+            b'\t' | b'\n' | b'\r' | b' '..=0x7e | 0x80.. => continue,
+            _ => format!("Control character '{}' is not allowed", char::from(b).escape_debug()),
+        };
+        return Err(TomlDecodeError::new(ErrorKind::InvalidCharacter, msg, s.to_string(), i..i + 1));
+    }
+    Ok(s)
+}
+
+/// How `LayeredLoader` treats a value that a later layer sets again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+    /// The later layer's value wins.
+    #[default]
+    Replace,
+    /// Arrays are concatenated, earlier layer first. Other values are
+    /// replaced.
+    Append,
+    /// Setting the value again is an error.
+    Error,
+}
+
+/// Error returned by `LayeredLoader::load`.
+#[derive(Debug)]
+pub enum MergeError {
+    /// A layer could not be read.
+    Io { path: PathBuf, error: std::io::Error },
+    /// A layer is not valid TOML.
+    Decode { path: PathBuf, error: Box<TomlDecodeError> },
+    /// A layer set a value already set by an earlier layer, under
+    /// `MergePolicy::Error`.
+    Conflict { path: PathBuf, key: Vec<String> },
+}
+
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            MergeError::Decode { path, error } => write!(f, "{}: {}", path.display(), error),
+            MergeError::Conflict { path, key } => {
+                let key: Vec<&str> = key.iter().map(String::as_str).collect();
+                write!(f, "{}: {} is already set by an earlier file", path.display(), _display_path(&key))
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MergeError::Io { error, .. } => Some(error),
+            MergeError::Decode { error, .. } => Some(&**error),
+            MergeError::Conflict { .. } => None,
+        }
+    }
+}
+
+/// A value that one layer replaced or appended to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    /// The path of the value, outermost key first.
+    pub key: Vec<String>,
+    /// The index in `MergeReport::layers` of the layer that set the value
+    /// before.
+    pub layer: usize,
+    /// The value before this layer changed it.
+    pub previous: Value,
+    /// Whether this layer's array was appended rather than replacing it.
+    pub appended: bool,
+}
+
+/// The values that one file changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerReport {
+    pub path: PathBuf,
+    pub overridden: Vec<Override>,
+}
+
+/// What each layer of a `LayeredLoader` overrode, in load order, and
+/// where each merged value came from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergeReport {
+    pub layers: Vec<LayerReport>,
+    /// The source of each key and value of the merged table.
+    pub sources: SourceMap,
+}
+
+enum Layer {
+    File(PathBuf),
+    Dir(PathBuf),
+}
+
+/// Loads several TOML files and merges them into one table.
+///
+/// Layers are merged in the order they were added, each one over the
+/// result of the ones before it. Tables are merged key by key, so a later
+/// file only changes the keys it sets. Arrays and other values that are
+/// set again are handled by the `arrays` and `scalars` policies, which both
+/// default to `MergePolicy::Replace`. A value that changes type is handled
+/// by the `scalars` policy.
+///
+/// ```ignore
+/// let (config, report) = LayeredLoader::new(None)
+///     .file("/etc/app/config.toml")
+///     .dir("/etc/app/conf.d")
+///     .arrays(MergePolicy::Append)
+///     .load()?;
+/// ```
+pub struct LayeredLoader {
+    decoder: TomlDecoder,
+    layers: Vec<Layer>,
+    arrays: MergePolicy,
+    scalars: MergePolicy,
+}
+
+impl LayeredLoader {
+    pub fn new(_dict: Option<fn() -> Table>) -> LayeredLoader {
+        LayeredLoader {
+            decoder: TomlDecoder::new(_dict),
+            layers: Vec::new(),
+            arrays: MergePolicy::Replace,
+            scalars: MergePolicy::Replace,
+        }
+    }
+
+    /// Adds a file. It is an error for the file not to exist.
+    pub fn file(mut self, path: impl Into<PathBuf>) -> LayeredLoader {
+        self.layers.push(Layer::File(path.into()));
+        self
+    }
+
+    /// Adds every `*.toml` file in a directory, such as `conf.d`, sorted
+    /// by file name. A directory that does not exist adds nothing.
+    pub fn dir(mut self, path: impl Into<PathBuf>) -> LayeredLoader {
+        self.layers.push(Layer::Dir(path.into()));
+        self
+    }
+
+    /// Sets how arrays, including arrays of tables, are merged.
+    pub fn arrays(mut self, policy: MergePolicy) -> LayeredLoader {
+        self.arrays = policy;
+        self
+    }
+
+    /// Sets how values other than tables and arrays are merged.
+    pub fn scalars(mut self, policy: MergePolicy) -> LayeredLoader {
+        self.scalars = policy;
+        self
+    }
+
+    /// Loads and merges every layer.
     ///
-    /// # Errors
+    /// Returns:
+    ///     The merged table and a report of the values each file overrode
     ///
-    /// * `TypeError` - When a non-string is passed.
-    /// * `TomlDecodeError` - Error while decoding toml.
-    
-    let mut implicitgroups = Vec::new();
-    let mut decoder = decoder.unwrap_or_else(|| TomlDecoder::new(_dict));
-    let mut retval = decoder.get_empty_table();
-    let mut currentlevel = &mut retval;
-
-    if !s.is_string() {
-        return Err(TomlDecodeError::new("Expecting something like a string".to_string(), s.to_string(), 0));
-    }
-
-    let original = s.to_string();
-    let sl: Vec<char> = s.chars().collect();
-    let mut openarr = 0;
-    let mut openstring = false;
-    let mut openstrchar = "";
-    let mut multilinestr = false;
-    let mut arrayoftables = false;
-    let mut beginline = true;
-    let mut keygroup = false;
-    let mut dottedkey = false;
-    let mut keyname = 0;
-    let mut key = String::new();
-    let mut prev_key = String::new();
-    let mut line_no = 1;
-
-    for (i, item) in sl.iter().enumerate() {
-        if *item == '\r' && sl.get(i + 1).map_or(false, |&c| c == '\n') {
-            sl[i] = ' ';
-            continue;
-        }
-        if keyname != 0 {
-            key.push(*item);
-            if *item == '\n' {
-                return Err(TomlDecodeError::new("Key name found without value. Reached end of line.".to_string(), &original, i));
-            }
-            if openstring {
-                if *item == openstrchar {
-                    let mut oddbackslash = false;
-                    let mut k = 1;
-                    while i >= k && sl[i - k] == '\\' {
-                        oddbackslash = !oddbackslash;
-                        k += 1;
-                    }
-                    if !oddbackslash {
-                        keyname = 2;
-                        openstring = false;
-                        openstrchar = "";
-                    }
-                }
-                continue;
-            } else if keyname == 1 {
-                if item.is_whitespace() {
-                    keyname = 2;
-                    continue;
-                } else if *item == '.' {
-                    dottedkey = true;
-                    continue;
-                } else if item.is_alphanumeric() || *item == '_' || *item == '-' {
-                    continue;
-                } else if dottedkey && sl[i - 1] == '.' && (*item == '"' || *item == '\'') {
-                    openstring = true;
-                    openstrchar = *item;
-                    continue;
-                }
-            } else if keyname == 2 {
-                if item.is_whitespace() {
-                    if dottedkey {
-                        if !sl.get(i + 1).map_or(false, |&c| !c.is_whitespace() && c != '.') {
-                            keyname = 1;
+    /// Raises:
+    ///     MergeError: When a file cannot be read or decoded, or a merge
+    ///     policy is `MergePolicy::Error` and a file sets a value again
+    pub fn load(&self) -> Result<(Table, MergeReport), MergeError> {
+        let mut merged = self.decoder.get_empty_table();
+        let mut report = MergeReport::default();
+        let mut origins = HashMap::new();
+        for path in self.files()? {
+            let content = std::fs::read(&path).map_err(|error| MergeError::Io { path: path.clone(), error })?;
+            let (layer, sources) = _decode_bytes(&content)
+                .and_then(|content| _loads_with_sources(content, Some(&path), None, Some(&self.decoder)))
+                .map_err(|error| MergeError::Decode { path: path.clone(), error: Box::new(error) })?;
+            let mut merge = Merge {
+                loader: self,
+                index: report.layers.len(),
+                origins: &mut origins,
+                layer_sources: &sources,
+                sources: &mut report.sources,
+                overridden: Vec::new(),
+                key: Vec::new(),
+            };
+            merge
+                .table(&mut merged, layer)
+                .map_err(|key| MergeError::Conflict { path: path.clone(), key })?;
+            let overridden = merge.overridden;
+            report.layers.push(LayerReport { path, overridden });
+        }
+        Ok((merged, report))
+    }
+
+    /// Expands the layers into the list of files to load.
+    fn files(&self) -> Result<Vec<PathBuf>, MergeError> {
+        let mut files = Vec::new();
+        for layer in &self.layers {
+            match layer {
+                Layer::File(path) => files.push(path.clone()),
+                Layer::Dir(path) => {
+                    let io_error = |error| MergeError::Io { path: path.clone(), error };
+                    let entries = match std::fs::read_dir(path) {
+                        Ok(entries) => entries,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(io_error(e)),
+                    };
+                    let mut found = Vec::new();
+                    for entry in entries {
+                        let entry = entry.map_err(io_error)?;
+                        let file = entry.path();
+                        if file.extension().is_some_and(|ext| ext == "toml") && file.is_file() {
+                            found.push(file);
                         }
                     }
-                    continue;
-                }
-                if *item == '.' {
-                    dottedkey = true;
-                    if !sl.get(i + 1).map_or(false, |&c| !c.is_whitespace() && c != '.') {
-                        keyname = 1;
-                    }
-                    continue;
+                    found.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+                    files.extend(found);
                 }
             }
-            if *item == '=' {
-                keyname = 0;
-                prev_key = key.trim_end().to_string();
-                key.clear();
-                dottedkey = false;
-            } else {
-                return Err(TomlDecodeError::new(format!("Found invalid character in key name: '{}'. Try quoting the key name.", item), &original, i));
-            }
         }
-        if *item == '\'' && openstrchar != '"' {
-            let mut k = 1;
-            while sl.get(i - k).map_or(false, |&c| c == '\'') {
-                k += 1;
-                if k == 3 {
-                    break;
-                }
-            }
-            if k == 3 {
-                multilinestr = !multilinestr;
-                openstring = multilinestr;
-            } else {
-                openstring = !openstring;
-            }
-            openstrchar = if openstring { "'" } else { "" };
-        }
-        if *item == '"' && openstrchar != '\'' {
-            let mut oddbackslash = false;
-            let mut k = 1;
-            let mut tripquote = false;
-            while sl.get(i - k).map_or(false, |&c| c == '"') {
-                k += 1;
-                if k == 3 {
-                    tripquote = true;
-                    break;
-                }
-            }
-            if k == 1 || (k == 3 && tripquote) {
-                while sl.get(i - k).map_or(false, |&c| c == '\\') {
-                    oddbackslash = !oddbackslash;
-                    k += 1;
+        Ok(files)
+    }
+}
+
+/// The state of merging one layer.
+struct Merge<'a> {
+    loader: &'a LayeredLoader,
+    index: usize,
+    /// The layer that last set each value, by path.
+    origins: &'a mut HashMap<Vec<String>, usize>,
+    layer_sources: &'a SourceMap,
+    /// The sources of the merged table.
+    sources: &'a mut SourceMap,
+    overridden: Vec<Override>,
+    key: Vec<String>,
+}
+
+impl Merge<'_> {
+    /// Merges `layer` into `base`. Fails with the path of a conflicting
+    /// value.
+    fn table(&mut self, base: &mut Table, layer: Table) -> Result<(), Vec<String>> {
+        for (k, v) in layer {
+            self.key.push(k.clone());
+            match (base.get_mut(&k), v) {
+                (None, v) => {
+                    self.record(&v);
+                    base.insert(k, v);
                 }
-            }
-            if !oddbackslash {
-                if tripquote {
-                    multilinestr = !multilinestr;
-                    openstring = multilinestr;
-                } else {
-                    openstring = !openstring;
+                (Some(Value::Table(old)), Value::Table(new)) => self.table(old, new)?,
+                (Some(old), v) if *old == v => {}
+                (Some(old), v) => {
+                    let policy = match (&*old, &v) {
+                        (Value::Array(_), Value::Array(_)) => self.loader.arrays,
+                        _ => self.loader.scalars,
+                    };
+                    let layer = self.origins.get(&self.key).copied().unwrap_or_default();
+                    let previous = old.clone();
+                    let appended = match (policy, old, v) {
+                        (MergePolicy::Error, _, _) => return Err(self.key.clone()),
+                        (MergePolicy::Append, Value::Array(old), Value::Array(new)) => {
+                            let mut element = self.key.clone();
+                            for i in 0..new.len() {
+                                element.push(i.to_string());
+                                let at = [&self.key[..], &[(old.len() + i).to_string()]].concat();
+                                self.sources.graft(&at, self.layer_sources, &element);
+                                element.pop();
+                            }
+                            old.extend(new);
+                            self.origins.insert(self.key.clone(), self.index);
+                            true
+                        }
+                        (_, old, v) => {
+                            self.record(&v);
+                            *old = v;
+                            false
+                        }
+                    };
+                    self.overridden.push(Override {
+                        key: self.key.clone(),
+                        layer,
+                        previous,
+                        appended,
+                    });
                 }
             }
-            openstrchar = if openstring { "\"" } else { "" };
+            self.key.pop();
         }
-        if *item == '#' && !openstring && !keygroup && !arrayoftables {
-            let mut j = i;
-            let mut comment = String::new();
-            while sl.get(j).map_or(false, |&c| c != '\n') {
-                comment.push(s[j]);
-                sl[j] = ' ';
-                j += 1;
-            }
-            if openarr == 0 {
-                decoder.preserve_comment(line_no, &prev_key, &comment, beginline);
+        Ok(())
+    }
+
+    /// Marks this layer as the origin of `value`, found at the current
+    /// key, and of every value in it.
+    fn record(&mut self, value: &Value) {
+        self.sources.graft(&self.key, self.layer_sources, &self.key);
+        self.originate(value);
+    }
+
+    fn originate(&mut self, value: &Value) {
+        self.origins.insert(self.key.clone(), self.index);
+        if let Value::Table(t) = value {
+            for (k, v) in t {
+                self.key.push(k.clone());
+                self.originate(v);
+                self.key.pop();
             }
         }
-        if *item == '[' && !openstring && !keygroup && !arrayoftables {
-            if beginline {
-                if sl.get(i + 1).map_or(false, |&c| c == '[') {
-                    arrayoftables = true;
-                } else {
-                    keygroup = true;
-                }
-            } else {
-                openarr += 1;
-            }
+    }
+}
+
+/// Walks from `root` down `path`, stepping into the last element of any
+/// array of tables on the way, and returns the table found there.
+fn _get_level<'t, 'a, V: Node<'a>>(root: &'t mut V::Table, path: &[Cow<'a, str>]) -> Option<&'t mut V::Table> {
+    let mut level = root;
+    for group in path {
+        level = level.level_mut(group)?;
+    }
+    Some(level)
+}
+
+/// Parses string as toml
+///
+/// Args:
+///     s: String to be parsed
+///     _dict: (optional) Specifies the class of the returned toml dictionary,
+///         e.g. `Table::ordered` or `Table::backed::<BTreeMap<String, Value>>`
+///     decoder: The decoder to use
+///
+/// Returns:
+///     Parsed toml file represented as a dictionary
+///
+/// Raises:
+///     TomlDecodeError: Error while decoding toml
+pub fn loads(s: &str, _dict: Option<fn() -> Table>, decoder: Option<&TomlDecoder>) -> Result<Table, TomlDecodeError> {
+    let owned;
+    let decoder = match decoder {
+        Some(decoder) => decoder,
+        None => {
+            owned = TomlDecoder::new(_dict);
+            &owned
         }
-        if *item == ']' && !openstring {
-            if keygroup {
-                keygroup = false;
-            } else if arrayoftables {
-                if sl[i - 1] == ']' {
-                    arrayoftables = false;
-                }
-            } else {
-                openarr -= 1;
-            }
+    };
+    let mut parser = Parser::new(s, decoder);
+    match parser.document(false).into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(parser.root),
+    }
+}
+
+/// Parses string as toml, collecting every error instead of stopping at
+/// the first one.
+///
+/// After an error the rest of the offending statement is skipped, through
+/// the end of any multi-line array it opened, along with the whole table
+/// when the error is in a table header, and decoding resumes from there.
+///
+/// Args:
+///     s: String to be parsed
+///     _dict: (optional) Specifies the class of the returned toml dictionary
+///     decoder: The decoder to use
+///
+/// Returns:
+///     The dictionary decoded from the lines that could be parsed, and the
+///     errors found, in document order
+pub fn loads_with_diagnostics(
+    s: &str,
+    _dict: Option<fn() -> Table>,
+    decoder: Option<&TomlDecoder>,
+) -> (Table, Vec<TomlDecodeError>) {
+    let owned;
+    let decoder = match decoder {
+        Some(decoder) => decoder,
+        None => {
+            owned = TomlDecoder::new(_dict);
+            &owned
         }
-        if *item == '\n' {
-            if openstring || multilinestr {
-                if !multilinestr {
-                    return Err(TomlDecodeError::new("Unbalanced quotes".to_string(), &original, i));
-                }
-                if (sl[i - 1] == '\'' || sl[i - 1] == '"') && sl[i - 2] == sl[i - 1] {
-                    sl[i] = sl[i - 1];
-                    if sl[i - 3] == sl[i - 1] {
-                        sl[i - 3] = ' ';
-                    }
-                }
-            } else if openarr > 0 {
-                sl[i] = ' ';
-            } else {
-                beginline = true;
-            }
-            line_no += 1;
-        } else if beginline && *item != ' ' && *item != '\t' {
-            beginline = false;
-            if !keygroup && !arrayoftables {
-                if *item == '=' {
-                    return Err(TomlDecodeError::new("Found empty keyname.".to_string(), &original, i));
-                }
-                keyname = 1;
-                key.push(*item);
-            }
+    };
+    let mut parser = Parser::new(s, decoder);
+    let errors = parser.document(true);
+    (parser.root, errors)
+}
+
+/// Parses string as toml, recording where each key and value is defined.
+///
+/// Args:
+///     s: String to be parsed
+///     _dict: (optional) Specifies the class of the returned toml dictionary
+///     decoder: The decoder to use
+///
+/// Returns:
+///     Parsed toml file represented as a dictionary, and the source of
+///     each of its keys and values
+///
+/// Raises:
+///     TomlDecodeError: Error while decoding toml
+pub fn loads_with_sources(s: &str, _dict: Option<fn() -> Table>, decoder: Option<&TomlDecoder>) -> Result<(Table, SourceMap), TomlDecodeError> {
+    _loads_with_sources(s, None, _dict, decoder)
+}
+
+/// Parses the named file as toml, recording where each key and value is
+/// defined. The sources name the file.
+///
+/// Args:
+///     path: Path to the file to open
+///     _dict: (optional) Specifies the class of the returned toml dictionary
+///     decoder: The decoder to use
+///
+/// Returns:
+///     Parsed toml file represented as a dictionary, and the source of
+///     each of its keys and values
+///
+/// Raises:
+///     DeError::File: When the file cannot be read, wrapping a
+///     `DeError::Io`, or is not valid TOML, wrapping a `DeError::Decode`
+pub fn load_with_sources(
+    path: impl AsRef<Path>,
+    _dict: Option<fn() -> Table>,
+    decoder: Option<&TomlDecoder>,
+) -> Result<(Table, SourceMap), DeError> {
+    let path = path.as_ref();
+    let parse = || -> Result<(Table, SourceMap), DeError> {
+        let bytes = std::fs::read(path)?;
+        Ok(_loads_with_sources(_decode_bytes(&bytes)?, Some(path), _dict, decoder)?)
+    };
+    parse().map_err(|e| e.in_file(path))
+}
+
+fn _loads_with_sources(
+    s: &str,
+    file: Option<&Path>,
+    _dict: Option<fn() -> Table>,
+    decoder: Option<&TomlDecoder>,
+) -> Result<(Table, SourceMap), TomlDecodeError> {
+    let owned;
+    let decoder = match decoder {
+        Some(decoder) => decoder,
+        None => {
+            owned = TomlDecoder::new(_dict);
+            &owned
         }
+    };
+    let mut parser = Parser::new(s, decoder);
+    parser.sources = Some(Vec::new());
+    if let Some(err) = parser.document(false).into_iter().next() {
+        return Err(err);
     }
-    if keyname != 0 {
-        return Err(TomlDecodeError::new("Key name found without value. Reached end of file.".to_string(), &original, s.len()));
+    let sources = SourceMap::from_locations(s, file, parser.sources.take().unwrap_or_default());
+    Ok((parser.root, sources))
+}
+
+/// Parses string as toml without copying its keys and strings where it
+/// can. See `BorrowedValue`.
+///
+/// Args:
+///     s: String to be parsed
+///
+/// Returns:
+///     The document's root table, borrowing from `s`
+///
+/// Raises:
+///     TomlDecodeError: Error while decoding toml
+pub fn loads_borrowed<'a>(s: &'a str) -> Result<BorrowedValue<'a>, TomlDecodeError> {
+    let decoder = TomlDecoder::new(None);
+    let mut parser: Parser<'a, '_, BorrowedValue<'a>> = Parser::with_root(s, &decoder, BorrowedTable::new());
+    match parser.statements(false).into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(BorrowedValue::Table(parser.root)),
     }
-    if openstring {
-        return Err(TomlDecodeError::new("Unterminated string found. Reached end of file.".to_string(), &original, s.len()));
+}
+
+/// A span of TOML source text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The byte range in the source.
+    pub bytes: Range<usize>,
+    /// The 1-based line the span starts on.
+    pub lineno: usize,
+    /// The 1-based column (in characters) the span starts at.
+    pub colno: usize,
+}
+
+/// Where a key and its value are defined.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Source {
+    /// The file, if the source was loaded from one.
+    pub file: Option<Arc<Path>>,
+    /// The key: the last part of a table header or dotted key naming the
+    /// value. `None` for an array element.
+    pub key: Option<Span>,
+    /// The value of a key/value pair or array element, or the header of
+    /// an element of an array of tables. `None` for a table made by a
+    /// header or dotted key.
+    pub value: Option<Span>,
+}
+
+impl std::fmt::Display for Source {
+    /// Formats the start of the source as `file:line:column`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(span) = self.key.as_ref().or(self.value.as_ref()) else {
+            return Ok(());
+        };
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}:{}", span.lineno, span.colno)
     }
-    let s = sl.iter().collect::<String>();
-    let s: Vec<&str> = s.split('\n').collect();
-    let mut multikey = None;
-    let mut multilinestr = String::new();
-    let mut multibackslash = false;
-    let mut pos = 0;
+}
 
-    for (idx, line) in s.iter().enumerate() {
-        if idx > 0 {
-            pos += s[idx - 1].len() + 1;
+/// The `Source` of each key and value of a decoded document.
+///
+/// Paths are lists of keys, as for `Document`, with array elements,
+/// including tables of arrays of tables, indexed by position. Tables that
+/// are only implied, like `a` in `[a.b]`, have the source of the first
+/// header or key that names them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    sources: BTreeMap<Vec<String>, Source>,
+}
+
+impl SourceMap {
+    fn from_locations(src: &str, file: Option<&Path>, locations: Vec<Location>) -> SourceMap {
+        let file: Option<Arc<Path>> = file.map(Arc::from);
+        let newlines: Vec<usize> = src.match_indices('\n').map(|(i, _)| i).collect();
+        let span = |bytes: Range<usize>| {
+            let line = newlines.partition_point(|&nl| nl < bytes.start);
+            let line_start = if line == 0 { 0 } else { newlines[line - 1] + 1 };
+            Span {
+                lineno: line + 1,
+                colno: src[line_start..bytes.start].chars().count() + 1,
+                bytes,
+            }
+        };
+        let mut sources = BTreeMap::new();
+        for Location { path, key, value } in locations {
+            sources.entry(path).or_insert_with(|| Source {
+                file: file.clone(),
+                key: key.map(span),
+                value: value.map(span),
+            });
         }
+        SourceMap { sources }
+    }
 
-        decoder.embed_comments(idx, currentlevel);
+    /// Returns the source of the key or value at `path`.
+    pub fn get(&self, path: &[&str]) -> Option<&Source> {
+        self.sources.get(&path.iter().map(|k| k.to_string()).collect::<Vec<_>>())
+    }
+
+    /// Returns each path and its source, in path order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<String>, &Source)> {
+        self.sources.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
 
-        if !multilinestr.is_empty() && !multibackslash && !multilinestr.contains('\n') {
-            line = line.trim();
+    /// Replaces the sources at and below `at` with those at and below
+    /// `from` in `other`.
+    fn graft(&mut self, at: &[String], other: &SourceMap, from: &[String]) {
+        let stale: Vec<Vec<String>> = self.sources.range(at.to_vec()..).map(|(k, _)| k).take_while(|k| k.starts_with(at)).cloned().collect();
+        for k in stale {
+            self.sources.remove(&k);
         }
-        if line.is_empty() && (multikey.is_none() || multibackslash) {
-            continue;
+        for (k, source) in other.sources.range(from.to_vec()..).take_while(|(k, _)| k.starts_with(from)) {
+            let mut path = at.to_vec();
+            path.extend_from_slice(&k[from.len()..]);
+            self.sources.insert(path, source.clone());
         }
-        if let Some(multikey) = multikey {
-            if multibackslash {
-                multilinestr.push_str(line);
-            } else {
-                multilinestr.push_str(line);
+    }
+}
+
+/// An item of a TOML document, as read by `EventReader`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A `[table]` header, with the parts of the table's name.
+    TableHeader(Vec<String>),
+    /// A `[[array]]` header, with the parts of the array's name.
+    ArrayTableHeader(Vec<String>),
+    /// A `key = value` pair. The key's parts are relative to the table of
+    /// the last header.
+    KeyValue(Vec<String>, Value),
+    /// A comment on a line of its own or after a header or pair, including
+    /// its `#`. Comments inside multi-line arrays are not reported.
+    Comment(String),
+}
+
+/// A pull parser that reads a TOML document from a reader one statement at
+/// a time, yielding each `Event` with its byte span in the input.
+///
+/// Only the statement being read is kept in memory, so arbitrarily large
+/// inputs can be processed. The syntax is checked as with `loads`, but
+/// rules that need the whole document, such as keys not being defined
+/// twice, are left to the consumer. Iteration stops after an error, whose
+/// `doc` is the text of the statement it was found in, starting at
+/// `doc_offset`; its `pos` and line are those in the whole input. A byte
+/// order mark at the start of the input is skipped, and the bytes are
+/// checked as by `from_bytes`, one line at a time.
+pub struct EventReader<R> {
+    reader: std::io::BufReader<R>,
+    decoder: TomlDecoder,
+    /// The input read but not yet parsed, starting at a line start.
+    buf: String,
+    /// The byte offset of `buf` in the input.
+    offset: usize,
+    /// The number of lines before `buf`.
+    lines: usize,
+    eof: bool,
+    done: bool,
+    events: VecDeque<(Event, Range<usize>)>,
+    /// An encoding error in the input after `buf`, reported once the
+    /// statements before it are read.
+    pending: Option<TomlDecodeError>,
+}
+
+impl<R: Read> EventReader<R> {
+    /// Creates an `EventReader` reading from `reader`.
+    pub fn new(reader: R) -> EventReader<R> {
+        EventReader {
+            reader: std::io::BufReader::new(reader),
+            decoder: TomlDecoder::default(),
+            buf: String::new(),
+            offset: 0,
+            lines: 0,
+            eof: false,
+            done: false,
+            events: VecDeque::new(),
+            pending: None,
+        }
+    }
+
+    /// Reads whole lines until `buf` has at least doubled, so that a
+    /// statement spanning many lines is parsed a logarithmic number of
+    /// times.
+    ///
+    /// Each line is checked as by `_decode_bytes`. A line that fails is
+    /// kept up to the offending byte, and the error is left in `pending`.
+    fn fill(&mut self) -> std::io::Result<()> {
+        let target = self.buf.len() * 2;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                self.eof = true;
+                return Ok(());
             }
-            multibackslash = false;
-            let closed = if multilinestr.starts_with('[') {
-                line.ends_with(']')
-            } else if multilinestr.len() > 2 {
-                line.ends_with(multilinestr.chars().next().unwrap()) && line.chars().rev().nth(1) == Some(multilinestr.chars().next().unwrap()) && line.chars().rev().nth(2) == Some(multilinestr.chars().next().unwrap())
-            } else {
-                false
-            };
-            if closed {
-                let value = decoder.load_value(&multilinestr)?;
-                currentlevel.insert(multikey, value);
-                multikey = None;
-                multilinestr.clear();
-            } else {
-                let mut k = multilinestr.len() - 1;
-                while k > 0 && multilinestr.chars().nth(k) == Some('\\') {
-                    multibackslash = !multibackslash;
-                    k -= 1;
+            let mut bytes = &line[..];
+            if self.offset == 0 && self.buf.is_empty() {
+                if let Err(err) = _check_utf16(bytes) {
+                    self.pending = Some(err);
+                    return Ok(());
                 }
-                if multibackslash {
-                    multilinestr.pop();
-                } else {
-                    multilinestr.push('\n');
+                if let Some(rest) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+                    bytes = rest;
+                    self.offset = 3;
+                }
+            }
+            match _decode_utf8(bytes) {
+                Ok(text) => self.buf.push_str(text),
+                Err(err) => {
+                    let valid = std::str::from_utf8(&bytes[..err.span().start]).unwrap_or_default();
+                    let lines = self.lines + self.buf.matches('\n').count();
+                    let err = err.within(self.offset + self.buf.len(), lines);
+                    self.buf.push_str(valid);
+                    self.pending = Some(err);
+                    return Ok(());
                 }
             }
-            continue;
+            if self.buf.len() >= target {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parses the next statement in `buf`. Returns `Ok(false)` if the
+    /// statement may continue past what was read so far.
+    fn statement(&mut self) -> Result<bool, TomlDecodeError> {
+        let mut parser = Parser::new(&self.buf, &self.decoder);
+        let events = match parser.events() {
+            Err(err) if !self.eof && err.span().end >= self.buf.len() => return Ok(false),
+            Err(err) => return Err(err.within(self.offset, self.lines)),
+            Ok(events) => events,
+        };
+        let rest = &self.buf[parser.lexer.pos..];
+        let end = if rest.starts_with("\r\n") {
+            parser.lexer.pos + 2
+        } else if rest.starts_with('\n') || rest.is_empty() {
+            parser.lexer.pos + rest.len().min(1)
+        } else {
+            parser.lexer.pos
+        };
+        if end == self.buf.len() && !self.buf.ends_with('\n') && !self.eof {
+            return Ok(false);
         }
-        if line.starts_with('[') {
-            arrayoftables = false;
-            if line.len() == 1 {
-                return Err(TomlDecodeError::new("Opening key group bracket on line by itself.".to_string(), &original, pos));
+        let offset = self.offset;
+        self.events.extend(events.into_iter().map(|(event, span)| (event, offset + span.start..offset + span.end)));
+        self.lines += self.buf[..end].matches('\n').count();
+        self.offset += end;
+        self.buf.drain(..end);
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for EventReader<R> {
+    type Item = Result<(Event, Range<usize>), TomlDecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
             }
-            if line.chars().nth(1) == Some('[') {
-                arrayoftables = true;
-                line = &line[2..];
-                let splitstr = "]]";
-            } else {
-                line = &line[1..];
-                let splitstr = "]";
-            }
-            let mut i = 1;
-            let quotesplits = decoder._get_split_on_quotes(line);
-            let mut quoted = false;
-            for quotesplit in quotesplits {
-                if !quoted && quotesplit.contains(splitstr) {
-                    break;
-                }
-                i += quotesplit.matches(splitstr).count();
-                quoted = !quoted;
-            }
-            line = line.split(splitstr).take(i).collect::<Vec<&str>>().join(splitstr);
-            if line.len() < i + 1 || line.trim() != "" {
-                return Err(TomlDecodeError::new("Key group not on a line by itself.".to_string(), &original, pos));
-            }
-            let groups: Vec<&str> = line.split('.').collect();
-            let mut i = 0;
-            while i < groups.len() {
-                let group = groups[i].trim();
-                if group.is_empty() {
-                    return Err(TomlDecodeError::new("Can't have a keygroup with an empty name".to_string(), &original, pos));
-                }
-                if group.starts_with('"') || group.starts_with('\'') {
-                    let groupstr = group.to_string();
-                    let mut j = i + 1;
-                    while !(groupstr.chars().next().unwrap() == groupstr.chars().last().unwrap() && groupstr.len() > 1) {
-                        j += 1;
-                        if j > groups.len() + 2 {
-                            return Err(TomlDecodeError::new(format!("Invalid group name '{}'. Something went wrong.", groupstr), &original, pos));
-                        }
+            if self.done {
+                return None;
+            }
+            if self.eof && self.buf.is_empty() {
+                self.done = true;
+                return None;
+            }
+            match self.statement() {
+                Ok(true) => {}
+                Ok(false) => {
+                    if let Some(err) = self.pending.take() {
+                        self.done = true;
+                        return Some(Err(err));
                     }
-                    groups[i] = &groupstr[1..groupstr.len() - 1];
-                    groups.splice(i + 1..j, std::iter::empty());
-                } else {
-                    if !_groupname_re.is_match(group) {
-                        return Err(TomlDecodeError::new(format!("Invalid group name '{}'. Try quoting it.", group), &original, pos));
+                    if let Err(e) = self.fill() {
+                        self.done = true;
+                        return Some(Err(TomlDecodeError::new(ErrorKind::Io, e.to_string(), String::new(), 0..0)));
                     }
                 }
-                i += 1;
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
             }
-            currentlevel = &mut retval;
-            for i in 0..groups.len() {
-                let group = groups[i];
-                if group.is_empty() {
-                    return Err(TomlDecodeError::new("Can't have a keygroup with an empty name".to_string(), &original, pos));
+        }
+    }
+}
+
+/// A lexical token. Strings and scalars carry their decoded value.
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Newline,
+    Comment(&'a str),
+    Equals,
+    Period,
+    Comma,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    BareKey(&'a str),
+    /// A string, borrowed from the document unless it had to be unescaped.
+    String { value: Cow<'a, str>, multiline: bool },
+    Scalar(Value),
+    Eof,
+}
+
+/// Splits a document into tokens with byte spans in a single pass.
+///
+/// Whether a bare word is a key or a value depends on where it appears
+/// (`true = true`), so the parser tells `next` which one it expects.
+#[derive(Debug, Clone)]
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    /// True when the last token was a newline, or nothing was read yet.
+    at_line_start: bool,
+    /// `src` as shared by the errors found in it, made on the first one.
+    error_source: OnceCell<Arc<ErrorSource>>,
+}
+
+/// A copy of a lexer's source that its errors share, and where its lines
+/// break. Recovering decoders report an error per bad statement, so the
+/// source is copied and indexed once rather than for each of them.
+#[derive(Debug)]
+struct ErrorSource {
+    doc: Arc<str>,
+    newlines: Vec<usize>,
+}
+
+impl ErrorSource {
+    fn new(src: &str) -> ErrorSource {
+        ErrorSource {
+            doc: Arc::from(src),
+            newlines: src.match_indices('\n').map(|(i, _)| i).collect(),
+        }
+    }
+
+    /// Returns the 1-based line and column (in characters) of byte `pos`,
+    /// which must be a char boundary.
+    fn line_col(&self, pos: usize) -> (usize, usize) {
+        let line = self.newlines.partition_point(|&nl| nl < pos);
+        let line_start = if line == 0 { 0 } else { self.newlines[line - 1] + 1 };
+        (line + 1, self.doc[line_start..pos].chars().count() + 1)
+    }
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Lexer<'a> {
+        Lexer {
+            src,
+            pos: 0,
+            at_line_start: true,
+            error_source: OnceCell::new(),
+        }
+    }
+
+    fn error(&self, kind: ErrorKind, msg: String, span: Range<usize>) -> TomlDecodeError {
+        let source = self.error_source();
+        TomlDecodeError::located(kind, msg, source.doc.clone(), span, |_, pos| source.line_col(pos))
+    }
+
+    /// Locates `bytes`, the first definition of something an error
+    /// redefines.
+    fn span(&self, bytes: Range<usize>) -> Span {
+        let (lineno, colno) = self.error_source().line_col(bytes.start);
+        Span { bytes, lineno, colno }
+    }
+
+    fn error_source(&self) -> &ErrorSource {
+        self.error_source.get_or_init(|| Arc::new(ErrorSource::new(self.src)))
+    }
+
+    fn peek_byte(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    /// Consumes `b` if it is the very next byte.
+    fn eat(&mut self, b: u8) -> bool {
+        let found = self.peek_byte() == Some(b);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Skips spaces and tabs.
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek_byte(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips the rest of a statement that started at `start` and failed to
+    /// parse at the current position. Stops at the first line end past the
+    /// error where the statement's brackets and braces are all closed, or
+    /// where the next line starts a table header or, outside an inline
+    /// table, a key/value pair, so that an error inside a multi-line array
+    /// does not resume decoding in the middle of it.
+    fn skip_statement(&mut self, start: usize) {
+        let src = self.src;
+        let bytes = src.as_bytes();
+        let mut open = Vec::new();
+        let mut pos = start;
+        let resumes = |open: &[u8], line: &str| {
+            open.is_empty() || STATEMENT_RE.captures(line).is_some_and(|caps| caps.name("header").is_some() || open.last() != Some(&b'{'))
+        };
+        while pos < bytes.len() {
+            match bytes[pos] {
+                b @ (b'[' | b'{') => open.push(b),
+                b']' if open.last() == Some(&b'[') => {
+                    open.pop();
+                }
+                b'}' if open.last() == Some(&b'{') => {
+                    open.pop();
                 }
-                if let Some(existing) = currentlevel.get(group) {
-                    if i == groups.len() - 1 {
-                        if implicitgroups.contains(&group) {
-                            implicitgroups.retain(|g| g != group);
-                            if arrayoftables {
-                                return Err(TomlDecodeError::new("An implicitly defined table can't be an array".to_string(), &original, pos));
+                b'#' => pos = src[pos..].find('\n').map_or(bytes.len(), |nl| pos + nl) - 1,
+                quote @ (b'"' | b'\'') => {
+                    let n = if bytes[pos..].starts_with(&[quote; 3]) { 3 } else { 1 };
+                    pos += n;
+                    loop {
+                        match bytes.get(pos) {
+                            None => break,
+                            // Leave the newline ending an unterminated
+                            // single-line string to the outer loop.
+                            Some(b'\n') if n == 1 => {
+                                pos -= 1;
+                                break;
                             }
-                        } else if arrayoftables {
-                            currentlevel.insert(group.to_string(), vec![decoder.get_empty_table()]);
-                        } else {
-                            return Err(TomlDecodeError::new(format!("What? {} already exists? {}", group, currentlevel), &original, pos));
+                            Some(b'\\') if quote == b'"' => pos += 2,
+                            Some(_) if bytes[pos..].starts_with(&[quote; 3][..n]) => {
+                                pos += n - 1;
+                                break;
+                            }
+                            Some(_) => pos += 1,
                         }
                     }
-                } else {
-                    currentlevel.insert(group.to_string(), decoder.get_empty_table());
-                    if i == groups.len() - 1 && arrayoftables {
-                        currentlevel.insert(group.to_string(), vec![decoder.get_empty_table()]);
-                    }
                 }
-                currentlevel = currentlevel.get_mut(group).unwrap();
-                if arrayoftables {
-                    currentlevel = currentlevel.last_mut().unwrap();
+                b'\n' if pos + 1 >= self.pos && resumes(&open, src[pos + 1..].lines().next().unwrap_or("")) => {
+                    pos += 1;
+                    break;
                 }
+                _ => {}
+            }
+            pos += 1;
+        }
+        self.pos = pos.min(bytes.len());
+        self.at_line_start = true;
+    }
+
+    /// Returns the next token and its span. `value` selects whether a bare
+    /// word is read as a value (boolean, number, date or time) or a key.
+    fn next(&mut self, value: bool) -> Result<(Token<'a>, Range<usize>), TomlDecodeError> {
+        self.skip_whitespace();
+        let src = self.src;
+        let start = self.pos;
+        let rest = &src[start..];
+        let token = match rest.as_bytes().first() {
+            None => Token::Eof,
+            Some(b'\n') => self.punct(Token::Newline),
+            Some(b'\r') if rest.starts_with("\r\n") => {
+                self.pos += 2;
+                Token::Newline
             }
-        } else if line.starts_with("{") {
-            if !line.ends_with("}") {
-                return Err(TomlDecodeError::new("Line breaks are not allowed in inline objects".to_string(), &original, pos));
+            Some(b'#') => {
+                let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+                let comment = line.strip_suffix('\r').unwrap_or(line);
+                if let Some((i, c)) = comment.char_indices().find(|&(_, c)| _is_control(c)) {
+                    let msg = format!("Control character '{}' is not allowed in comments", c.escape_debug());
+                    return Err(self.error(ErrorKind::InvalidCharacter, msg, start + i..start + i + c.len_utf8()));
+                }
+                self.pos += comment.len();
+                Token::Comment(comment)
             }
-            decoder.load_inline_object(line, currentlevel, multikey, multibackslash)?;
-        } else if line.contains('=') {
-            let ret = decoder.load_line(line, currentlevel, multikey, multibackslash)?;
-            if let Some((mk, mls, mb)) = ret {
-                multikey = Some(mk);
-                multilinestr = mls;
-                multibackslash = mb;
+            Some(b'=') => self.punct(Token::Equals),
+            Some(b'.') => self.punct(Token::Period),
+            Some(b',') => self.punct(Token::Comma),
+            Some(b'[') => self.punct(Token::LeftBracket),
+            Some(b']') => self.punct(Token::RightBracket),
+            Some(b'{') => self.punct(Token::LeftBrace),
+            Some(b'}') => self.punct(Token::RightBrace),
+            Some(&quote @ (b'"' | b'\'')) => self.string(quote)?,
+            Some(_) if value => self.bare_value()?,
+            Some(_) => self.bare_key()?,
+        };
+        self.at_line_start = token == Token::Newline;
+        Ok((token, start..self.pos))
+    }
+
+    fn punct(&mut self, token: Token<'a>) -> Token<'a> {
+        self.pos += 1;
+        token
+    }
+
+    fn bare_key(&mut self) -> Result<Token<'a>, TomlDecodeError> {
+        let src = self.src;
+        let start = self.pos;
+        let len = src[start..].bytes().take_while(|&b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-').count();
+        if len == 0 {
+            let c = src[start..].chars().next().unwrap_or_default();
+            let msg = format!("Found invalid character in key name: '{}'. Try quoting the key name.", c.escape_debug());
+            return Err(self.error(ErrorKind::InvalidKey, msg, start..start + c.len_utf8()));
+        }
+        self.pos += len;
+        Ok(Token::BareKey(&src[start..self.pos]))
+    }
+
+    fn bare_value(&mut self) -> Result<Token<'a>, TomlDecodeError> {
+        let src = self.src;
+        let b = src.as_bytes();
+        let start = self.pos;
+        let run = |from: usize| {
+            from + b[from..].iter().take_while(|&&b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'+' | b'-' | b'.' | b':')).count()
+        };
+        let mut end = run(start);
+        // A space may separate the date and the time of a datetime.
+        if end - start == 10
+            && b[start + 4] == b'-'
+            && b.len() > end + 3
+            && b[end] == b' '
+            && b[end + 1].is_ascii_digit()
+            && b[end + 2].is_ascii_digit()
+            && b[end + 3] == b':'
+        {
+            end = run(end + 1);
+        }
+        if end == start {
+            let c = src[start..].chars().next().unwrap_or_default();
+            let msg = format!("Invalid value: {}", c.escape_debug());
+            return Err(self.error(ErrorKind::InvalidValue, msg, start..start + c.len_utf8()));
+        }
+        self.pos = end;
+        _load_scalar(&src[start..end])
+            .map(Token::Scalar)
+            .map_err(|(kind, msg)| self.error(kind, msg, start..end))
+    }
+
+    fn string(&mut self, quote: u8) -> Result<Token<'a>, TomlDecodeError> {
+        let src = self.src;
+        let start = self.pos;
+        let literal = quote == b'\'';
+        let delimiter = if literal { "'''" } else { "\"\"\"" };
+        let multiline = src[start..].starts_with(delimiter);
+        if multiline {
+            self.pos += 3;
+            // A newline immediately following the opening delimiter is trimmed.
+            if src[self.pos..].starts_with('\n') {
+                self.pos += 1;
+            } else if src[self.pos..].starts_with("\r\n") {
+                self.pos += 2;
+            }
+        } else {
+            self.pos += 1;
+        }
+        let content = self.pos;
+        // Set once the value stops being a plain slice of the document.
+        let mut unescaped: Option<String> = None;
+        let end = loop {
+            // Skip the run of bytes that need no attention in one step;
+            // every byte that ends it is ASCII.
+            let run = src.as_bytes()[self.pos..]
+                .iter()
+                .position(|&b| b == quote || b == b'\\' || b < b' ' || b == 0x7f)
+                .unwrap_or(src.len() - self.pos);
+            if let Some(value) = &mut unescaped {
+                value.push_str(&src[self.pos..self.pos + run]);
+            }
+            self.pos += run;
+            let c = match src.as_bytes().get(self.pos) {
+                Some(&b) => b as char,
+                None => {
+                    let msg = "Unterminated string found. Reached end of file.".to_string();
+                    return Err(self.error(ErrorKind::UnterminatedString, msg, start..self.pos));
+                }
+            };
+            if c == quote as char {
+                if !multiline {
+                    self.pos += 1;
+                    break self.pos - 1;
+                }
+                if src[self.pos..].starts_with(delimiter) {
+                    // Up to two quotes right before the closing delimiter
+                    // belong to the string.
+                    let run = src[self.pos..].bytes().take_while(|&b| b == quote).count();
+                    if run > 5 {
+                        let msg = "Found tokens after a closed string. Invalid TOML.".to_string();
+                        return Err(self.error(ErrorKind::InvalidString, msg, self.pos..self.pos + run));
+                    }
+                    if let Some(value) = &mut unescaped {
+                        value.extend(std::iter::repeat_n(c, run - 3));
+                    }
+                    self.pos += run;
+                    break self.pos - 3;
+                }
+            } else if c == '\n' && !multiline {
+                return Err(self.error(ErrorKind::UnterminatedString, "Unbalanced quotes".to_string(), start..self.pos));
+            } else if c == '\r' && multiline && src[self.pos..].starts_with("\r\n") {
+                // Line endings in multi-line strings are normalized to LF.
+                unescaped.get_or_insert_with(|| src[content..self.pos].to_string());
+                self.pos += 1;
+                continue;
+            } else if _is_control(c) && !(c == '\n' && multiline) {
+                let msg = format!("Control character '{}' is not allowed in strings", c.escape_debug());
+                return Err(self.error(ErrorKind::InvalidCharacter, msg, self.pos..self.pos + 1));
+            } else if c == '\\' && !literal {
+                let value = unescaped.get_or_insert_with(|| src[content..self.pos].to_string());
+                self.escape(value, multiline)?;
+                continue;
+            }
+            if let Some(value) = &mut unescaped {
+                value.push(c);
+            }
+            self.pos += c.len_utf8();
+        };
+        let value = match unescaped {
+            Some(value) => Cow::Owned(value),
+            None => Cow::Borrowed(&src[content..end]),
+        };
+        Ok(Token::String { value, multiline })
+    }
+
+    /// Decodes the escape sequence at `pos` into `value`.
+    fn escape(&mut self, value: &mut String, multiline: bool) -> Result<(), TomlDecodeError> {
+        let src = self.src;
+        let start = self.pos;
+        self.pos += 1;
+        let c = match src[self.pos..].chars().next() {
+            Some(c) => c,
+            None => {
+                let msg = "Unterminated string found. Reached end of file.".to_string();
+                return Err(self.error(ErrorKind::UnterminatedString, msg, start..self.pos));
+            }
+        };
+        self.pos += c.len_utf8();
+        let unescaped = match c {
+            'b' => '\u{8}',
+            't' => '\t',
+            'n' => '\n',
+            'f' => '\u{c}',
+            'r' => '\r',
+            '"' => '"',
+            '\\' => '\\',
+            'u' | 'U' => {
+                let len = if c == 'u' { 4 } else { 8 };
+                let hex = src.get(self.pos..self.pos + len).filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()));
+                let Some(hex) = hex else {
+                    let msg = format!("Invalid escape sequence: \\{}", c);
+                    return Err(self.error(ErrorKind::InvalidEscape, msg, start..self.pos));
+                };
+                self.pos += len;
+                match u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+                    Some(unescaped) => unescaped,
+                    None => {
+                        let msg = format!("Invalid escape sequence: \\{}{}. Only scalar unicode points are allowed.", c, hex);
+                        return Err(self.error(ErrorKind::InvalidEscape, msg, start..self.pos));
+                    }
+                }
+            }
+            ' ' | '\t' | '\r' | '\n' if multiline => {
+                // A line ending backslash trims the newline and all
+                // whitespace up to the next non-whitespace character.
+                let rest = &src[start + 1..];
+                let indent = rest.bytes().take_while(|&b| b == b' ' || b == b'\t').count();
+                if !(rest[indent..].starts_with('\n') || rest[indent..].starts_with("\r\n")) {
+                    let msg = "Reserved escape sequence used".to_string();
+                    return Err(self.error(ErrorKind::InvalidEscape, msg, start..self.pos));
+                }
+                self.pos = start + 1 + rest.bytes().take_while(u8::is_ascii_whitespace).count();
+                return Ok(());
+            }
+            _ => return Err(self.error(ErrorKind::InvalidEscape, "Reserved escape sequence used".to_string(), start..self.pos)),
+        };
+        value.push(unescaped);
+        Ok(())
+    }
+}
+
+/// A recursive-descent parser building a `Table`, or the tree of another
+/// `Node` type, from `Lexer` tokens.
+struct Parser<'a, 'd, V: Node<'a> = Value> {
+    lexer: Lexer<'a>,
+    decoder: &'d TomlDecoder,
+    root: V::Table,
+    current_path: Vec<Cow<'a, str>>,
+    /// How each table and key was defined, for the redefinition rules.
+    definitions: Definitions<'a>,
+    /// Whether the decoder keeps comments, so that the parser tracks the
+    /// path of each item.
+    comments: bool,
+    /// Comments on lines of their own, waiting for the item below them.
+    pending_comments: Vec<String>,
+    /// The path of the value being parsed, when keeping comments or
+    /// sources.
+    value_path: Vec<String>,
+    /// Set after a bad table header so that the table's keys are dropped
+    /// while recovering from the error.
+    skip_table: bool,
+    /// The headers and key/value pairs read so far, when recording an
+    /// outline for `Document`.
+    items: Option<Vec<Item>>,
+    /// The path of the current table as recorded in `items` and used for
+    /// comments.
+    current_item_path: Vec<(String, Option<Range<usize>>)>,
+    /// The key and value spans of each path, when recording sources for
+    /// `loads_with_sources`.
+    sources: Option<Vec<Location>>,
+}
+
+/// Where the key and value at a path are, as recorded by the parser.
+struct Location {
+    path: Vec<String>,
+    key: Option<Range<usize>>,
+    value: Option<Range<usize>>,
+}
+
+/// A parsed `key = value` pair and where its parts are.
+struct KeyValue<'a, V> {
+    path: Vec<Cow<'a, str>>,
+    /// The span of each part of the key.
+    spans: Vec<Range<usize>>,
+    value: V,
+    value_span: Range<usize>,
+}
+
+impl<V> KeyValue<'_, V> {
+    fn key_span(&self) -> Range<usize> {
+        self.spans[0].start..self.spans[self.spans.len() - 1].end
+    }
+}
+
+/// The parts of a possibly dotted key, and the span of each.
+type KeyParts<'a> = (Vec<Cow<'a, str>>, Vec<Range<usize>>);
+
+/// A parsed `[a.b]` or `[[a.b]]` header.
+struct Header<'a> {
+    arrayoftables: bool,
+    groups: Vec<Cow<'a, str>>,
+    /// The span of each part of the name.
+    spans: Vec<Range<usize>>,
+}
+
+/// A table header or key/value pair of a document, with the spans a
+/// `Document` edits.
+#[derive(Debug, Clone)]
+struct Item {
+    /// The path the item names: a header's table, or a pair's table
+    /// followed by its key. Arrays of tables are indexed by element, as in
+    /// `["fruit", "1", "name"]`. Each part has the span of its text if this
+    /// item spells it out.
+    path: Vec<(String, Option<Range<usize>>)>,
+    /// The span of the value of a key/value pair; `None` for a header.
+    value: Option<Range<usize>>,
+    /// The item's lines, from the start of the first through the newline
+    /// ending the last.
+    lines: Range<usize>,
+}
+
+impl Item {
+    /// Returns true if the item's path starts with `prefix`.
+    fn starts_with(&self, prefix: &[&str]) -> bool {
+        self.path.len() >= prefix.len() && self.path.iter().zip(prefix).all(|((part, _), p)| part == p)
+    }
+
+    /// Returns the path of the table the item defines or belongs to.
+    fn table(&self) -> Vec<&str> {
+        let len = if self.value.is_some() {
+            self.path.iter().rposition(|(_, span)| span.is_none()).map_or(0, |i| i + 1)
+        } else {
+            self.path.len()
+        };
+        self.path[..len].iter().map(|(part, _)| part.as_str()).collect()
+    }
+}
+
+/// A tree of values that `Parser` can build: `Value`, or `BorrowedValue`
+/// for `loads_borrowed`.
+trait Node<'a>: Sized + std::fmt::Debug {
+    type Table: NodeTable<'a, Self>;
+
+    /// Returns an empty table, or inline table, as the decoder makes them.
+    fn new_table(decoder: &TomlDecoder, inline: bool) -> Self::Table;
+    fn string(s: Cow<'a, str>) -> Self;
+    /// Wraps a decoded boolean, number, date or time.
+    fn scalar(v: Value) -> Self;
+    fn array(items: Vec<Self>) -> Self;
+    fn table(table: Self::Table) -> Self;
+    fn as_array(&self) -> Option<&Vec<Self>>;
+    fn as_array_mut(&mut self) -> Option<&mut Vec<Self>>;
+    fn as_table(&self) -> Option<&Self::Table>;
+    fn as_table_mut(&mut self) -> Option<&mut Self::Table>;
+}
+
+/// The tables of a `Node` tree.
+trait NodeTable<'a, V> {
+    fn get(&self, key: &str) -> Option<&V>;
+    fn get_mut(&mut self, key: &str) -> Option<&mut V>;
+    fn insert(&mut self, key: Cow<'a, str>, value: V);
+    fn get_or_insert_with(&mut self, key: Cow<'a, str>, default: impl FnOnce() -> V) -> &mut V;
+    /// Returns the table at `key`, or the last table of the array of
+    /// tables there.
+    fn level_mut(&mut self, key: &str) -> Option<&mut Self>;
+    /// Returns true if the table carries the inline table marker.
+    fn is_inline(&self) -> bool;
+}
+
+impl<'a> Node<'a> for Value {
+    type Table = Table;
+
+    fn new_table(decoder: &TomlDecoder, inline: bool) -> Table {
+        if inline {
+            decoder.get_empty_inline_table()
+        } else {
+            decoder.get_empty_table()
+        }
+    }
+
+    fn string(s: Cow<'a, str>) -> Value {
+        Value::String(s.into_owned())
+    }
+
+    fn scalar(v: Value) -> Value {
+        v
+    }
+
+    fn array(items: Vec<Value>) -> Value {
+        Value::Array(items)
+    }
+
+    fn table(table: Table) -> Value {
+        Value::Table(table)
+    }
+
+    fn as_array(&self) -> Option<&Vec<Value>> {
+        Value::as_array(self)
+    }
+
+    fn as_array_mut(&mut self) -> Option<&mut Vec<Value>> {
+        Value::as_array_mut(self)
+    }
+
+    fn as_table(&self) -> Option<&Table> {
+        Value::as_table(self)
+    }
+
+    fn as_table_mut(&mut self) -> Option<&mut Table> {
+        Value::as_table_mut(self)
+    }
+}
+
+impl<'a> NodeTable<'a, Value> for Table {
+    fn get(&self, key: &str) -> Option<&Value> {
+        Table::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        Table::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: Cow<'a, str>, value: Value) {
+        Table::insert(self, key.into_owned(), value);
+    }
+
+    fn get_or_insert_with(&mut self, key: Cow<'a, str>, default: impl FnOnce() -> Value) -> &mut Value {
+        Table::get_or_insert_with(self, &key, default)
+    }
+
+    fn level_mut(&mut self, key: &str) -> Option<&mut Table> {
+        match Table::get_mut(self, key)? {
+            Value::Table(t) => Some(t),
+            Value::Array(a) => a.last_mut()?.as_table_mut(),
+            _ => None,
+        }
+    }
+
+    fn is_inline(&self) -> bool {
+        Table::is_inline(self)
+    }
+}
+
+impl<'a> Node<'a> for BorrowedValue<'a> {
+    type Table = BorrowedTable<'a>;
+
+    fn new_table(_decoder: &TomlDecoder, _inline: bool) -> BorrowedTable<'a> {
+        BorrowedTable::new()
+    }
+
+    fn string(s: Cow<'a, str>) -> BorrowedValue<'a> {
+        BorrowedValue::String(s)
+    }
+
+    fn scalar(v: Value) -> BorrowedValue<'a> {
+        BorrowedValue::from(v)
+    }
+
+    fn array(items: Vec<BorrowedValue<'a>>) -> BorrowedValue<'a> {
+        BorrowedValue::Array(items)
+    }
+
+    fn table(table: BorrowedTable<'a>) -> BorrowedValue<'a> {
+        BorrowedValue::Table(table)
+    }
+
+    fn as_array(&self) -> Option<&Vec<BorrowedValue<'a>>> {
+        BorrowedValue::as_array(self)
+    }
+
+    fn as_array_mut(&mut self) -> Option<&mut Vec<BorrowedValue<'a>>> {
+        match self {
+            BorrowedValue::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    fn as_table(&self) -> Option<&BorrowedTable<'a>> {
+        BorrowedValue::as_table(self)
+    }
+
+    fn as_table_mut(&mut self) -> Option<&mut BorrowedTable<'a>> {
+        match self {
+            BorrowedValue::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> NodeTable<'a, BorrowedValue<'a>> for BorrowedTable<'a> {
+    fn get(&self, key: &str) -> Option<&BorrowedValue<'a>> {
+        HashMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut BorrowedValue<'a>> {
+        HashMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: Cow<'a, str>, value: BorrowedValue<'a>) {
+        HashMap::insert(self, key, value);
+    }
+
+    fn get_or_insert_with(&mut self, key: Cow<'a, str>, default: impl FnOnce() -> BorrowedValue<'a>) -> &mut BorrowedValue<'a> {
+        self.entry(key).or_insert_with(default)
+    }
+
+    fn level_mut(&mut self, key: &str) -> Option<&mut BorrowedTable<'a>> {
+        match HashMap::get_mut(self, key)? {
+            BorrowedValue::Table(t) => Some(t),
+            BorrowedValue::Array(a) => a.last_mut()?.as_table_mut(),
+            _ => None,
+        }
+    }
+
+    /// Borrowed tables carry no marker; the parser's own records keep
+    /// inline tables closed.
+    fn is_inline(&self) -> bool {
+        false
+    }
+}
+
+impl<'a, 'd> Parser<'a, 'd> {
+    fn new(src: &'a str, decoder: &'d TomlDecoder) -> Parser<'a, 'd> {
+        Parser::with_root(src, decoder, decoder.get_empty_table())
+    }
+
+    /// Parses the document into `root` and returns the errors found. Stops
+    /// at the first error unless `recover` is set, in which case decoding
+    /// resumes after the offending statement.
+    fn document(&mut self, recover: bool) -> Vec<TomlDecodeError> {
+        self.decoder.clear_comments();
+        let errors = self.statements(recover);
+        self.decoder.embed_comments(&self.root);
+        errors
+    }
+
+    /// Parses the next statement as events, without building a table, for
+    /// `EventReader`. Returns no events at the end of the input.
+    fn events(&mut self) -> Result<Vec<(Event, Range<usize>)>, TomlDecodeError> {
+        let owned = |path: Vec<Cow<'_, str>>| path.into_iter().map(Cow::into_owned).collect();
+        let mut events = Vec::new();
+        let (event, span, kind, msg) = loop {
+            let (token, span) = self.lexer.next(false)?;
+            match token {
+                Token::Eof => return Ok(events),
+                Token::Newline => {}
+                Token::Comment(comment) => {
+                    events.push((Event::Comment(comment.to_string()), span));
+                    return Ok(events);
+                }
+                Token::LeftBracket => {
+                    let header = self.header_name(span.start)?;
+                    let groups = owned(header.groups);
+                    let event = if header.arrayoftables { Event::ArrayTableHeader(groups) } else { Event::TableHeader(groups) };
+                    break (event, span.start..self.lexer.pos, ErrorKind::InvalidTableHeader, "Key group not on a line by itself.");
+                }
+                Token::BareKey(_) | Token::String { .. } => {
+                    let kv = self.key_value(token, span)?;
+                    let span = kv.spans[0].start..kv.value_span.end;
+                    let msg = "Found tokens after a value. Expected a newline or a comment.";
+                    break (Event::KeyValue(owned(kv.path), kv.value), span, ErrorKind::UnexpectedToken, msg);
+                }
+                Token::Equals => return Err(self.lexer.error(ErrorKind::InvalidKey, "Found empty keyname. ".to_string(), span)),
+                _ => return Err(self.lexer.error(ErrorKind::UnexpectedToken, "Expected a key or a table header".to_string(), span)),
+            }
+        };
+        events.push((event, span));
+        if let Some(comment) = self.line_end(kind, msg)? {
+            let end = self.lexer.pos;
+            events.push((Event::Comment(comment.to_string()), end - comment.len()..end));
+        }
+        Ok(events)
+    }
+}
+
+impl<'a, 'd, V: Node<'a>> Parser<'a, 'd, V> {
+    fn with_root(src: &'a str, decoder: &'d TomlDecoder, root: V::Table) -> Parser<'a, 'd, V> {
+        Parser {
+            lexer: Lexer::new(src),
+            decoder,
+            root,
+            current_path: Vec::new(),
+            definitions: Definitions::new(),
+            comments: decoder.saved_comments.is_some(),
+            pending_comments: Vec::new(),
+            value_path: Vec::new(),
+            skip_table: false,
+            items: None,
+            current_item_path: Vec::new(),
+            sources: None,
+        }
+    }
+
+    /// Returns true if the parser tracks the path of each value, for
+    /// comments or sources.
+    fn tracks_paths(&self) -> bool {
+        self.comments || self.sources.is_some()
+    }
+
+    /// Records the spans of the key and value at `path`.
+    fn locate(&mut self, path: Vec<String>, key: Option<Range<usize>>, value: Option<Range<usize>>) {
+        if let Some(sources) = &mut self.sources {
+            sources.push(Location { path, key, value });
+        }
+    }
+
+    /// Parses the statements of the document into `root` and returns the
+    /// errors found. Stops at the first error unless `recover` is set, in
+    /// which case decoding resumes after the offending statement.
+    fn statements(&mut self, recover: bool) -> Vec<TomlDecodeError> {
+        let mut errors = Vec::new();
+        loop {
+            let start = self.lexer.pos;
+            match self.statement() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    errors.push(err);
+                    if !recover {
+                        break;
+                    }
+                    self.lexer.skip_statement(start);
+                }
+            }
+        }
+        errors
+    }
+
+    /// Parses a table header, key/value pair, comment or blank line.
+    /// Returns false at the end of the document.
+    fn statement(&mut self) -> Result<bool, TomlDecodeError> {
+        let (token, span) = self.lexer.next(false)?;
+        match token {
+            Token::Eof => {
+                for comment in std::mem::take(&mut self.pending_comments) {
+                    self.decoder.preserve_comment(&[], &comment, CommentPlacement::Closing);
+                }
+                return Ok(false);
+            }
+            Token::Newline => {}
+            Token::Comment(comment) => {
+                if self.comments {
+                    self.pending_comments.push(comment.to_string());
+                }
+            }
+            Token::LeftBracket => {
+                let header = self.table_header(span.start);
+                self.skip_table = header.is_err();
+                let spans = header?;
+                let header = span.start..self.lexer.pos;
+                let trailing = self.line_end(ErrorKind::InvalidTableHeader, "Key group not on a line by itself.")?;
+                if self.items.is_some() || self.tracks_paths() {
+                    self.current_item_path = self.resolve_item_path(&spans);
+                }
+                if self.sources.is_some() {
+                    for i in 0..self.current_item_path.len() {
+                        let path = self.current_item_path[..=i].iter().map(|(part, _)| part.clone()).collect();
+                        match &self.current_item_path[i].1 {
+                            Some(key) => self.locate(path, Some(key.clone()), None),
+                            None => self.locate(path, None, Some(header.clone())),
+                        }
+                    }
+                }
+                if self.comments {
+                    let path: Vec<String> = self.current_item_path.iter().map(|(part, _)| part.clone()).collect();
+                    self.attach_comments(&path, trailing);
+                }
+                if self.items.is_some() {
+                    let path = self.current_item_path.clone();
+                    self.record(Item { path, value: None, lines: span.start..self.lexer.pos });
+                }
+            }
+            Token::BareKey(_) | Token::String { .. } => {
+                if self.tracks_paths() {
+                    self.value_path = self.current_item_path.iter().map(|(part, _)| part.clone()).collect();
+                }
+                let kv = self.key_value(token, span)?;
+                let trailing = self.line_end(ErrorKind::UnexpectedToken, "Found tokens after a value. Expected a newline or a comment.")?;
+                if !self.skip_table {
+                    if self.comments {
+                        let mut path = self.value_path.clone();
+                        path.extend(kv.path.iter().map(|part| part.to_string()));
+                        self.attach_comments(&path, trailing);
+                    }
+                    if self.items.is_some() {
+                        // The header spells out the table's parts, not this pair.
+                        let mut path: Vec<_> = self.current_item_path.iter().map(|(part, _)| (part.clone(), None)).collect();
+                        path.extend(kv.path.iter().map(|part| part.to_string()).zip(kv.spans.iter().cloned().map(Some)));
+                        self.record(Item { path, value: Some(kv.value_span.clone()), lines: kv.spans[0].start..self.lexer.pos });
+                    }
+                    self.insert(kv.path, &kv.spans, kv.value)?;
+                }
+            }
+            Token::Equals => return Err(self.lexer.error(ErrorKind::InvalidKey, "Found empty keyname. ".to_string(), span)),
+            _ => return Err(self.lexer.error(ErrorKind::UnexpectedToken, "Expected a key or a table header".to_string(), span)),
+        }
+        Ok(true)
+    }
+
+    /// Checks that nothing but a comment follows on the current line, and
+    /// returns the comment.
+    fn line_end(&mut self, kind: ErrorKind, msg: &str) -> Result<Option<&'a str>, TomlDecodeError> {
+        self.lexer.skip_whitespace();
+        let start = self.lexer.pos;
+        let rest = &self.lexer.src[start..];
+        if rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n") {
+            return Ok(None);
+        }
+        if rest.starts_with('#') {
+            return match self.lexer.next(false)? {
+                (Token::Comment(comment), _) => Ok(Some(comment)),
+                _ => Ok(None),
+            };
+        }
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        Err(self.lexer.error(kind, msg.to_string(), start..start + line.trim_end().len()))
+    }
+
+    /// Gives the item at `path` the comments waiting above it and the one
+    /// ending its line.
+    fn attach_comments(&mut self, path: &[String], trailing: Option<&str>) {
+        for comment in std::mem::take(&mut self.pending_comments) {
+            self.decoder.preserve_comment(path, &comment, CommentPlacement::Leading);
+        }
+        if let Some(comment) = trailing {
+            self.decoder.preserve_comment(path, comment, CommentPlacement::Trailing);
+        }
+    }
+
+    /// Records an item whose `lines` run from somewhere on its first line
+    /// to the end of its last, widening them to whole lines.
+    fn record(&mut self, mut item: Item) {
+        let src = self.lexer.src;
+        item.lines.start = src[..item.lines.start].rfind('\n').map_or(0, |nl| nl + 1);
+        let rest = &src[item.lines.end..];
+        if rest.starts_with("\r\n") {
+            item.lines.end += 2;
+        } else if rest.starts_with('\n') {
+            item.lines.end += 1;
+        }
+        if let Some(items) = &mut self.items {
+            items.push(item);
+        }
+    }
+
+    /// Returns the current table's path with the span of each header part,
+    /// indexing arrays of tables by their last element.
+    fn resolve_item_path(&self, spans: &[Range<usize>]) -> Vec<(String, Option<Range<usize>>)> {
+        let mut path = Vec::new();
+        let mut level = &self.root;
+        for (group, span) in self.current_path.iter().zip(spans) {
+            path.push((group.to_string(), Some(span.clone())));
+            let Some(value) = level.get(group) else {
+                break;
+            };
+            let table = match value.as_array() {
+                Some(a) => {
+                    path.push(((a.len() - 1).to_string(), None));
+                    a.last().and_then(V::as_table)
+                }
+                None => value.as_table(),
+            };
+            match table {
+                Some(table) => level = table,
+                None => break,
+            }
+        }
+        path
+    }
+
+    /// Parses `[a.b]` or `[[a.b]]`, whose opening bracket is at `start`,
+    /// and makes the table it names the current one. Returns the span of
+    /// each part of the name.
+    ///
+    /// A part naming an array of tables, at any depth, stands for its last
+    /// element, so sub-tables and nested arrays of tables attach to the
+    /// most recent `[[...]]`. A static array can't be appended to.
+    fn table_header(&mut self, start: usize) -> Result<Vec<Range<usize>>, TomlDecodeError> {
+        let Header { arrayoftables, groups, spans } = self.header_name(start)?;
+        let header = start..self.lexer.pos;
+        let name = &self.lexer.src[header.clone()];
+
+        let decoder = self.decoder;
+        self.current_path.clear();
+        for (i, group) in groups.iter().enumerate() {
+            let last = i == groups.len() - 1;
+            let currentlevel = match _get_level::<V>(&mut self.root, &self.current_path) {
+                Some(level) => level,
+                None => return Err(self.lexer.error(ErrorKind::DuplicateKey, format!("Key group {} is not a table", group), header)),
+            };
+            self.current_path.push(group.clone());
+            let defined = self.definitions.get(&self.current_path).cloned();
+            let Some(Definition { by, span }) = defined else {
+                let table = V::table(V::new_table(decoder, false));
+                let by = if !last {
+                    currentlevel.insert(group.clone(), table);
+                    DefinedBy::Implicit
+                } else if arrayoftables {
+                    currentlevel.insert(group.clone(), V::array(vec![table]));
+                    DefinedBy::ArrayOfTables
+                } else {
+                    currentlevel.insert(group.clone(), table);
+                    DefinedBy::Header
+                };
+                self.definitions.insert(self.current_path.clone(), Definition { by, span: header.clone() });
+                continue;
+            };
+            let msg = match (by, last, arrayoftables) {
+                (DefinedBy::Header | DefinedBy::Implicit | DefinedBy::Dotted | DefinedBy::ArrayOfTables, false, _) => continue,
+                (DefinedBy::Implicit, true, false) => {
+                    self.definitions.insert(self.current_path.clone(), Definition { by: DefinedBy::Header, span: header.clone() });
+                    continue;
+                }
+                (DefinedBy::ArrayOfTables, true, true) => {
+                    if let Some(a) = currentlevel.get_mut(group).and_then(V::as_array_mut) {
+                        a.push(V::table(V::new_table(decoder, false)));
+                    }
+                    // What was defined in the previous element is out of reach.
+                    self.definitions.forget_below(&self.current_path);
+                    continue;
+                }
+                (_, false, _) => format!("Table {} can't add to {}, which is {}", name, _dump_path(&self.current_path), by.what()),
+                (_, true, false) => format!("Table {} can't redefine {}, which is {}", name, _dump_path(&self.current_path), by.what()),
+                (_, true, true) => format!("Array of tables {} can't append to {}, which is {}", name, _dump_path(&self.current_path), by.what()),
+            };
+            let kind = if by.is_value() { ErrorKind::DuplicateKey } else { ErrorKind::DuplicateTable };
+            return Err(self.lexer.error(kind, msg, header).with_original(self.lexer.span(span)));
+        }
+        Ok(spans)
+    }
+
+    /// Parses the rest of a `[a.b]` or `[[a.b]]` header whose opening
+    /// bracket is at `start`.
+    fn header_name(&mut self, start: usize) -> Result<Header<'a>, TomlDecodeError> {
+        let arrayoftables = self.lexer.eat(b'[');
+        let (token, span) = self.lexer.next(false)?;
+        match token {
+            Token::Newline | Token::Comment(_) | Token::Eof => {
+                let msg = "Opening key group bracket on line by itself.".to_string();
+                return Err(self.lexer.error(ErrorKind::InvalidTableHeader, msg, start..span.start));
+            }
+            Token::RightBracket => {
+                let msg = "Can't have a keygroup with an empty name".to_string();
+                return Err(self.lexer.error(ErrorKind::InvalidTableHeader, msg, start..span.end));
+            }
+            _ => {}
+        }
+        let (groups, spans) = self.key(token, span)?;
+        let (token, span) = self.lexer.next(false)?;
+        if token != Token::RightBracket || (arrayoftables && !self.lexer.eat(b']')) {
+            let msg = format!("Expected '{}' at the end of the key group", if arrayoftables { "]]" } else { "]" });
+            return Err(self.lexer.error(ErrorKind::InvalidTableHeader, msg, span));
+        }
+        Ok(Header { arrayoftables, groups, spans })
+    }
+
+    /// Parses a possibly dotted key starting with `token`, returning its
+    /// parts and the span of each.
+    fn key(&mut self, token: Token<'a>, span: Range<usize>) -> Result<KeyParts<'a>, TomlDecodeError> {
+        let mut path = vec![self.simple_key(token, span.clone())?];
+        let mut spans = vec![span];
+        loop {
+            self.lexer.skip_whitespace();
+            if !self.lexer.eat(b'.') {
+                break;
+            }
+            let (token, span) = self.lexer.next(false)?;
+            path.push(self.simple_key(token, span.clone())?);
+            spans.push(span);
+        }
+        Ok((path, spans))
+    }
+
+    fn simple_key(&mut self, token: Token<'a>, span: Range<usize>) -> Result<Cow<'a, str>, TomlDecodeError> {
+        match token {
+            Token::BareKey(key) => Ok(Cow::Borrowed(key)),
+            Token::String { value, multiline: false } => Ok(value),
+            Token::String { .. } => Err(self.lexer.error(ErrorKind::InvalidKey, "Multi-line strings can't be used as keys".to_string(), span)),
+            _ => Err(self.lexer.error(ErrorKind::InvalidKey, "Expected a key name".to_string(), span)),
+        }
+    }
+
+    /// Parses `key = value` starting with the key's first token.
+    fn key_value(&mut self, token: Token<'a>, span: Range<usize>) -> Result<KeyValue<'a, V>, TomlDecodeError> {
+        let (path, spans) = self.key(token, span)?;
+        let (token, span) = self.lexer.next(false)?;
+        match token {
+            Token::Equals => {}
+            Token::Newline | Token::Comment(_) | Token::Eof => {
+                let msg = "Key name found without value. Reached end of line.".to_string();
+                return Err(self.lexer.error(ErrorKind::KeyWithoutValue, msg, spans[0].start..spans[spans.len() - 1].end));
+            }
+            _ => return Err(self.lexer.error(ErrorKind::InvalidKey, "Expected '=' after the key name".to_string(), span)),
+        }
+        self.lexer.skip_whitespace();
+        let start = self.lexer.pos;
+        let len = self.value_path.len();
+        if self.tracks_paths() {
+            self.value_path.extend(path.iter().map(|part| part.to_string()));
+        }
+        let value = self.value();
+        if self.sources.is_some() {
+            for (i, span) in spans.iter().enumerate() {
+                let value = if i == spans.len() - 1 { Some(start..self.lexer.pos) } else { None };
+                self.locate(self.value_path[..len + i + 1].to_vec(), Some(span.clone()), value);
+            }
+        }
+        self.value_path.truncate(len);
+        let value = value?;
+        Ok(KeyValue {
+            path,
+            spans,
+            value,
+            value_span: start..self.lexer.pos,
+        })
+    }
+
+    /// Assigns a parsed key/value pair in the current table.
+    fn insert(&mut self, path: Vec<Cow<'a, str>>, spans: &[Range<usize>], value: V) -> Result<(), TomlDecodeError> {
+        let key_span = spans[0].start..spans[spans.len() - 1].end;
+        let currentlevel = match _get_level::<V>(&mut self.root, &self.current_path) {
+            Some(level) => level,
+            None => return Err(self.lexer.error(ErrorKind::DuplicateKey, "Key group is not a table".to_string(), key_span)),
+        };
+        let decoder = self.decoder;
+        let scope = Scope {
+            definitions: &mut self.definitions,
+            base: &self.current_path,
+            spans,
+        };
+        _insert_key(currentlevel, &path, value, || V::new_table(decoder, false), Some(scope)).map_err(|e| e.at(&self.lexer, key_span))
+    }
+
+    fn value(&mut self) -> Result<V, TomlDecodeError> {
+        let (token, span) = self.lexer.next(true)?;
+        self.value_from(token, span)
+    }
+
+    fn value_from(&mut self, token: Token<'a>, span: Range<usize>) -> Result<V, TomlDecodeError> {
+        match token {
+            Token::String { value, .. } => Ok(V::string(value)),
+            Token::Scalar(value) => Ok(V::scalar(value)),
+            Token::LeftBracket => self.array(span).map(V::array),
+            Token::LeftBrace => self.inline_table(span).map(V::table),
+            Token::Newline | Token::Comment(_) | Token::Eof => {
+                Err(self.lexer.error(ErrorKind::InvalidValue, "Empty value is invalid".to_string(), span))
+            }
+            _ => Err(self.lexer.error(ErrorKind::InvalidValue, "Expected a value".to_string(), span)),
+        }
+    }
+
+    /// Parses the rest of an array whose `[` is at `open`.
+    fn array(&mut self, open: Range<usize>) -> Result<Vec<V>, TomlDecodeError> {
+        let mut items = Vec::new();
+        let mut comments = Vec::new();
+        let mut pending = Vec::new();
+        loop {
+            let (token, span) = self.next_in_array(&mut comments)?;
+            self.array_comments(&mut comments, &mut pending, items.len());
+            let placement = if token == Token::RightBracket { CommentPlacement::Closing } else { CommentPlacement::Leading };
+            let mut path = self.value_path.clone();
+            if placement == CommentPlacement::Leading {
+                path.push(items.len().to_string());
+            }
+            for comment in pending.drain(..) {
+                self.decoder.preserve_comment(&path, &comment, placement);
+            }
+            match token {
+                Token::RightBracket => return Ok(items),
+                Token::Eof => return Err(self.lexer.error(ErrorKind::InvalidArray, "Unterminated array".to_string(), open.start..span.end)),
+                token => {
+                    if self.tracks_paths() {
+                        self.value_path.push(items.len().to_string());
+                    }
+                    let start = span.start;
+                    let value = self.value_from(token, span);
+                    if self.tracks_paths() {
+                        self.locate(self.value_path.clone(), None, Some(start..self.lexer.pos));
+                        self.value_path.pop();
+                    }
+                    items.push(value?);
+                }
+            }
+            let (token, span) = self.next_in_array(&mut comments)?;
+            self.array_comments(&mut comments, &mut pending, items.len());
+            match token {
+                Token::Comma => {}
+                Token::RightBracket => {
+                    for comment in pending.drain(..) {
+                        self.decoder.preserve_comment(&self.value_path, &comment, CommentPlacement::Closing);
+                    }
+                    return Ok(items);
+                }
+                Token::Eof => return Err(self.lexer.error(ErrorKind::InvalidArray, "Unterminated array".to_string(), open.start..span.end)),
+                _ => return Err(self.lexer.error(ErrorKind::InvalidArray, "Expected ',' or ']' after an array element".to_string(), span)),
+            }
+        }
+    }
+
+    /// Returns the next token in an array, skipping newlines and comments.
+    /// When keeping comments, the skipped ones are added to `comments`,
+    /// each with whether a newline came before it.
+    fn next_in_array(&mut self, comments: &mut Vec<(&'a str, bool)>) -> Result<(Token<'a>, Range<usize>), TomlDecodeError> {
+        let mut beginline = false;
+        loop {
+            let (token, span) = self.lexer.next(true)?;
+            match token {
+                Token::Newline => beginline = true,
+                Token::Comment(comment) if self.comments => comments.push((comment, beginline)),
+                Token::Comment(_) => {}
+                _ => return Ok((token, span)),
+            }
+        }
+    }
+
+    /// Attaches the comments found after the first `items` elements of the
+    /// array at `value_path`: one on the line of the last element trails
+    /// it, and the others wait in `pending` for the next element or the
+    /// closing bracket.
+    fn array_comments(&mut self, comments: &mut Vec<(&'a str, bool)>, pending: &mut Vec<String>, items: usize) {
+        for (comment, beginline) in comments.drain(..) {
+            if beginline || items == 0 {
+                pending.push(comment.to_string());
+            } else {
+                let mut path = self.value_path.clone();
+                path.push((items - 1).to_string());
+                self.decoder.preserve_comment(&path, comment, CommentPlacement::Trailing);
+            }
+        }
+    }
+
+    /// Parses the rest of an inline table whose `{` is at `open`.
+    fn inline_table(&mut self, open: Range<usize>) -> Result<V::Table, TomlDecodeError> {
+        let mut table = V::new_table(self.decoder, true);
+        let mut definitions = Definitions::new();
+        let (mut token, mut span) = self.lexer.next(false)?;
+        if token == Token::RightBrace {
+            return Ok(table);
+        }
+        loop {
+            match token {
+                Token::Newline | Token::Comment(_) => {
+                    let msg = "Line breaks are not allowed in inline objects".to_string();
+                    return Err(self.lexer.error(ErrorKind::InvalidInlineTable, msg, span));
+                }
+                Token::Eof => {
+                    let msg = "Unterminated inline table".to_string();
+                    return Err(self.lexer.error(ErrorKind::InvalidInlineTable, msg, open.start..span.end));
+                }
+                _ => {}
+            }
+            let kv = self.key_value(token, span)?;
+            let key_span = kv.key_span();
+            let decoder = self.decoder;
+            let scope = Scope {
+                definitions: &mut definitions,
+                base: &[],
+                spans: &kv.spans,
+            };
+            _insert_key(&mut table, &kv.path, kv.value, || V::new_table(decoder, true), Some(scope)).map_err(|e| e.at(&self.lexer, key_span))?;
+            let (next, next_span) = self.lexer.next(false)?;
+            match next {
+                Token::RightBrace => return Ok(table),
+                Token::Comma => {
+                    (token, span) = self.lexer.next(false)?;
+                    if token == Token::RightBrace {
+                        let msg = "Trailing commas are not allowed in inline tables".to_string();
+                        return Err(self.lexer.error(ErrorKind::InvalidInlineTable, msg, next_span));
+                    }
+                }
+                Token::Newline | Token::Comment(_) => {
+                    let msg = "Line breaks are not allowed in inline objects".to_string();
+                    return Err(self.lexer.error(ErrorKind::InvalidInlineTable, msg, next_span));
+                }
+                Token::Eof => {
+                    let msg = "Unterminated inline table".to_string();
+                    return Err(self.lexer.error(ErrorKind::InvalidInlineTable, msg, open.start..next_span.end));
+                }
+                _ => {
+                    let msg = "Expected ',' or '}' in inline table".to_string();
+                    return Err(self.lexer.error(ErrorKind::InvalidInlineTable, msg, next_span));
+                }
+            }
+        }
+    }
+
+    /// Checks that only whitespace is left, for the single-value entry
+    /// points on `TomlDecoder`.
+    fn end_of_input(&mut self) -> Result<(), TomlDecodeError> {
+        self.lexer.skip_whitespace();
+        let start = self.lexer.pos;
+        if self.lexer.src[start..].trim().is_empty() {
+            return Ok(());
+        }
+        let msg = "Found tokens after a value. Expected a newline or a comment.".to_string();
+        Err(self.lexer.error(ErrorKind::UnexpectedToken, msg, start..self.lexer.src.len()))
+    }
+}
+
+/// How a table or key was defined, which decides what may redefine or
+/// add to it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DefinedBy {
+    /// Named by a `[table]` header.
+    Header,
+    /// Implied by a header below it, as `[a.b]` implies `a`. It may still
+    /// get a header of its own.
+    Implicit,
+    /// Implied by a dotted key such as `a.b = 1`. Only other dotted keys in
+    /// the same table may add to it, and headers may add sub-tables.
+    Dotted,
+    /// An `[[array]]` of tables, whose last element later headers add to.
+    ArrayOfTables,
+    /// A key holding an inline table, which is closed.
+    Inline,
+    /// A key holding an array.
+    Array,
+    /// A key holding any other value.
+    Value,
+}
+
+impl DefinedBy {
+    /// Describes the definition, to follow "which is".
+    fn what(self) -> &'static str {
+        match self {
+            DefinedBy::Header => "defined by a header",
+            DefinedBy::Implicit => "implied by a header",
+            DefinedBy::Dotted => "defined by dotted keys",
+            DefinedBy::ArrayOfTables => "an array of tables",
+            DefinedBy::Inline => "an inline table",
+            DefinedBy::Array => "a static array",
+            DefinedBy::Value => "not a table",
+        }
+    }
+
+    /// Returns true for a key/value pair rather than a table.
+    fn is_value(self) -> bool {
+        matches!(self, DefinedBy::Inline | DefinedBy::Array | DefinedBy::Value)
+    }
+}
+
+/// How a table or key was defined, and where.
+#[derive(Debug, Clone)]
+struct Definition {
+    by: DefinedBy,
+    /// The header, or the key, that first defined it.
+    span: Range<usize>,
+}
+
+/// The definition of every table and key by its full path. Inside arrays
+/// of tables the path leaves out the index, since only the last element
+/// can be added to.
+#[derive(Debug, Default)]
+struct Definitions<'a> {
+    paths: BTreeMap<Vec<Cow<'a, str>>, Definition>,
+}
+
+impl<'a> Definitions<'a> {
+    fn new() -> Definitions<'a> {
+        Definitions::default()
+    }
+
+    fn get(&self, path: &[Cow<'a, str>]) -> Option<&Definition> {
+        self.paths.get(path)
+    }
+
+    fn insert(&mut self, path: Vec<Cow<'a, str>>, definition: Definition) {
+        self.paths.insert(path, definition);
+    }
+
+    /// Forgets the definitions strictly below `path`, when an array of
+    /// tables gets a new element.
+    fn forget_below(&mut self, path: &[Cow<'a, str>]) {
+        let below: Vec<_> = self
+            .paths
+            .range::<[Cow<'a, str>], _>((std::ops::Bound::Excluded(path), std::ops::Bound::Unbounded))
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(path))
+            .cloned()
+            .collect();
+        for p in below {
+            self.paths.remove(&p);
+        }
+    }
+}
+
+/// The definitions a key/value pair is checked against and recorded in,
+/// and where it is.
+struct Scope<'s, 'a> {
+    definitions: &'s mut Definitions<'a>,
+    /// The path of the table the pair is in.
+    base: &'s [Cow<'a, str>],
+    /// The span of each part of the key.
+    spans: &'s [Range<usize>],
+}
+
+/// Why a key/value pair could not be assigned.
+struct Conflict {
+    kind: ErrorKind,
+    msg: String,
+    /// The first definition of what the pair redefines, if known.
+    original: Option<Range<usize>>,
+}
+
+impl Conflict {
+    fn new(msg: String) -> Conflict {
+        Conflict {
+            kind: ErrorKind::DuplicateKey,
+            msg,
+            original: None,
+        }
+    }
+
+    /// Turns the conflict into an error at `span` of the lexer's source.
+    fn at(self, lexer: &Lexer<'_>, span: Range<usize>) -> TomlDecodeError {
+        let err = lexer.error(self.kind, self.msg, span);
+        match self.original {
+            Some(original) => err.with_original(lexer.span(original)),
+            None => err,
+        }
+    }
+}
+
+/// Renders a key path as it would be written in TOML.
+fn _dump_path(path: &[Cow<'_, str>]) -> String {
+    path.iter().map(|part| _dump_key(part)).collect::<Vec<_>>().join(".")
+}
+
+/// Assigns `value` to the possibly dotted key `path` in `table`, creating
+/// the tables a dotted key implies with `new_table`.
+///
+/// With a `scope`, the key is checked against the TOML redefinition rules
+/// and recorded: a dotted key may only go through tables that dotted keys
+/// implied, not through an inline table or a table that has or is
+/// implied by a header. Without one the history of `table` is unknown and
+/// any table in it but an inline one may be added to.
+fn _insert_key<'a, V: Node<'a>>(
+    table: &mut V::Table,
+    path: &[Cow<'a, str>],
+    value: V,
+    new_table: impl Fn() -> V::Table,
+    mut scope: Option<Scope<'_, 'a>>,
+) -> Result<(), Conflict> {
+    let (key, parents) = path.split_last().expect("a key has at least one part");
+    let mut full: Vec<Cow<'a, str>> = scope.as_ref().map_or_else(Vec::new, |scope| scope.base.to_vec());
+    let mut level = table;
+    for (i, parent) in parents.iter().enumerate() {
+        full.push(parent.clone());
+        if let Some(scope) = &mut scope {
+            match scope.definitions.get(&full) {
+                None => {
+                    let span = scope.spans[0].start..scope.spans[i].end;
+                    scope.definitions.insert(full.clone(), Definition { by: DefinedBy::Dotted, span });
+                }
+                Some(Definition { by: DefinedBy::Dotted, .. }) => {}
+                Some(Definition { by, span }) => {
+                    let msg = format!("Dotted key {} can't add to {}, which is {}", _dump_path(path), _dump_path(&full), by.what());
+                    return Err(Conflict { original: Some(span.clone()), ..Conflict::new(msg) });
+                }
+            }
+        }
+        level = match level.get_or_insert_with(parent.clone(), || V::table(new_table())).as_table_mut() {
+            Some(table) if table.is_inline() && scope.is_none() => {
+                let msg = format!("Dotted key {} can't add to {}, which is an inline table", _dump_path(path), _dump_path(&path[..=i]));
+                return Err(Conflict::new(msg));
+            }
+            Some(table) => table,
+            None => return Err(Conflict::new(format!("Key {} is not a table", _dump_path(&path[..=i])))),
+        };
+    }
+    full.push(key.clone());
+    if let Some(scope) = &mut scope {
+        if let Some(Definition { span, .. }) = scope.definitions.get(&full) {
+            return Err(Conflict { original: Some(span.clone()), ..Conflict::new(format!("Duplicate key {}", _dump_path(path))) });
+        }
+        let by = if value.as_table().is_some() {
+            DefinedBy::Inline
+        } else if value.as_array().is_some() {
+            DefinedBy::Array
+        } else {
+            DefinedBy::Value
+        };
+        let span = scope.spans[0].start..scope.spans[scope.spans.len() - 1].end;
+        scope.definitions.insert(full, Definition { by, span });
+    }
+    if level.get(key).is_some() {
+        return Err(Conflict::new(format!("Duplicate key {}", _dump_path(path))));
+    }
+    level.insert(key.clone(), value);
+    Ok(())
+}
+
+/// Decodes a bare value: a boolean, number, date, time or datetime.
+fn _load_scalar(v: &str) -> Result<Value, (ErrorKind, String)> {
+    match v {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        "inf" | "+inf" => return Ok(Value::Float(f64::INFINITY)),
+        "-inf" => return Ok(Value::Float(f64::NEG_INFINITY)),
+        "nan" | "+nan" => return Ok(Value::Float(f64::NAN)),
+        "-nan" => return Ok(Value::Float(-f64::NAN)),
+        _ => {}
+    }
+    if v.eq_ignore_ascii_case("true") || v.eq_ignore_ascii_case("false") {
+        return Err((ErrorKind::InvalidValue, "Only all lowercase booleans allowed".to_string()));
+    }
+    let b = v.as_bytes();
+    if b.len() >= 10 && b[4] == b'-' && b[7] == b'-' {
+        return _load_date(v)
+            .map(Value::Datetime)
+            .ok_or_else(|| (ErrorKind::InvalidDatetime, format!("Invalid date or datetime: {}", v)));
+    }
+    if b.len() >= 3 && b[2] == b':' {
+        return _load_local_time(v)
+            .map(Value::Datetime)
+            .ok_or_else(|| (ErrorKind::InvalidDatetime, format!("Invalid time: {}", v)));
+    }
+    _load_number(v)
+}
+
+/// Decodes a decimal, hexadecimal, octal or binary integer, or a float.
+fn _load_number(v: &str) -> Result<Value, (ErrorKind, String)> {
+    let digits = v.replace('_', "");
+    if DEC_INT_RE.is_match(v) {
+        return digits
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| (ErrorKind::InvalidNumber, format!("Integer out of range: {}", v)));
+    }
+    if PREFIXED_INT_RE.is_match(v) {
+        let radix = match v.as_bytes()[1] {
+            b'x' => 16,
+            b'o' => 8,
+            _ => 2,
+        };
+        return i64::from_str_radix(&digits[2..], radix)
+            .map(Value::Integer)
+            .map_err(|_| (ErrorKind::InvalidNumber, format!("Integer out of range: {}", v)));
+    }
+    if FLOAT_RE.is_match(v) {
+        return match digits.parse::<f64>() {
+            Ok(f) if f.is_finite() => Ok(Value::Float(f)),
+            Ok(_) => Err((ErrorKind::InvalidNumber, format!("Float out of range: {}", v))),
+            Err(_) => Err((ErrorKind::InvalidNumber, format!("Invalid float: {}", v))),
+        };
+    }
+    if v.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-' || c == '.') {
+        Err((ErrorKind::InvalidNumber, format!("Invalid number: {}", v)))
+    } else {
+        Err((ErrorKind::InvalidValue, format!("Invalid value: {}", v)))
+    }
+}
+
+/// Parses a local time, keeping nanosecond precision and the number of
+/// fraction digits written.
+fn _load_local_time(v: &str) -> Option<Datetime> {
+    let captures = TIME_RE.captures(v)?;
+    let nano = captures.get(5).map_or(Some(0), |frac| _load_fraction(frac.as_str()))?;
+    let t = _load_hms(captures[1].parse().ok()?, captures[2].parse().ok()?, captures[3].parse().ok()?, nano)?;
+    let format = DatetimeFormat {
+        fraction_digits: _fraction_digits(captures.get(5)),
+        ..DatetimeFormat::default()
+    };
+    Some(Datetime::LocalTime(t, format))
+}
+
+/// Builds a time of day, accepting `60` seconds as a leap second, which
+/// chrono stores as second 59 with a nanosecond count past one second.
+fn _load_hms(hour: u32, min: u32, sec: u32, nano: u32) -> Option<NaiveTime> {
+    match sec {
+        60 => NaiveTime::from_hms_nano_opt(hour, min, 59, nano + 1_000_000_000),
+        _ => NaiveTime::from_hms_nano_opt(hour, min, sec, nano),
+    }
+}
+
+/// Converts the digits of a fractional second to nanoseconds, truncating
+/// any further digits.
+fn _load_fraction(frac: &str) -> Option<u32> {
+    let frac = &frac[..frac.len().min(9)];
+    Some(frac.parse::<u32>().ok()? * 10_u32.pow(9 - frac.len() as u32))
+}
+
+/// Returns how many digits of a fractional second were written, up to
+/// the 9 that are kept.
+fn _fraction_digits(frac: Option<regex::Match<'_>>) -> Option<u8> {
+    Some(frac.map_or(0, |frac| frac.len().min(9) as u8))
+}
+
+/// Parses a local date, local datetime or offset datetime.
+pub fn _load_date(val: &str) -> Option<Datetime> {
+    let captures = DATETIME_RE.captures(val)?;
+    let d = NaiveDate::from_ymd_opt(captures[1].parse().ok()?, captures[2].parse().ok()?, captures[3].parse().ok()?)?;
+    if captures.get(4).is_none() {
+        return Some(Datetime::LocalDate(d));
+    }
+    let nanosecond = captures.get(9).map_or(Some(0), |frac| _load_fraction(frac.as_str()))?;
+    let t = _load_hms(captures[5].parse().ok()?, captures[6].parse().ok()?, captures[7].parse().ok()?, nanosecond)?;
+    let dt = NaiveDateTime::new(d, t);
+    let format = DatetimeFormat {
+        separator: captures[4].chars().next()?,
+        fraction_digits: _fraction_digits(captures.get(9)),
+    };
+    match captures.get(10) {
+        Some(tzval) => {
+            let tz = TomlTz::new(tzval.as_str()).ok()?;
+            Some(Datetime::OffsetDateTime(dt.and_local_timezone(tz).single()?, format))
+        }
+        None => Some(Datetime::LocalDateTime(dt, format)),
+    }
+}
+
+/// Returns true for the control characters TOML forbids in comments and
+/// strings: everything below U+0020 except tab, and DEL.
+fn _is_control(c: char) -> bool {
+    (c < ' ' && c != '\t') || c == '\u{7f}'
+}
+
+/// A decoder for TOML data.
+#[derive(Debug)]
+pub struct TomlDecoder {
+    _dict: fn() -> Table,
+    /// The comments of the last document decoded, keyed by path, when
+    /// keeping comments.
+    #[allow(deprecated)]
+    saved_comments: Option<Mutex<HashMap<Vec<String>, CommentValue>>>,
+}
+
+impl Clone for TomlDecoder {
+    fn clone(&self) -> Self {
+        TomlDecoder {
+            _dict: self._dict,
+            saved_comments: self.saved_comments.as_ref().map(|saved| Mutex::new(_lock(saved).clone())),
+        }
+    }
+}
+
+impl Default for TomlDecoder {
+    fn default() -> Self {
+        TomlDecoder::new(None)
+    }
+}
+
+impl TomlDecoder {
+    /// Creates a new TomlDecoder with the specified table factory.
+    pub fn new(_dict: Option<fn() -> Table>) -> TomlDecoder {
+        TomlDecoder {
+            _dict: _dict.unwrap_or(Table::new),
+            saved_comments: None,
+        }
+    }
+
+    /// Returns an empty table.
+    pub fn get_empty_table(&self) -> Table {
+        (self._dict)()
+    }
+
+    /// Returns an empty table for an inline table: a table from the table
+    /// factory, marked as inline.
+    pub fn get_empty_inline_table(&self) -> Table {
+        let mut table = self.get_empty_table();
+        table.set_inline(true);
+        table
+    }
+
+    /// Loads an inline object such as `{ a = 1, b = 2 }` into the current
+    /// level.
+    pub fn load_inline_object(&self, line: &str, currentlevel: &mut Table) -> Result<(), TomlDecodeError> {
+        let table = match self.load_value(line)? {
+            Value::Table(table) => table,
+            _ => {
+                let msg = "Invalid inline table encountered".to_string();
+                return Err(TomlDecodeError::new(ErrorKind::InvalidInlineTable, msg, line.to_string(), 0..line.len()));
+            }
+        };
+        for (key, value) in table {
+            _insert_key(currentlevel, &[Cow::Owned(key)], value, || self.get_empty_table(), None)
+                .map_err(|e| TomlDecodeError::new(e.kind, e.msg, line.to_string(), 0..line.len()))?;
+        }
+        Ok(())
+    }
+
+    /// Loads a `key = value` line into the current level.
+    ///
+    /// A dotted key such as `a.b.c = 1` assigns `c` in the table `a.b`,
+    /// creating `a` and `a.b` as needed; quoted parts may contain dots.
+    /// It is an error for a part to name a value that is not a table, or
+    /// an inline table. A single line can't tell how the other tables
+    /// already in `currentlevel` were defined, so any of them may be added
+    /// to.
+    pub fn load_line(&self, line: &str, currentlevel: &mut Table) -> Result<(), TomlDecodeError> {
+        let mut parser = Parser::new(line, self);
+        let (token, span) = parser.lexer.next(false)?;
+        let kv = parser.key_value(token, span)?;
+        parser.line_end(ErrorKind::UnexpectedToken, "Found tokens after a value. Expected a newline or a comment.")?;
+        let key_span = kv.key_span();
+        _insert_key(currentlevel, &kv.path, kv.value, || self.get_empty_table(), None).map_err(|e| e.at(&parser.lexer, key_span))
+    }
+
+    /// Loads a value from a string.
+    pub fn load_value(&self, v: &str) -> Result<Value, TomlDecodeError> {
+        let mut parser = Parser::new(v, self);
+        let value = parser.value()?;
+        parser.end_of_input()?;
+        Ok(value)
+    }
+
+    /// Loads an array from a string.
+    pub fn load_array(&self, a: &str) -> Result<Vec<Value>, TomlDecodeError> {
+        match self.load_value(a)? {
+            Value::Array(array) => Ok(array),
+            _ => Err(TomlDecodeError::new(ErrorKind::InvalidArray, "Expected an array".to_string(), a.to_string(), 0..a.len())),
+        }
+    }
+
+    /// Preserves a comment belonging to the key, table header or array
+    /// element at `path`, if this decoder keeps comments.
+    #[allow(deprecated)]
+    pub fn preserve_comment(&self, path: &[String], comment: &str, placement: CommentPlacement) {
+        if let Some(saved) = &self.saved_comments {
+            _lock(saved)
+                .entry(path.to_vec())
+                .or_insert_with(|| CommentValue {
+                    val: Value::Table(Table::new()),
+                    leading: Vec::new(),
+                    trailing: None,
+                    closing: Vec::new(),
+                })
+                .add_comment(comment, placement);
+        }
+    }
+
+    /// Embeds the decoded values into the preserved comments, dropping the
+    /// comments of anything that was not decoded.
+    #[allow(deprecated)]
+    pub fn embed_comments(&self, root: &Table) {
+        if let Some(saved) = &self.saved_comments {
+            _lock(saved).retain(|path, cv| {
+                let val = match path.split_first() {
+                    None => Some(Value::Table(root.clone())),
+                    Some((first, rest)) => {
+                        let rest: Vec<&str> = rest.iter().map(String::as_str).collect();
+                        root.get(first).and_then(|v| _lookup(v, &rest)).cloned()
+                    }
+                };
+                val.map(|val| cv.val = val).is_some()
+            });
+        }
+    }
+
+    fn clear_comments(&self) {
+        if let Some(saved) = &self.saved_comments {
+            _lock(saved).clear();
+        }
+    }
+}
+
+/// Locks a mutex, recovering the data if another thread panicked with it.
+fn _lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// A decoder for TOML files that preserves comments.
+///
+/// Comments are attached to the key, table header or array element they
+/// sit above or at the end of the line of. After `loads`, `comments`
+/// returns them, and a `TomlPreserveCommentEncoder` writes them back.
+///
+/// Dereferences to the wrapped `TomlDecoder` for everything but comment
+/// handling. Superseded by `Document`.
+#[deprecated(note = "use `Document`, which keeps comments along with the rest of the formatting")]
+#[derive(Debug, Clone)]
+pub struct TomlPreserveCommentDecoder {
+    decoder: TomlDecoder,
+}
+
+#[allow(deprecated)]
+impl TomlPreserveCommentDecoder {
+    /// Creates a new instance of `TomlPreserveCommentDecoder`.
+    pub fn new(_dict: Option<fn() -> Table>) -> TomlPreserveCommentDecoder {
+        TomlPreserveCommentDecoder {
+            decoder: TomlDecoder {
+                saved_comments: Some(Mutex::new(HashMap::new())),
+                ..TomlDecoder::new(_dict)
+            },
+        }
+    }
+
+    /// Returns the comments of the last document decoded, keyed by the
+    /// path of what they belong to. Arrays and arrays of tables are indexed
+    /// by position, as in `["fruit", "0"]`, and the comments at the end of
+    /// the document belong to the empty path.
+    pub fn comments(&self) -> HashMap<Vec<String>, CommentValue> {
+        self.decoder.saved_comments.as_ref().map(|saved| _lock(saved).clone()).unwrap_or_default()
+    }
+}
+
+#[allow(deprecated)]
+impl std::ops::Deref for TomlPreserveCommentDecoder {
+    type Target = TomlDecoder;
+
+    fn deref(&self) -> &TomlDecoder {
+        &self.decoder
+    }
+}
+
+/// Error returned when a `Document` edit cannot be made. The document is
+/// left as it was.
+#[derive(Debug)]
+pub enum DocumentError {
+    /// Nothing exists at the path.
+    NotFound(String),
+    /// A key on the path holds a value that is not a table.
+    NotATable(String),
+    /// `rename` would replace an existing key.
+    KeyExists(String),
+    /// The edit would have made the document invalid.
+    Invalid(TomlDecodeError),
+}
+
+impl std::fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DocumentError::NotFound(path) => write!(f, "No key or table at {}", path),
+            DocumentError::NotATable(path) => write!(f, "{} is not a table", path),
+            DocumentError::KeyExists(path) => write!(f, "{} already exists", path),
+            DocumentError::Invalid(e) => write!(f, "The edit would make the document invalid: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DocumentError::Invalid(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A TOML document that keeps its text as written.
+///
+/// Where `loads` keeps only the values, a `Document` also keeps comments,
+/// blank lines, key order, quoting, number formats and line endings. Each
+/// edit rewrites only the spans it touches, so writing the document back
+/// reproduces the original text apart from the edits. It replaces the
+/// deprecated `TomlPreserveCommentDecoder` and `TomlPreserveCommentEncoder`.
+///
+/// Paths are lists of keys, so that keys containing `.` can be named.
+/// Arrays, including arrays of tables, are indexed by position, as in
+/// `["fruit", "0", "name"]`.
+#[derive(Debug, Clone)]
+pub struct Document {
+    src: String,
+    root: Table,
+    items: Vec<Item>,
+}
+
+impl Document {
+    /// Parses a document.
+    pub fn parse(s: &str) -> Result<Document, TomlDecodeError> {
+        let decoder = TomlDecoder::new(Some(Table::ordered));
+        let mut parser = Parser::new(s, &decoder);
+        parser.items = Some(Vec::new());
+        if let Some(err) = parser.document(false).into_iter().next() {
+            return Err(err);
+        }
+        Ok(Document {
+            src: s.to_string(),
+            items: parser.items.take().unwrap_or_default(),
+            root: parser.root,
+        })
+    }
+
+    /// Returns the decoded contents of the document.
+    pub fn as_table(&self) -> &Table {
+        &self.root
+    }
+
+    /// Returns the value at `path`.
+    pub fn get(&self, path: &[&str]) -> Option<&Value> {
+        let (first, rest) = path.split_first()?;
+        _lookup(self.root.get(first)?, rest)
+    }
+
+    /// Sets the value at `path`, returning the value it replaces.
+    ///
+    /// An existing key keeps its place and only its value is rewritten. A
+    /// new key goes after the last key of its table, and a new table is
+    /// added as a section at the end of the document.
+    pub fn insert(&mut self, path: &[&str], value: Value) -> Result<Option<Value>, DocumentError> {
+        let (key, parent) = path.split_last().ok_or_else(|| DocumentError::NotFound(String::new()))?;
+        if let Some(item) = self.owner(path) {
+            if item.path.len() == path.len() {
+                let old = self.get(path).cloned();
+                let span = item.value.clone().expect("a key/value pair has a value");
+                self.splice(vec![(span, TomlEncoder::default().dump_value(&value))])?;
+                return Ok(old);
+            }
+            let inner = &path[item.path.len()..];
+            return self.edit_value(&item, |v| {
+                let (key, parent) = inner.split_last().expect("the path goes into the value");
+                match _lookup_mut(v, parent) {
+                    Some(Value::Table(t)) => Ok(t.insert(key.to_string(), value)),
+                    Some(Value::Array(a)) => match key.parse::<usize>().ok().and_then(|i| a.get_mut(i)) {
+                        Some(slot) => Ok(Some(std::mem::replace(slot, value))),
+                        None => Err(DocumentError::NotFound(_display_path(path))),
+                    },
+                    Some(_) => Err(DocumentError::NotATable(_display_path(&path[..path.len() - 1]))),
+                    None => Err(DocumentError::NotFound(_display_path(&path[..path.len() - 1]))),
+                }
+            });
+        }
+        if self.get(path).is_some() {
+            // A table made by headers or dotted keys is replaced as a whole.
+            let saved = self.clone();
+            let old = self.remove(path)?;
+            return match self.insert(path, value) {
+                Ok(_) => Ok(old),
+                Err(e) => {
+                    *self = saved;
+                    Err(e)
+                }
+            };
+        }
+
+        // Every existing table on the way must be a table, or an array of
+        // tables indexed by position.
+        let mut in_array = false;
+        for i in 1..=parent.len() {
+            match self.get(&parent[..i]) {
+                None => break,
+                Some(Value::Table(_)) => {}
+                Some(Value::Array(_)) if i < parent.len() && self.get(&parent[..=i]).is_some() => in_array = true,
+                Some(_) => return Err(DocumentError::NotATable(_display_path(&parent[..i]))),
+            }
+        }
+        let nl = self.newline();
+        let is_table = matches!(&value, Value::Table(t) if !t.is_inline()) || matches!(&value, Value::Array(a) if _is_array_of_tables(a));
+        match self.insertion_point(parent) {
+            Some((pos, prefix, indent)) if !is_table || in_array => {
+                let key = prefix.iter().chain([key]).map(|k| _dump_key(k)).collect::<Vec<_>>().join(".");
+                let mut text = format!("{}{} = {}{}", indent, key, TomlEncoder::default().dump_value(&value), nl);
+                if pos > 0 && !self.src[..pos].ends_with('\n') {
+                    text.insert_str(0, nl);
+                }
+                if self.items.first().is_some_and(|first| pos <= first.lines.start && first.value.is_none()) {
+                    // The first key of the root table goes above the first header.
+                    text.push_str(nl);
+                }
+                self.splice(vec![(pos..pos, text)])?;
+            }
+            _ => {
+                let mut wrapped = value;
+                for part in path.iter().rev() {
+                    let mut table = Table::ordered();
+                    table.insert(part.to_string(), wrapped);
+                    wrapped = Value::Table(table);
+                }
+                let mut text = dumps(&wrapped, None);
+                if nl != "\n" {
+                    text = text.replace('\n', nl);
+                }
+                if !self.src.is_empty() {
+                    let separator = if self.src.ends_with('\n') { "" } else { nl };
+                    let blank = if self.src.trim_end_matches(['\r', '\n']).len() + 2 * nl.len() <= self.src.len() { "" } else { nl };
+                    text = format!("{}{}{}", separator, blank, text);
+                }
+                let end = self.src.len();
+                self.splice(vec![(end..end, text)])?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Removes the key or table at `path`, returning its value. Removing a
+    /// table removes its header, its keys and its sub-tables.
+    pub fn remove(&mut self, path: &[&str]) -> Result<Option<Value>, DocumentError> {
+        let Some(old) = self.get(path).cloned() else {
+            return Ok(None);
+        };
+        if let Some(item) = self.owner(path).filter(|item| item.path.len() < path.len()) {
+            let inner = &path[item.path.len()..];
+            self.edit_value(&item, |v| {
+                let (key, parent) = inner.split_last().expect("the path goes into the value");
+                match _lookup_mut(v, parent) {
+                    Some(Value::Table(t)) => {
+                        t.remove(key);
+                    }
+                    Some(Value::Array(a)) => {
+                        a.remove(key.parse::<usize>().expect("the path exists"));
+                    }
+                    _ => unreachable!("the path exists"),
+                }
+                Ok(())
+            })?;
+            return Ok(Some(old));
+        }
+        let mut edits = Vec::new();
+        let mut items = self.items.iter().peekable();
+        while let Some(item) = items.next() {
+            if !item.starts_with(path) {
+                continue;
+            }
+            let mut lines = item.lines.clone();
+            if item.value.is_none() {
+                // Take the header's whole section, with the comments above
+                // it and the blank line that sets it apart.
+                lines.start = self.comments_above(lines.start);
+                if lines.start > 0 {
+                    let blank = self.src[..lines.start - 1].rfind('\n').map_or(0, |nl| nl + 1);
+                    if self.src[blank..lines.start].trim().is_empty() {
+                        lines.start = blank;
+                    }
+                }
+                while let Some(next) = items.next_if(|next| next.value.is_some()) {
+                    lines.end = next.lines.end;
+                }
+            }
+            edits.push((lines, String::new()));
+        }
+        self.splice(edits)?;
+        Ok(Some(old))
+    }
+
+    /// Renames the last key of `path` to `new_key`, wherever the document
+    /// spells it out: in the key itself, in dotted keys and in the headers
+    /// of its sub-tables.
+    pub fn rename(&mut self, path: &[&str], new_key: &str) -> Result<(), DocumentError> {
+        let (key, parent) = path.split_last().ok_or_else(|| DocumentError::NotFound(String::new()))?;
+        if self.get(path).is_none() {
+            return Err(DocumentError::NotFound(_display_path(path)));
+        }
+        if new_key == *key {
+            return Ok(());
+        }
+        let renamed: Vec<&str> = parent.iter().copied().chain([new_key]).collect();
+        if self.get(&renamed).is_some() {
+            return Err(DocumentError::KeyExists(_display_path(&renamed)));
+        }
+        if let Some(item) = self.owner(path).filter(|item| item.path.len() < path.len()) {
+            let inner = &path[item.path.len()..];
+            return self.edit_value(&item, |v| {
+                let (key, parent) = inner.split_last().expect("the path goes into the value");
+                match _lookup_mut(v, parent) {
+                    Some(Value::Table(t)) => {
+                        let value = t.remove(key).expect("the path exists");
+                        t.insert(new_key.to_string(), value);
+                        Ok(())
+                    }
+                    _ => Err(DocumentError::NotATable(_display_path(&path[..path.len() - 1]))),
+                }
+            });
+        }
+        let edits: Vec<_> = self
+            .items
+            .iter()
+            .filter(|item| item.starts_with(path))
+            .filter_map(|item| item.path[path.len() - 1].1.clone())
+            .map(|span| (span, _dump_key(new_key)))
+            .collect();
+        if edits.is_empty() {
+            return Err(DocumentError::NotATable(_display_path(parent)));
+        }
+        self.splice(edits)
+    }
+
+    /// Returns the key/value pair whose key is `path` or a prefix of it.
+    fn owner(&self, path: &[&str]) -> Option<Item> {
+        self.items
+            .iter()
+            .find(|item| item.value.is_some() && item.path.len() <= path.len() && item.starts_with(&path[..item.path.len()]))
+            .cloned()
+    }
+
+    /// Rewrites the value of the pair `item` after `edit` changes it.
+    fn edit_value<T>(&mut self, item: &Item, edit: impl FnOnce(&mut Value) -> Result<T, DocumentError>) -> Result<T, DocumentError> {
+        let path: Vec<&str> = item.path.iter().map(|(part, _)| part.as_str()).collect();
+        let mut value = self.get(&path).cloned().expect("every pair has a value");
+        let result = edit(&mut value)?;
+        let span = item.value.clone().expect("a key/value pair has a value");
+        self.splice(vec![(span, TomlEncoder::default().dump_value(&value))])?;
+        Ok(result)
+    }
+
+    /// Finds where a new key of the table `parent` goes: after the last
+    /// pair that is in the table, or after the table's header. Returns the
+    /// position, the parts of `parent` the new key must repeat as a dotted
+    /// prefix, and the indentation of the pair before it.
+    fn insertion_point<'p, 'k>(&self, parent: &'p [&'k str]) -> Option<(usize, &'p [&'k str], &str)> {
+        let pair = self.items.iter().rev().find(|item| {
+            item.value.is_some() && item.path.len() > parent.len() && item.starts_with(parent) && item.table().len() <= parent.len()
+        });
+        if let Some(pair) = pair {
+            let line = &self.src[pair.lines.start..];
+            let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+            return Some((pair.lines.end, &parent[pair.table().len()..], indent));
+        }
+        let header = self.items.iter().find(|item| item.value.is_none() && item.path.len() == parent.len() && item.starts_with(parent));
+        if let Some(header) = header {
+            return Some((header.lines.end, &[], ""));
+        }
+        if !parent.is_empty() {
+            return None;
+        }
+        // The root table has no keys yet: they go above the first header
+        // and the comments leading up to it.
+        let Some(first) = self.items.first() else {
+            return Some((self.src.len(), &[], ""));
+        };
+        Some((self.comments_above(first.lines.start), &[], ""))
+    }
+
+    /// Returns the start of the block of comment lines that ends at `pos`.
+    fn comments_above(&self, mut pos: usize) -> usize {
+        while pos > 0 {
+            let start = self.src[..pos - 1].rfind('\n').map_or(0, |nl| nl + 1);
+            if !self.src[start..pos].trim_start().starts_with('#') {
+                break;
+            }
+            pos = start;
+        }
+        pos
+    }
+
+    /// Returns the line ending the document uses.
+    fn newline(&self) -> &'static str {
+        if self.src.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+
+    /// Replaces non-overlapping spans of the text and reads the document
+    /// again, leaving it unchanged if the result is not valid TOML.
+    fn splice(&mut self, mut edits: Vec<(Range<usize>, String)>) -> Result<(), DocumentError> {
+        edits.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+        let mut src = self.src.clone();
+        for (span, text) in edits {
+            src.replace_range(span, &text);
+        }
+        *self = Document::parse(&src).map_err(DocumentError::Invalid)?;
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Document {
+    type Err = TomlDecodeError;
+
+    fn from_str(s: &str) -> Result<Document, TomlDecodeError> {
+        Document::parse(s)
+    }
+}
+
+impl std::fmt::Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.src)
+    }
+}
+
+/// Follows `path` from `value` through tables by key and arrays by index.
+fn _lookup<'v>(value: &'v Value, path: &[&str]) -> Option<&'v Value> {
+    path.iter().try_fold(value, |value, part| match value {
+        Value::Table(t) => t.get(part),
+        Value::Array(a) => a.get(part.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+fn _lookup_mut<'v>(value: &'v mut Value, path: &[&str]) -> Option<&'v mut Value> {
+    path.iter().try_fold(value, |value, part| match value {
+        Value::Table(t) => t.get_mut(part),
+        Value::Array(a) => a.get_mut(part.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+/// Renders a path as a dotted key for messages.
+fn _display_path(path: &[&str]) -> String {
+    path.iter().map(|k| _dump_key(k)).collect::<Vec<_>>().join(".")
+}
+
+/// A fixed offset from UTC as written in a TOML offset datetime, such as
+/// `-07:00` or `Z`.
+///
+/// `TomlTz` is a chrono `TimeZone` and its own `Offset`, so decoded offset
+/// datetimes are `DateTime<TomlTz>`. Offsets compare and hash by their
+/// distance from UTC, so `Z`, `+00:00` and `-00:00` are all equal.
+#[derive(Debug, Clone, Copy)]
+pub struct TomlTz {
+    _sign: i32,
+    _hours: i32,
+    _minutes: i32,
+    /// The letter a zero offset was written as, `Z` or `z`, if it was.
+    _zulu: Option<char>,
+}
+
+impl TomlTz {
+    /// Creates a new TomlTz instance from a toml_offset string: `Z` or an
+    /// offset of the form `+HH:MM` or `-HH:MM` within a day.
+    ///
+    /// Raises:
+    ///     TomlDecodeError: The offset is malformed
+    pub fn new(toml_offset: &str) -> Result<TomlTz, TomlDecodeError> {
+        if toml_offset == "Z" || toml_offset == "z" {
+            return Ok(TomlTz { _sign: 1, _hours: 0, _minutes: 0, _zulu: toml_offset.chars().next() });
+        }
+        let field = |range: Range<usize>| {
+            toml_offset.get(range).filter(|f| f.bytes().all(|b| b.is_ascii_digit())).and_then(|f| f.parse::<i32>().ok())
+        };
+        let sign = match toml_offset.as_bytes().first() {
+            Some(b'+') => Some(1),
+            Some(b'-') => Some(-1),
+            _ => None,
+        };
+        match (sign, field(1..3), toml_offset.as_bytes().get(3), field(4..6)) {
+            (Some(sign), Some(hours), Some(b':'), Some(minutes)) if toml_offset.len() == 6 && hours < 24 && minutes < 60 => Ok(TomlTz {
+                _sign: sign,
+                _hours: hours,
+                _minutes: minutes,
+                _zulu: None,
+            }),
+            _ => {
+                let msg = format!("Invalid UTC offset: {}", toml_offset);
+                Err(TomlDecodeError::new(ErrorKind::InvalidDatetime, msg, toml_offset.to_string(), 0..toml_offset.len()))
+            }
+        }
+    }
+
+    /// Returns the raw offset as a string, with `Z` written as `+00:00`.
+    pub fn get_raw_offset(&self) -> String {
+        format!("{}{:02}:{:02}", if self._sign < 0 { '-' } else { '+' }, self._hours, self._minutes)
+    }
+
+    /// Returns the initialization arguments for the TomlTz instance.
+    #[deprecated(note = "use `to_string`, which `FromStr` parses back")]
+    pub fn get_init_args(&self) -> (String,) {
+        (self.get_raw_offset(),)
+    }
+
+    /// Creates a deep copy of the TomlTz instance.
+    #[deprecated(note = "`TomlTz` is `Copy`")]
+    pub fn deepcopy(&self) -> TomlTz {
+        *self
+    }
+
+    /// Returns the timezone name in UTC format.
+    #[deprecated(note = "use `Display`, or the `FixedOffset` from `Offset::fix`")]
+    pub fn tzname(&self) -> String {
+        format!("UTC{}", self.get_raw_offset())
+    }
+
+    /// Returns the UTC offset as a Duration.
+    #[deprecated(note = "use `Offset::fix`, as in `tz.fix().local_minus_utc()`")]
+    pub fn utcoffset(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.total_seconds().into())
+    }
+
+    /// Returns the daylight saving time offset, which is always zero.
+    #[deprecated(note = "TOML offsets are fixed and have no daylight saving time")]
+    pub fn dst(&self) -> chrono::Duration {
+        chrono::Duration::zero()
+    }
+
+    /// Returns the offset from UTC in seconds.
+    fn total_seconds(&self) -> i32 {
+        self._sign * (self._hours * 3600 + self._minutes * 60)
+    }
+}
+
+impl PartialEq for TomlTz {
+    fn eq(&self, other: &TomlTz) -> bool {
+        self.total_seconds() == other.total_seconds()
+    }
+}
+
+impl Eq for TomlTz {}
+
+impl std::hash::Hash for TomlTz {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.total_seconds().hash(state);
+    }
+}
+
+/// Converts a chrono offset, dropping any seconds, which TOML offsets
+/// cannot express. A zero offset is written as `Z`.
+impl From<FixedOffset> for TomlTz {
+    fn from(offset: FixedOffset) -> TomlTz {
+        let minutes = offset.local_minus_utc() / 60;
+        TomlTz {
+            _sign: if minutes < 0 { -1 } else { 1 },
+            _hours: minutes.abs() / 60,
+            _minutes: minutes.abs() % 60,
+            _zulu: if minutes == 0 { Some('Z') } else { None },
+        }
+    }
+}
+
+impl std::str::FromStr for TomlTz {
+    type Err = TomlDecodeError;
+
+    fn from_str(s: &str) -> Result<TomlTz, TomlDecodeError> {
+        TomlTz::new(s)
+    }
+}
+
+/// Writes the offset as it was parsed: `Z`, `z`, or `+HH:MM` or `-HH:MM`,
+/// keeping the sign of `-00:00`.
+impl std::fmt::Display for TomlTz {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self._zulu {
+            Some(zulu) => write!(f, "{}", zulu),
+            None => f.write_str(&self.get_raw_offset()),
+        }
+    }
+}
+
+impl chrono::Offset for TomlTz {
+    fn fix(&self) -> FixedOffset {
+        FixedOffset::east_opt(self.total_seconds()).expect("offsets are less than a day")
+    }
+}
+
+impl chrono::TimeZone for TomlTz {
+    type Offset = TomlTz;
+
+    fn from_offset(offset: &TomlTz) -> TomlTz {
+        *offset
+    }
+
+    fn offset_from_local_date(&self, _local: &NaiveDate) -> chrono::MappedLocalTime<TomlTz> {
+        chrono::MappedLocalTime::Single(*self)
+    }
+
+    fn offset_from_local_datetime(&self, _local: &NaiveDateTime) -> chrono::MappedLocalTime<TomlTz> {
+        chrono::MappedLocalTime::Single(*self)
+    }
+
+    fn offset_from_utc_date(&self, _utc: &NaiveDate) -> TomlTz {
+        *self
+    }
+
+    fn offset_from_utc_datetime(&self, _utc: &NaiveDateTime) -> TomlTz {
+        *self
+    }
+}
+
+impl serde::Serialize for TomlTz {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TomlTz {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<TomlTz, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Writes out a table as toml to a writer
+///
+/// Args:
+///     o: Value to dump into toml
+///     f: Writer where the toml should be stored
+///     encoder: The `TomlEncoder` to use for constructing the output string
+///
+/// Returns:
+///     String containing the toml corresponding to the table
+pub fn dump<W: std::io::Write>(o: &Value, mut f: W, encoder: Option<&TomlEncoder>) -> std::io::Result<String> {
+    let d = dumps(o, encoder);
+    f.write_all(d.as_bytes())?;
+    Ok(d)
+}
+
+/// Stringifies a table as toml
+///
+/// Args:
+///     o: Value to dump into toml. Anything other than a table is rendered
+///        as a single TOML value rather than a document.
+///     encoder: The `TomlEncoder` to use for constructing the output string
+///
+/// Returns:
+///     String containing the toml corresponding to the table
+pub fn dumps(o: &Value, encoder: Option<&TomlEncoder>) -> String {
+    let owned;
+    let encoder = match encoder {
+        Some(encoder) => encoder,
+        None => {
+            owned = TomlEncoder::new(None, false);
+            &owned
+        }
+    };
+    let o = match o {
+        Value::Table(o) => o,
+        _ => return encoder.dump_value(o),
+    };
+    let mut retval = String::new();
+    if o.is_ordered() {
+        retval.push_str(&encoder.dump_keys(o, &[]));
+        encoder.dump_ordered_sections(o, "", &[], &mut retval);
+    } else {
+        let (addtoretval, sections) = encoder.dump_sections_at(o, "", &[]);
+        retval.push_str(&addtoretval);
+        encoder.dump_breadth_first(sections, &mut retval);
+    }
+    if let Some(cv) = encoder.comments.get(&[] as &[String]) {
+        if !retval.is_empty() && !cv.closing().is_empty() && !retval.ends_with("\n\n") {
+            retval.push('\n');
+        }
+        for comment in cv.closing() {
+            retval.push_str(comment);
+            retval.push('\n');
+        }
+    }
+    retval
+}
+
+/// Renders a string as a TOML basic string, escaping quotes, backslashes
+/// and control characters.
+pub fn _dump_str(v: &str) -> String {
+    let mut retval = String::with_capacity(v.len() + 2);
+    retval.push('"');
+    for c in v.chars() {
+        match c {
+            '"' => retval.push_str("\\\""),
+            '\\' => retval.push_str("\\\\"),
+            '\u{8}' => retval.push_str("\\b"),
+            '\t' => retval.push_str("\\t"),
+            '\n' => retval.push_str("\\n"),
+            '\u{c}' => retval.push_str("\\f"),
+            '\r' => retval.push_str("\\r"),
+            c if c.is_control() => retval.push_str(&format!("\\u{:04x}", c as u32)),
+            c => retval.push(c),
+        }
+    }
+    retval.push('"');
+    retval
+}
+
+/// Renders a float so that it always reads back as a float.
+pub fn _dump_float(v: f64) -> String {
+    if v.is_nan() {
+        "nan".to_string()
+    } else if v.is_infinite() {
+        if v > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format!("{:?}", v)
+    }
+}
+
+/// Renders a local time, with the fraction of a second only when it is
+/// non-zero.
+pub fn _dump_time(v: &NaiveTime) -> String {
+    Datetime::from(*v).to_string()
+}
+
+/// Quotes a key unless it is a valid bare key.
+fn _dump_key(k: &str) -> String {
+    if GROUPNAME_RE.is_match(k) {
+        k.to_string()
+    } else {
+        _dump_str(k)
+    }
+}
+
+/// Returns true if `a` is written as an array of tables, `[[...]]`: it
+/// holds only tables, not all of them inline.
+fn _is_array_of_tables(a: &[Value]) -> bool {
+    a.iter().all(|v| matches!(v, Value::Table(_))) && a.iter().any(|v| matches!(v, Value::Table(t) if !t.is_inline()))
+}
+
+/// Sub-tables still to be written as sections: the header, the path and
+/// the table.
+type Sections<'a> = Vec<(String, Vec<String>, &'a Table)>;
+
+/// An encoder for TOML data.
+#[derive(Debug, Clone)]
+pub struct TomlEncoder {
+    _dict: fn() -> Table,
+    preserve: bool,
+    /// Comments to write back, keyed by the path of what they belong to.
+    #[allow(deprecated)]
+    comments: HashMap<Vec<String>, CommentValue>,
+}
+
+impl Default for TomlEncoder {
+    fn default() -> Self {
+        TomlEncoder::new(None, false)
+    }
+}
+
+impl TomlEncoder {
+    /// Creates a new TomlEncoder with the specified table factory.
+    ///
+    /// Tables marked as inline, and arrays of them, are written inline.
+    /// With `preserve` set, every nested table is written as an inline
+    /// table instead of being expanded into its own section.
+    pub fn new(_dict: Option<fn() -> Table>, preserve: bool) -> TomlEncoder {
+        TomlEncoder {
+            _dict: _dict.unwrap_or(Table::new),
+            preserve,
+            comments: HashMap::new(),
+        }
+    }
+
+    /// Returns an empty table.
+    pub fn get_empty_table(&self) -> Table {
+        (self._dict)()
+    }
+
+    /// Dumps an array, one element after another with a trailing comma.
+    pub fn dump_list(&self, v: &[Value]) -> String {
+        let mut retval = "[".to_string();
+        for u in v {
+            retval.push_str(&format!(" {},", self.dump_value(u)));
+        }
+        retval.push(']');
+        retval
+    }
+
+    /// Preserve inline table in its compact syntax instead of expanding
+    /// into subsection.
+    pub fn dump_inline_table(&self, section: &Value) -> String {
+        match section {
+            Value::Table(t) => {
+                let val_list: Vec<String> = t
+                    .iter()
+                    .map(|(k, v)| format!("{} = {}", _dump_key(k), self.dump_inline_table(v)))
+                    .collect();
+                format!("{{ {} }}", val_list.join(", "))
+            }
+            _ => self.dump_value(section),
+        }
+    }
+
+    /// Dumps a single value.
+    pub fn dump_value(&self, v: &Value) -> String {
+        match v {
+            Value::String(s) => _dump_str(s),
+            Value::Integer(i) => i.to_string(),
+            Value::Float(f) => _dump_float(*f),
+            Value::Boolean(b) => b.to_string(),
+            Value::Datetime(dt) => dt.to_string(),
+            Value::Array(a) => self.dump_list(a),
+            Value::Table(_) => self.dump_inline_table(v),
+        }
+    }
+
+    /// Dumps the plain keys and arrays of tables of `o`, and returns them
+    /// together with the sub-tables still to be written as sections.
+    pub fn dump_sections<'a>(&self, o: &'a Table, sup: &str) -> (String, Vec<(String, &'a Table)>) {
+        let (retstr, retdict) = self.dump_sections_at(o, sup, &[]);
+        (retstr, retdict.into_iter().map(|(section, _, table)| (section, table)).collect())
+    }
+
+    /// Dumps a line, with the comments of the item at `path` around it.
+    fn dump_line(&self, path: &[String], line: &str) -> String {
+        let mut retstr = match self.comments.get(path) {
+            Some(cv) => cv.surround(line),
+            None => line.to_string(),
+        };
+        retstr.push('\n');
+        retstr
+    }
+
+    /// Dumps the value at `path` on a line indented by `indent`. Arrays
+    /// with comments on their elements are written one element per line.
+    fn dump_value_at(&self, v: &Value, path: &[String], indent: &str) -> String {
+        let a = match v {
+            Value::Array(a) if self.comments.keys().any(|p| p.len() >= path.len() && p.starts_with(path)) => a,
+            _ => return self.dump_value(v),
+        };
+        let inner = format!("{}    ", indent);
+        let mut retval = "[\n".to_string();
+        let mut path = path.to_vec();
+        for (i, u) in a.iter().enumerate() {
+            path.push(i.to_string());
+            let cv = self.comments.get(&path);
+            for comment in cv.map_or(&[][..], |cv| cv.leading()) {
+                retval.push_str(&format!("{}{}\n", inner, comment));
+            }
+            retval.push_str(&format!("{}{},", inner, self.dump_value_at(u, &path, &inner)));
+            if let Some(comment) = cv.and_then(|cv| cv.trailing()) {
+                retval.push_str(&format!(" {}", comment));
+            }
+            retval.push('\n');
+            path.pop();
+        }
+        for comment in self.comments.get(&path).map_or(&[][..], |cv| cv.closing()) {
+            retval.push_str(&format!("{}{}\n", inner, comment));
+        }
+        retval.push_str(indent);
+        retval.push(']');
+        retval
+    }
+
+    /// Does the work of `dump_sections` for the table at `path`, returning
+    /// the path of each sub-table as well.
+    fn dump_sections_at<'a>(&self, o: &'a Table, sup: &str, path: &[String]) -> (String, Sections<'a>) {
+        let mut retstr = String::new();
+        let mut sup = sup.to_string();
+        if !sup.is_empty() && !sup.ends_with('.') {
+            sup.push('.');
+        }
+        let mut retdict = Vec::new();
+        let mut arraystr = String::new();
+        for (section, value) in o {
+            let qsection = _dump_key(section);
+            let mut spath = path.to_vec();
+            spath.push(section.clone());
+            match value {
+                Value::Table(t) if !self.preserve && !t.is_inline() => retdict.push((qsection, spath, t)),
+                Value::Array(a) if _is_array_of_tables(a) => {
+                    for (i, a) in a.iter().filter_map(Value::as_table).enumerate() {
+                        let mut apath = spath.clone();
+                        apath.push(i.to_string());
+                        let mut arraytabstr = "\n".to_string();
+                        arraystr.push_str(&self.dump_line(&apath, &format!("[[{}{}]]", sup, qsection)));
+                        let (s, mut d) = self.dump_sections_at(a, &format!("{}{}", sup, qsection), &apath);
+                        if s.starts_with('[') {
+                            arraytabstr.push_str(&s);
+                        } else {
+                            arraystr.push_str(&s);
+                        }
+                        while !d.is_empty() {
+                            let mut newd = Vec::new();
+                            for (dsec, dpath, dt) in d {
+                                let (s1, d1) = self.dump_sections_at(dt, &format!("{}{}.{}", sup, qsection, dsec), &dpath);
+                                if !s1.is_empty() || d1.is_empty() || self.comments.contains_key(&dpath) {
+                                    arraytabstr.push_str(&self.dump_line(&dpath, &format!("[{}{}.{}]", sup, qsection, dsec)));
+                                    arraytabstr.push_str(&s1);
+                                }
+                                for (s1, p1, t1) in d1 {
+                                    newd.push((format!("{}.{}", dsec, s1), p1, t1));
+                                }
+                            }
+                            d = newd;
+                        }
+                        arraystr.push_str(&arraytabstr);
+                    }
+                }
+                _ => {
+                    let line = format!("{} = {}", qsection, self.dump_value_at(value, &spath, ""));
+                    retstr.push_str(&self.dump_line(&spath, &line));
+                }
+            }
+        }
+        retstr.push_str(&arraystr);
+        (retstr, retdict)
+    }
+
+    /// Writes `sections` and the sub-tables under them, a level at a time,
+    /// as the Python encoder does.
+    fn dump_breadth_first(&self, mut sections: Sections<'_>, retval: &mut String) {
+        while !sections.is_empty() {
+            let mut newsections = Vec::new();
+            for (section, path, table) in sections {
+                let (addtoretval, addtosections) = self.dump_sections_at(table, &section, &path);
+
+                if !addtoretval.is_empty() || addtosections.is_empty() || self.comments.contains_key(&path) {
+                    if !retval.is_empty() && !retval.ends_with("\n\n") {
+                        retval.push('\n');
+                    }
+                    retval.push_str(&self.dump_line(&path, &format!("[{}]", section)));
+                    retval.push_str(&addtoretval);
+                }
+                for (s, p, t) in addtosections {
+                    newsections.push((format!("{}.{}", section, s), p, t));
+                }
+            }
+            sections = newsections;
+        }
+    }
+
+    /// Returns true if `value` is written under a header of its own: a
+    /// sub-table not written inline, or an array of tables.
+    fn is_section(&self, value: &Value) -> bool {
+        match value {
+            Value::Table(t) => !self.preserve && !t.is_inline(),
+            Value::Array(a) => _is_array_of_tables(a),
+            _ => false,
+        }
+    }
+
+    /// Dumps the `key = value` lines of the table at `path`, leaving out
+    /// what goes under headers.
+    fn dump_keys(&self, o: &Table, path: &[String]) -> String {
+        let mut retstr = String::new();
+        for (key, value) in o.iter().filter(|(_, v)| !self.is_section(v)) {
+            let mut kpath = path.to_vec();
+            kpath.push(key.clone());
+            let line = format!("{} = {}", _dump_key(key), self.dump_value_at(value, &kpath, ""));
+            retstr.push_str(&self.dump_line(&kpath, &line));
+        }
+        retstr
+    }
+
+    /// Writes the sub-tables and arrays of tables of an ordered table in
+    /// key order, each followed by the sections under it, so that a document decoded
+    /// into ordered tables is written back in the order it was read.
+    fn dump_ordered_sections(&self, o: &Table, sup: &str, path: &[String], retstr: &mut String) {
+        for (key, value) in o.iter().filter(|(_, v)| self.is_section(v)) {
+            let name = if sup.is_empty() { _dump_key(key) } else { format!("{}.{}", sup, _dump_key(key)) };
+            let mut spath = path.to_vec();
+            spath.push(key.clone());
+            let header = |retstr: &mut String, path: &[String], line: String, body: &str| {
+                if !retstr.is_empty() && !retstr.ends_with("\n\n") {
+                    retstr.push('\n');
+                }
+                retstr.push_str(&self.dump_line(path, &line));
+                retstr.push_str(body);
+            };
+            match value {
+                Value::Table(t) => {
+                    let body = self.dump_keys(t, &spath);
+                    let has_sections = t.values().any(|v| self.is_section(v));
+                    if !body.is_empty() || !has_sections || self.comments.contains_key(&spath) {
+                        header(retstr, &spath, format!("[{}]", name), &body);
+                    }
+                    self.dump_ordered_sections(t, &name, &spath, retstr);
+                }
+                Value::Array(a) => {
+                    for (i, t) in a.iter().filter_map(Value::as_table).enumerate() {
+                        let mut apath = spath.clone();
+                        apath.push(i.to_string());
+                        header(retstr, &apath, format!("[[{}]]", name), &self.dump_keys(t, &apath));
+                        self.dump_ordered_sections(t, &name, &apath, retstr);
+                    }
+                }
+                _ => unreachable!("only tables and arrays are sections"),
+            }
+        }
+    }
+}
+
+/// An encoder that writes back the comments kept by a
+/// `TomlPreserveCommentDecoder`.
+///
+/// Dereferences to the wrapped `TomlEncoder`. Superseded by `Document`.
+#[deprecated(note = "use `Document`, which keeps comments along with the rest of the formatting")]
+#[derive(Debug, Clone)]
+pub struct TomlPreserveCommentEncoder {
+    encoder: TomlEncoder,
+}
+
+#[allow(deprecated)]
+impl TomlPreserveCommentEncoder {
+    /// Creates a new instance of `TomlPreserveCommentEncoder` writing
+    /// `comments`, as returned by `TomlPreserveCommentDecoder::comments`.
+    pub fn new(_dict: Option<fn() -> Table>, preserve: bool, comments: HashMap<Vec<String>, CommentValue>) -> TomlPreserveCommentEncoder {
+        TomlPreserveCommentEncoder {
+            encoder: TomlEncoder {
+                comments,
+                ..TomlEncoder::new(_dict, preserve)
+            },
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl std::ops::Deref for TomlPreserveCommentEncoder {
+    type Target = TomlEncoder;
+
+    fn deref(&self) -> &TomlEncoder {
+        &self.encoder
+    }
+}
+
+/// A decoder that keeps every table in the order its keys appear in the
+/// document.
+#[derive(Debug, Clone)]
+pub struct TomlOrderedDecoder {
+    decoder: TomlDecoder,
+}
+
+impl Default for TomlOrderedDecoder {
+    fn default() -> Self {
+        TomlOrderedDecoder::new()
+    }
+}
+
+impl TomlOrderedDecoder {
+    /// Creates a new TomlOrderedDecoder.
+    pub fn new() -> TomlOrderedDecoder {
+        TomlOrderedDecoder {
+            decoder: TomlDecoder::new(Some(Table::ordered)),
+        }
+    }
+}
+
+impl std::ops::Deref for TomlOrderedDecoder {
+    type Target = TomlDecoder;
+
+    fn deref(&self) -> &TomlDecoder {
+        &self.decoder
+    }
+}
+
+/// An encoder whose tables keep their keys in insertion order.
+#[derive(Debug, Clone)]
+pub struct TomlOrderedEncoder {
+    encoder: TomlEncoder,
+}
+
+impl Default for TomlOrderedEncoder {
+    fn default() -> Self {
+        TomlOrderedEncoder::new()
+    }
+}
+
+impl TomlOrderedEncoder {
+    /// Creates a new TomlOrderedEncoder.
+    pub fn new() -> TomlOrderedEncoder {
+        TomlOrderedEncoder {
+            encoder: TomlEncoder::new(Some(Table::ordered), false),
+        }
+    }
+}
+
+impl std::ops::Deref for TomlOrderedEncoder {
+    type Target = TomlEncoder;
+
+    fn deref(&self) -> &TomlEncoder {
+        &self.encoder
+    }
+}
+
+/// Error returned when reading TOML or deserializing a typed value from
+/// it.
+#[derive(Debug)]
+pub enum DeError {
+    /// The input was not valid TOML.
+    Decode(TomlDecodeError),
+    /// The input could not be read.
+    Io(std::io::Error),
+    /// The TOML was valid but did not match the requested type. `key` is
+    /// the path to the offending value, outermost key first, and `span`
+    /// is where it is in the input when that was a string or bytes.
+    Custom { msg: String, key: Vec<String>, span: Option<Span> },
+    /// An error in the named file.
+    File { path: PathBuf, error: Box<DeError> },
+}
+
+impl DeError {
+    /// Returns the 1-based line and column of the error, if known.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        match self {
+            DeError::Decode(e) => Some((e.lineno(), e.colno())),
+            DeError::Custom { span: Some(span), .. } => Some((span.lineno, span.colno)),
+            DeError::File { error, .. } => error.line_col(),
+            _ => None,
+        }
+    }
+
+    /// Returns the file the error is in, if the input was a file.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            DeError::File { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Returns the error without the name of the file it is in.
+    pub fn inner(&self) -> &DeError {
+        match self {
+            DeError::File { error, .. } => error,
+            _ => self,
+        }
+    }
+
+    fn in_file(self, path: &Path) -> DeError {
+        DeError::File {
+            path: path.to_path_buf(),
+            error: Box::new(self),
+        }
+    }
+
+    fn prepend_key(mut self, key: &str) -> DeError {
+        if let DeError::Custom { key: path, .. } = &mut self {
+            path.insert(0, key.to_string());
+        }
+        self
+    }
+
+    /// Sets the span of a `Custom` error to the source of its key, or of
+    /// the nearest table above it that has one.
+    fn locate(mut self, sources: &SourceMap) -> DeError {
+        if let DeError::Custom { key, span, .. } = &mut self {
+            *span = (0..=key.len()).rev().find_map(|len| {
+                let source = sources.sources.get(&key[..len])?;
+                source.value.clone().or_else(|| source.key.clone())
+            });
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for DeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeError::Decode(e) => write!(f, "{}", e),
+            DeError::Io(e) => write!(f, "{}", e),
+            DeError::Custom { msg, key, span } => {
+                write!(f, "{}", msg)?;
+                if !key.is_empty() {
+                    write!(f, " for key `{}`", key.join("."))?;
+                }
+                match span {
+                    Some(span) => write!(f, " at line {} column {}", span.lineno, span.colno),
+                    None => Ok(()),
+                }
+            }
+            DeError::File { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for DeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeError::Decode(e) => Some(e),
+            DeError::Io(e) => Some(e),
+            DeError::File { error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+impl serde::de::Error for DeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        DeError::Custom {
+            msg: msg.to_string(),
+            key: Vec::new(),
+            span: None,
+        }
+    }
+}
+
+impl From<TomlDecodeError> for DeError {
+    fn from(e: TomlDecodeError) -> Self {
+        DeError::Decode(e)
+    }
+}
+
+impl From<MergeError> for DeError {
+    fn from(e: MergeError) -> Self {
+        match e {
+            MergeError::Io { path, error } => DeError::Io(error).in_file(&path),
+            MergeError::Decode { path, error } => DeError::Decode(*error).in_file(&path),
+            MergeError::Conflict { path, key } => DeError::Custom {
+                msg: "value is already set by an earlier file".to_string(),
+                key,
+                span: None,
+            }
+            .in_file(&path),
+        }
+    }
+}
+
+impl From<std::io::Error> for DeError {
+    fn from(e: std::io::Error) -> Self {
+        DeError::Io(e)
+    }
+}
+
+/// Deserializes an instance of `T` from a string of TOML. Keys and
+/// strings without escapes are lent from `s`, so `T` may hold `&'a str`.
+/// A value that does not match `T` is reported with its line and column.
+pub fn from_str<'a, T: serde::Deserialize<'a>>(s: &'a str) -> Result<T, DeError> {
+    let value = loads_borrowed(s)?;
+    T::deserialize(ValueDeserializer::borrowed(value)).map_err(|e| match e {
+        // The sources are only needed on failure, so `s` is parsed again
+        // to find them rather than recording them up front.
+        DeError::Custom { .. } => match loads_with_sources(s, None, None) {
+            Ok((_, sources)) => e.locate(&sources),
+            Err(_) => e,
+        },
+        e => e,
+    })
+}
+
+/// Deserializes an instance of `T` from UTF-8 encoded TOML. A byte order
+/// mark is skipped; UTF-16, invalid UTF-8 and stray control characters
+/// are decode errors with their line and column.
+pub fn from_bytes<'a, T: serde::Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, DeError> {
+    from_str(_decode_bytes(bytes)?)
+}
+
+/// Deserializes an instance of `T` from a reader of TOML.
+pub fn from_reader<T: serde::de::DeserializeOwned, R: Read>(mut rdr: R) -> Result<T, DeError> {
+    let mut bytes = Vec::new();
+    rdr.read_to_end(&mut bytes)?;
+    from_bytes(&bytes)
+}
+
+/// Deserializes an instance of `T` from a TOML file. Errors are
+/// `DeError::File`, naming the file.
+pub fn from_path<T: serde::de::DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, DeError> {
+    let path = path.as_ref();
+    let parse = || -> Result<T, DeError> { from_bytes(&std::fs::read(path)?) };
+    parse().map_err(|e| e.in_file(path))
+}
+
+/// Deserializes an instance of `T` from several TOML files, deep-merged
+/// in order by a `LayeredLoader` with the default policies. Errors reading
+/// or decoding a file are `DeError::File`, naming the file.
+pub fn from_paths<T, I>(paths: I) -> Result<T, DeError>
+where
+    T: serde::de::DeserializeOwned,
+    I: IntoIterator,
+    I::Item: AsRef<Path>,
+{
+    let loader = paths.into_iter().fold(LayeredLoader::new(None), |loader, path| loader.file(path.as_ref()));
+    let (table, _) = loader.load()?;
+    T::deserialize(ValueDeserializer::new(Value::Table(table)))
+}
+
+/// A serde `Deserializer` over a decoded `Value` tree, or a
+/// `BorrowedValue` tree whose borrowed strings and keys can be
+/// deserialized into `&'de str`.
+///
+/// Datetimes are handed to `deserialize_str` and `deserialize_string` as
+/// their TOML text, which is what chrono's own `Deserialize` impls
+/// expect. Anywhere else they are a single-entry map keyed by the private
+/// datetime field, which `Value` reads back as `Value::Datetime`.
+pub struct ValueDeserializer<V = Value> {
+    value: V,
+}
+
+impl ValueDeserializer {
+    /// Creates a deserializer that reads from `value`.
+    pub fn new(value: Value) -> ValueDeserializer {
+        ValueDeserializer { value }
+    }
+}
+
+impl<'a> ValueDeserializer<BorrowedValue<'a>> {
+    /// Creates a deserializer that reads from `value`, lending its
+    /// borrowed strings.
+    pub fn borrowed(value: BorrowedValue<'a>) -> ValueDeserializer<BorrowedValue<'a>> {
+        ValueDeserializer { value }
+    }
+}
+
+impl<'de> serde::de::IntoDeserializer<'de, DeError> for Value {
+    type Deserializer = ValueDeserializer;
+
+    fn into_deserializer(self) -> ValueDeserializer {
+        ValueDeserializer::new(self)
+    }
+}
+
+impl<'de, 'a: 'de> serde::de::IntoDeserializer<'de, DeError> for BorrowedValue<'a> {
+    type Deserializer = ValueDeserializer<BorrowedValue<'a>>;
+
+    fn into_deserializer(self) -> ValueDeserializer<BorrowedValue<'a>> {
+        ValueDeserializer::borrowed(self)
+    }
+}
+
+/// A value tree that `ValueDeserializer` reads, one level at a time.
+trait DeNode<'de>: Sized {
+    fn into_de(self) -> DeValue<'de, Self>;
+}
+
+/// One level of a value being deserialized.
+enum DeValue<'de, V> {
+    String(Cow<'de, str>),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Datetime(Datetime),
+    Array(Vec<V>),
+    Table(Vec<(Cow<'de, str>, V)>),
+}
+
+impl<'de> DeNode<'de> for Value {
+    fn into_de(self) -> DeValue<'de, Value> {
+        match self {
+            Value::String(s) => DeValue::String(Cow::Owned(s)),
+            Value::Integer(i) => DeValue::Integer(i),
+            Value::Float(f) => DeValue::Float(f),
+            Value::Boolean(b) => DeValue::Boolean(b),
+            Value::Datetime(dt) => DeValue::Datetime(dt),
+            Value::Array(a) => DeValue::Array(a),
+            Value::Table(t) => DeValue::Table(t.into_iter().map(|(k, v)| (Cow::Owned(k), v)).collect()),
+        }
+    }
+}
+
+impl<'de, 'a: 'de> DeNode<'de> for BorrowedValue<'a> {
+    fn into_de(self) -> DeValue<'de, BorrowedValue<'a>> {
+        match self {
+            BorrowedValue::String(s) => DeValue::String(s),
+            BorrowedValue::Integer(i) => DeValue::Integer(i),
+            BorrowedValue::Float(f) => DeValue::Float(f),
+            BorrowedValue::Boolean(b) => DeValue::Boolean(b),
+            BorrowedValue::Datetime(dt) => DeValue::Datetime(dt),
+            BorrowedValue::Array(a) => DeValue::Array(a),
+            BorrowedValue::Table(t) => DeValue::Table(t.into_iter().map(|(k, v)| (k as Cow<'de, str>, v)).collect()),
+        }
+    }
+}
+
+impl<V> DeValue<'_, V> {
+    fn unexpected(&self) -> serde::de::Unexpected<'_> {
+        use serde::de::Unexpected;
+        match self {
+            DeValue::String(s) => Unexpected::Str(s),
+            DeValue::Integer(i) => Unexpected::Signed(*i),
+            DeValue::Float(f) => Unexpected::Float(*f),
+            DeValue::Boolean(b) => Unexpected::Bool(*b),
+            DeValue::Datetime(dt) => Unexpected::Other(dt.type_str()),
+            DeValue::Array(_) => Unexpected::Seq,
+            DeValue::Table(_) => Unexpected::Map,
+        }
+    }
+}
+
+/// Hands one level of a value to `visitor`, as `deserialize_any` does.
+fn deserialize_de<'de, N: DeNode<'de>, T: serde::de::Visitor<'de>>(value: DeValue<'de, N>, visitor: T) -> Result<T::Value, DeError> {
+    match value {
+        DeValue::String(s) => serde::Deserializer::deserialize_any(StrDeserializer(s), visitor),
+        DeValue::Integer(i) => visitor.visit_i64(i),
+        DeValue::Float(f) => visitor.visit_f64(f),
+        DeValue::Boolean(b) => visitor.visit_bool(b),
+        DeValue::Datetime(dt) => visitor.visit_map(DatetimeDeserializer(Some(dt))),
+        DeValue::Array(a) => {
+            let len = a.len();
+            let mut seq = SeqDeserializer { iter: a.into_iter(), index: 0 };
+            let ret = visitor.visit_seq(&mut seq)?;
+            if seq.iter.len() == 0 {
+                Ok(ret)
+            } else {
+                Err(serde::de::Error::invalid_length(len, &"fewer elements in array"))
+            }
+        }
+        DeValue::Table(t) => visitor.visit_map(MapDeserializer { iter: t.into_iter(), value: None }),
+    }
+}
+
+impl<'de, N: DeNode<'de>> serde::Deserializer<'de> for ValueDeserializer<N> {
+    type Error = DeError;
+
+    fn deserialize_any<T: serde::de::Visitor<'de>>(self, visitor: T) -> Result<T::Value, DeError> {
+        deserialize_de(self.value.into_de(), visitor)
+    }
+
+    fn deserialize_str<T: serde::de::Visitor<'de>>(self, visitor: T) -> Result<T::Value, DeError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<T: serde::de::Visitor<'de>>(self, visitor: T) -> Result<T::Value, DeError> {
+        match self.value.into_de() {
+            DeValue::Datetime(dt) => visitor.visit_string(dt.to_string()),
+            other => deserialize_de(other, visitor),
+        }
+    }
+
+    fn deserialize_option<T: serde::de::Visitor<'de>>(self, visitor: T) -> Result<T::Value, DeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<T: serde::de::Visitor<'de>>(self, _name: &'static str, visitor: T) -> Result<T::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<T: serde::de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: T,
+    ) -> Result<T::Value, DeError> {
+        match self.value.into_de() {
+            DeValue::String(variant) => visitor.visit_enum(StrDeserializer(variant)),
+            DeValue::Table(t) => {
+                let mut iter = t.into_iter();
+                let (variant, value) = match (iter.next(), iter.next()) {
+                    (Some(entry), None) => entry,
+                    _ => {
+                        return Err(serde::de::Error::invalid_value(
+                            serde::de::Unexpected::Map,
+                            &"a table with exactly one key naming the variant",
+                        ))
+                    }
+                };
+                visitor.visit_enum(EnumDeserializer { variant, value })
+            }
+            other => Err(serde::de::Error::invalid_type(other.unexpected(), &"a string or table naming an enum variant")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// Deserializes a string or key, lending it if it is borrowed, and reads
+/// it as a unit variant when an enum is expected.
+struct StrDeserializer<'de>(Cow<'de, str>);
+
+impl<'de> serde::Deserializer<'de> for StrDeserializer<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<T: serde::de::Visitor<'de>>(self, visitor: T) -> Result<T::Value, DeError> {
+        match self.0 {
+            Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+            Cow::Owned(s) => visitor.visit_string(s),
+        }
+    }
+
+    fn deserialize_enum<T: serde::de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: T,
+    ) -> Result<T::Value, DeError> {
+        visitor.visit_enum(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> serde::de::EnumAccess<'de> for StrDeserializer<'de> {
+    type Error = DeError;
+    type Variant = UnitVariant;
+
+    fn variant_seed<T: serde::de::DeserializeSeed<'de>>(self, seed: T) -> Result<(T::Value, UnitVariant), DeError> {
+        Ok((seed.deserialize(self)?, UnitVariant))
+    }
+}
+
+/// The variant of an enum given as a bare string, which can only be a
+/// unit variant.
+struct UnitVariant;
+
+impl<'de> serde::de::VariantAccess<'de> for UnitVariant {
+    type Error = DeError;
+
+    fn unit_variant(self) -> Result<(), DeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: serde::de::DeserializeSeed<'de>>(self, _seed: T) -> Result<T::Value, DeError> {
+        Err(serde::de::Error::invalid_type(serde::de::Unexpected::UnitVariant, &"newtype variant"))
+    }
+
+    fn tuple_variant<T: serde::de::Visitor<'de>>(self, _len: usize, _visitor: T) -> Result<T::Value, DeError> {
+        Err(serde::de::Error::invalid_type(serde::de::Unexpected::UnitVariant, &"tuple variant"))
+    }
+
+    fn struct_variant<T: serde::de::Visitor<'de>>(self, _fields: &'static [&'static str], _visitor: T) -> Result<T::Value, DeError> {
+        Err(serde::de::Error::invalid_type(serde::de::Unexpected::UnitVariant, &"struct variant"))
+    }
+}
+
+/// Hands a datetime to a visitor as a map with the private datetime
+/// field as its only key and the TOML text as its value.
+struct DatetimeDeserializer(Option<Datetime>);
+
+impl<'de> serde::de::MapAccess<'de> for DatetimeDeserializer {
+    type Error = DeError;
+
+    fn next_key_seed<K: serde::de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, DeError> {
+        match self.0 {
+            Some(_) => seed.deserialize(StrDeserializer(Cow::Borrowed(DATETIME_FIELD))).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<T: serde::de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, DeError> {
+        let dt = self.0.take().ok_or_else(|| <DeError as serde::de::Error>::custom("value is missing"))?;
+        seed.deserialize(StrDeserializer(Cow::Owned(dt.to_string())))
+    }
+}
+
+struct SeqDeserializer<V> {
+    iter: std::vec::IntoIter<V>,
+    index: usize,
+}
+
+impl<'de, V: DeNode<'de>> serde::de::SeqAccess<'de> for SeqDeserializer<V> {
+    type Error = DeError;
+
+    fn next_element_seed<T: serde::de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, DeError> {
+        let index = self.index;
+        self.index += 1;
+        match self.iter.next() {
+            Some(value) => seed.deserialize(ValueDeserializer { value }).map(Some).map_err(|e| e.prepend_key(&index.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapDeserializer<'de, V> {
+    iter: std::vec::IntoIter<(Cow<'de, str>, V)>,
+    value: Option<(Cow<'de, str>, V)>,
+}
+
+impl<'de, V: DeNode<'de>> serde::de::MapAccess<'de> for MapDeserializer<'de, V> {
+    type Error = DeError;
+
+    fn next_key_seed<K: serde::de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, DeError> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                let ret = seed.deserialize(StrDeserializer(key.clone()));
+                self.value = Some((key, value));
+                ret.map(Some)
             }
+            None => Ok(None),
         }
     }
-    Ok(retval)
-}
 
-impl TomlDecoder {
-    pub fn get_empty_inline_table(&self) -> DynamicInlineTableDict {
-        /// Concrete sentinel subclass for inline tables.
-        /// It is a subclass of _dict which is passed in dynamically at load time.
-        /// It is also a subclass of InlineTableDict.
-        struct DynamicInlineTableDict;
+    fn next_value_seed<T: serde::de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, DeError> {
+        let (key, value) = self.value.take().ok_or_else(|| <DeError as serde::de::Error>::custom("value is missing"))?;
+        seed.deserialize(ValueDeserializer { value }).map_err(|e| e.prepend_key(&key))
+    }
 
-        DynamicInlineTableDict
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
     }
 }
 
-impl TomlDecoder {
-    pub fn load_inline_object(
-        &self,
-        line: &str,
-        currentlevel: &mut std::collections::HashMap<String, Box<dyn std::any::Any>>,
-        multikey: bool,
-        multibackslash: bool,
-    ) -> Result<(), String> {
-        let candidate_groups: Vec<&str> = line[1..line.len()-1].split(',').collect();
-        let mut groups: Vec<&str> = Vec::new();
-        let mut candidate_groups = candidate_groups;
-
-        if candidate_groups.len() == 1 && candidate_groups[0].trim().is_empty() {
-            candidate_groups.pop();
-        }
-
-        while !candidate_groups.is_empty() {
-            let candidate_group = candidate_groups.remove(0);
-            let parts: Vec<&str> = candidate_group.splitn(2, '=').collect();
-            if parts.len() != 2 {
-                return Err("Invalid inline table encountered".to_string());
-            }
-            let value = parts[1].trim();
-
-            if (value.chars().next().unwrap() == value.chars().last().unwrap() && 
-                (value.chars().next().unwrap() == '"' || value.chars().next().unwrap() == '\'')) ||
-                value.chars().next().unwrap().is_digit(10) ||
-                value == "true" || value == "false" ||
-                (value.starts_with('[') && value.ends_with(']')) ||
-                (value.starts_with('{') && value.ends_with('}')) {
-                groups.push(candidate_group);
-            } else if !candidate_groups.is_empty() {
-                candidate_groups[0] = format!("{}{}", candidate_group, candidate_groups[0]);
-            } else {
-                return Err("Invalid inline table value encountered".to_string());
-            }
-        }
+struct EnumDeserializer<'de, V> {
+    variant: Cow<'de, str>,
+    value: V,
+}
 
-        for group in groups {
-            let status = self.load_line(group, currentlevel, multikey, multibackslash);
-            if status.is_ok() {
-                break;
-            }
-        }
-        Ok(())
+impl<'de, V: DeNode<'de>> serde::de::EnumAccess<'de> for EnumDeserializer<'de, V> {
+    type Error = DeError;
+    type Variant = VariantDeserializer<'de, V>;
+
+    fn variant_seed<T: serde::de::DeserializeSeed<'de>>(self, seed: T) -> Result<(T::Value, VariantDeserializer<'de, V>), DeError> {
+        let variant = seed.deserialize(StrDeserializer(self.variant.clone()))?;
+        Ok((variant, VariantDeserializer { key: self.variant, value: self.value }))
     }
 }
 
-pub fn _load_unicode_escapes(v: &str, hexbytes: Vec<&str>, prefix: &str) -> Result<String, String> {
-    let mut skip = false;
-    let mut i = v.len() as isize - 1;
-    while i > -1 && v.chars().nth(i as usize).unwrap() == '\\' {
-        skip = !skip;
-        i -= 1;
-    }
-    let mut v = v.to_string();
-    for hx in hexbytes {
-        if skip {
-            skip = false;
-            i = hx.len() as isize - 1;
-            while i > -1 && hx.chars().nth(i as usize).unwrap() == '\\' {
-                skip = !skip;
-                i -= 1;
-            }
-            v.push_str(prefix);
-            v.push_str(hx);
-            continue;
-        }
-        let mut hxb = String::new();
-        let mut i = 0;
-        let hxblen = if prefix == "\\U" { 8 } else { 4 };
-        hxb.push_str(&hx[0..hxblen].to_lowercase());
-        if hxb.chars().any(|c| !c.is_digit(16)) {
-            return Err(format!("Invalid escape sequence: {}", hxb));
-        }
-        if hxb.chars().nth(0).unwrap() == 'd' && hxb.chars().nth(1).unwrap().is_digit(8) {
-            return Err(format!("Invalid escape sequence: {}. Only scalar unicode points are allowed.", hxb));
-        }
-        let codepoint = u32::from_str_radix(&hxb, 16).map_err(|_| "Invalid escape sequence".to_string())?;
-        v.push(char::from_u32(codepoint).ok_or("Invalid unicode character".to_string())?);
-        v.push_str(&hx[hxblen..]);
-    }
-    Ok(v)
-}
-
-pub fn _unescape(v: &str) -> String {
-    /// Unescape characters in a TOML string.
-    let mut i = 0;
-    let mut backslash = false;
-    let mut result = String::new();
-    
-    while i < v.len() {
-        let c = v.chars().nth(i).unwrap();
-        if backslash {
-            backslash = false;
-            if _escapes.contains(&c) {
-                result.push_str(&_escape_to_escapedchars[c]);
-            } else if c == '\\' {
-                result.push(c);
-            } else if c == 'u' || c == 'U' {
-                i += 1;
-            } else {
-                panic!("Reserved escape sequence used");
-            }
-        } else if c == '\\' {
-            backslash = true;
-        } else {
-            result.push(c);
-        }
-        i += 1;
-    }
-    result
+struct VariantDeserializer<'de, V> {
+    key: Cow<'de, str>,
+    value: V,
 }
 
-pub fn _load_date(val: &str) -> Option<chrono::NaiveDateTime> {
-    let mut microsecond = 0;
-    let mut tz = None;
-    if val.len() > 19 {
-        if val.chars().nth(19) == Some('.') {
-            let (subsecondval, tzval) = if val.ends_with('Z') {
-                let subsecondval = &val[20..val.len()-1];
-                (subsecondval, "Z")
-            } else {
-                let subsecondvalandtz = &val[20..];
-                if let Some(splitpoint) = subsecondvalandtz.find('+') {
-                    let subsecondval = &subsecondvalandtz[..splitpoint];
-                    let tzval = &subsecondvalandtz[splitpoint..];
-                    (subsecondval, tzval)
-                } else if let Some(splitpoint) = subsecondvalandtz.find('-') {
-                    let subsecondval = &subsecondvalandtz[..splitpoint];
-                    let tzval = &subsecondvalandtz[splitpoint..];
-                    (subsecondval, tzval)
-                } else {
-                    (subsecondvalandtz, "")
-                }
-            };
-            if !tzval.is_empty() {
-                tz = Some(TomlTz::new(tzval));
-            }
-            microsecond = (subsecondval.parse::<i64>().ok()? * 10_i64.pow(6 - subsecondval.len() as u32)) as u32;
-        } else {
-            tz = Some(TomlTz::new(&val[19..].to_uppercase()));
+impl<'de, V: DeNode<'de>> serde::de::VariantAccess<'de> for VariantDeserializer<'de, V> {
+    type Error = DeError;
+
+    fn unit_variant(self) -> Result<(), DeError> {
+        match self.value.into_de() {
+            DeValue::Table(t) if t.is_empty() => Ok(()),
+            other => Err(serde::de::Error::invalid_type(other.unexpected(), &"unit variant")),
         }
     }
-    if !val[1..].contains('-') {
-        return None;
+
+    fn newtype_variant_seed<T: serde::de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, DeError> {
+        let key = self.key;
+        seed.deserialize(ValueDeserializer { value: self.value }).map_err(|e| e.prepend_key(&key))
+    }
+
+    fn tuple_variant<T: serde::de::Visitor<'de>>(self, _len: usize, visitor: T) -> Result<T::Value, DeError> {
+        let key = self.key;
+        serde::Deserializer::deserialize_seq(ValueDeserializer { value: self.value }, visitor).map_err(|e| e.prepend_key(&key))
     }
-    if val.len() == 10 {
-        let d = chrono::NaiveDate::from_ymd(
-            val[..4].parse().ok()?,
-            val[5..7].parse().ok()?,
-            val[8..10].parse().ok()?,
-        );
-        return Some(d.and_hms(0, 0, 0));
-    } else {
-        let d = chrono::NaiveDateTime::parse_from_str(val, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
-        return Some(d);
+
+    fn struct_variant<T: serde::de::Visitor<'de>>(self, _fields: &'static [&'static str], visitor: T) -> Result<T::Value, DeError> {
+        let key = self.key;
+        serde::Deserializer::deserialize_map(ValueDeserializer { value: self.value }, visitor).map_err(|e| e.prepend_key(&key))
     }
 }
 
-use std::collections::HashMap;
-use std::any::Any;
+impl<'de> serde::Deserialize<'de> for Value {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        struct ValueVisitor;
 
-#[derive(Debug, Clone)]
-pub struct TomlDecoder {
-    _dict: Box<dyn Any>, // Using Box<dyn Any> to represent Type[dict]
-}
+        impl<'de> serde::de::Visitor<'de> for ValueVisitor {
+            type Value = Value;
 
-impl TomlDecoder {
-    /// Creates a new TomlDecoder with the specified dictionary type.
-    pub fn new(_dict: Box<dyn Any>) -> TomlDecoder {
-        TomlDecoder { _dict }
-    }
-
-    /// Returns an empty table as a HashMap.
-    pub fn get_empty_table(&self) -> HashMap<String, Box<dyn Any>> {
-        // Assuming _dict can be cast to a HashMap
-        self._dict.downcast_ref::<HashMap<String, Box<dyn Any>>>().unwrap().clone()
-    }
-
-    /// Returns a new inline table dictionary.
-    pub fn get_empty_inline_table(&self) -> DynamicInlineTableDict {
-        /// Concrete sentinel subclass for inline tables.
-        /// It is a subclass of _dict which is passed in dynamically at load time.
-        struct DynamicInlineTableDict;
-
-        DynamicInlineTableDict
-    }
-
-    /// Loads an inline object from a string line into the current level.
-    pub fn load_inline_object(
-        &self,
-        line: &str,
-        currentlevel: &mut HashMap<String, Box<dyn Any>>,
-        multikey: bool,
-        multibackslash: bool,
-    ) -> Result<(), String> {
-        let candidate_groups: Vec<&str> = line[1..line.len()-1].split(',').collect();
-        let mut groups: Vec<&str> = Vec::new();
-        let mut candidate_groups = candidate_groups;
-
-        if candidate_groups.len() == 1 && candidate_groups[0].trim().is_empty() {
-            candidate_groups.pop();
-        }
-
-        while !candidate_groups.is_empty() {
-            let candidate_group = candidate_groups.remove(0);
-            let parts: Vec<&str> = candidate_group.splitn(2, '=').collect();
-            if parts.len() != 2 {
-                return Err("Invalid inline table encountered".to_string());
-            }
-            let value = parts[1].trim();
-
-            if (value.chars().next().unwrap() == value.chars().last().unwrap() && 
-                (value.chars().next().unwrap() == '"' || value.chars().next().unwrap() == '\'')) ||
-                value.chars().next().unwrap().is_digit(10) ||
-                value == "true" || value == "false" ||
-                (value.starts_with('[') && value.ends_with(']')) ||
-                (value.starts_with('{') && value.ends_with('}')) {
-                groups.push(candidate_group);
-            } else if !candidate_groups.is_empty() {
-                candidate_groups[0] = format!("{}{}", candidate_group, candidate_groups[0]);
-            } else {
-                return Err("Invalid inline table value encountered".to_string());
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("any valid TOML value")
             }
-        }
 
-        for group in groups {
-            let status = self.load_line(group, currentlevel, multikey, multibackslash);
-            if status.is_ok() {
-                break;
+            fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+                Ok(Value::Boolean(v))
             }
-        }
-        Ok(())
-    }
 
-    /// Splits a line on quotes and returns the resulting parts.
-    pub fn _get_split_on_quotes(&self, line: &str) -> Vec<String> {
-        let doublequotesplits: Vec<&str> = line.split('"').collect();
-        let mut quoted = false;
-        let mut quotesplits = Vec::new();
-        
-        if doublequotesplits.len() > 1 && doublequotesplits[0].contains('\'') {
-            let mut singlequotesplits: Vec<&str> = doublequotesplits[0].split('\'').collect();
-            let mut doublequotesplits = &doublequotesplits[1..];
-            while singlequotesplits.len() % 2 == 0 && !doublequotesplits.is_empty() {
-                singlequotesplits.last_mut().unwrap().to_string() += &format!("\"{}", doublequotesplits[0]);
-                doublequotesplits = &doublequotesplits[1..];
-                if singlequotesplits.last().unwrap().contains('\'') {
-                    singlequotesplits = singlequotesplits[..singlequotesplits.len()-1]
-                        .iter()
-                        .chain(singlequotesplits.last().unwrap().split('\''))
-                        .map(|s| s.trim())
-                        .collect();
-                }
-            }
-            quotesplits.extend(singlequotesplits);
-        }
-        
-        for doublequotesplit in doublequotesplits {
-            if quoted {
-                quotesplits.push(doublequotesplit.to_string());
-            } else {
-                quotesplits.extend(doublequotesplit.split('\'').map(|s| s.trim().to_string()));
-            }
-            quoted = !quoted;
-        }
-        quotesplits
-    }
-
-    /// Loads a line into the current level.
-    pub fn load_line(
-        &self,
-        line: &str,
-        currentlevel: &mut HashMap<String, Box<dyn Any>>,
-        multikey: Option<&str>,
-        multibackslash: bool,
-    ) -> Result<(String, String, bool), String> {
-        let mut i = 1;
-        let quotesplits = self._get_split_on_quotes(line);
-        let mut quoted = false;
-        for quotesplit in quotesplits {
-            if !quoted && quotesplit.contains('=') {
-                break;
-            }
-            i += quotesplit.matches('=').count();
-            quoted = !quoted;
-        }
-        let pair: Vec<&str> = line.splitn(i, '=').collect();
-        let mut strictly_valid = _strictly_valid_num(pair.last().unwrap_or(&"")).unwrap_or(false);
-        let mut pair_last = pair.last().unwrap_or(&"").to_string();
-        if _number_with_underscores.is_match(&pair_last) {
-            pair_last = pair_last.replace('_', "");
-        }
-        while !pair_last.is_empty() && !matches!(pair_last.chars().next().unwrap(), ' ' | '\t' | '\'' | '"' | '[' | '{') && pair_last.trim() != "true" && pair_last.trim() != "false" {
-            if pair_last.parse::<f64>().is_ok() {
-                break;
+            fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+                Ok(Value::Integer(v))
             }
-            if _load_date(&pair_last).is_some() {
-                break;
-            }
-            if TIME_RE.is_match(&pair_last) {
-                break;
-            }
-            i += 1;
-            let prev_val = pair_last.clone();
-            let pair: Vec<&str> = line.splitn(i, '=').collect();
-            pair_last = pair.last().unwrap_or(&"").to_string();
-            if prev_val == pair_last {
-                return Err("Invalid date or number".to_string());
-            }
-            if strictly_valid {
-                strictly_valid = _strictly_valid_num(pair_last.as_str()).unwrap_or(false);
-            }
-        }
-        let pair = vec![
-            pair[..pair.len() - 1].join("=").trim().to_string(),
-            pair_last.trim().to_string(),
-        ];
-        let mut key = pair[0].clone();
-        if key.contains('.') {
-            if key.contains('"') || key.contains('\'') {
-                let quotesplits = self._get_split_on_quotes(&key);
-                let mut quoted = false;
-                let mut levels = Vec::new();
-                for quotesplit in quotesplits {
-                    if quoted {
-                        levels.push(quotesplit);
-                    } else {
-                        levels.extend(quotesplit.split('.').map(|s| s.trim().to_string()));
-                    }
-                    quoted = !quoted;
-                }
-                key = levels.pop().unwrap_or_default();
-            } else {
-                key = key.split('.').last().unwrap_or("").trim().to_string();
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Value, E> {
+                i64::try_from(v).map(Value::Integer).map_err(|_| E::custom("u64 value was too large"))
             }
-        } else if (key.chars().next().unwrap() == '"' || key.chars().next().unwrap() == '\'') && (key.chars().last().unwrap() == key.chars().next().unwrap()) {
-            key = _unescape(&key[1..key.len()-1]);
-        }
-        let (k, koffset) = self._load_line_multiline_str(&pair[1])?;
-        let mut multilinestr = String::new();
-        if k > -1 {
-            let mut k = k;
-            while k > -1 && pair[1].chars().nth(k + koffset) == Some('\\') {
-                multibackslash = !multibackslash;
-                k -= 1;
+
+            fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+                Ok(Value::Float(v))
             }
-            multilinestr = if multibackslash {
-                pair[1][..pair[1].len()-1].to_string()
-            } else {
-                format!("{}\n", pair[1])
-            };
-            multikey = Some(&pair[0]);
-        } else {
-            let (value, vtype) = self.load_value(&pair[1], strictly_valid)?;
-        }
-        if currentlevel.contains_key(&key) {
-            return Err("Duplicate keys!".to_string());
-        } else {
-            if let Some(multikey) = multikey {
-                return Ok((multikey.to_string(), multilinestr, multibackslash));
-            } else {
-                currentlevel.insert(key, value);
+
+            fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+                Ok(Value::String(v.to_string()))
             }
-        }
-        Ok((String::new(), String::new(), false))
-    }
 
-    /// Loads a multiline string from a given string.
-    pub fn _load_line_multiline_str(&self, p: &str) -> (i32, usize) {
-        let mut poffset = 0;
-        if p.len() < 3 {
-            return (-1, poffset);
-        }
-        if p.chars().next().unwrap() == '[' && (p.trim().chars().last().unwrap() != ']' && self._load_array_isstrarray(p)) {
-            let mut newp: Vec<&str> = p[1..].trim().split(',').collect();
-            while newp.len() > 1 && !newp.last().unwrap().starts_with('"') && !newp.last().unwrap().starts_with('\'') {
-                let last = newp.pop().unwrap();
-                let second_last = newp.pop().unwrap();
-                newp.push(&format!("{},{}", second_last, last));
+            fn visit_string<E>(self, v: String) -> Result<Value, E> {
+                Ok(Value::String(v))
             }
-            let last = newp.pop().unwrap();
-            poffset = p.len() - last.len();
-            p = last;
-        }
-        if !p.starts_with('"') && !p.starts_with('\'') {
-            return (-1, poffset);
-        }
-        if p.chars().nth(1).unwrap() != p.chars().next().unwrap() || p.chars().nth(2).unwrap() != p.chars().next().unwrap() {
-            return (-1, poffset);
-        }
-        if p.len() > 5 && p.chars().last().unwrap() == p.chars().next().unwrap() && p.chars().nth_back(1).unwrap() == p.chars().next().unwrap() && p.chars().nth_back(2).unwrap() == p.chars().next().unwrap() {
-            return (-1, poffset);
-        }
-        (p.len() as i32 - 1, poffset)
-    }
 
-    /// Loads a value from a string.
-    pub fn load_value(&self, v: &str, strictly_valid: bool) -> Result<Box<dyn Any>, String> {
-        if v.is_empty() {
-            return Err("Empty value is invalid".to_string());
-        }
-        if v == "true" {
-            return Ok(Box::new(true));
-        } else if v.to_lowercase() == "true" {
-            return Err("Only all lowercase booleans allowed".to_string());
-        } else if v == "false" {
-            return Ok(Box::new(false));
-        } else if v.to_lowercase() == "false" {
-            return Err("Only all lowercase booleans allowed".to_string());
-        } else if v.starts_with('"') || v.starts_with('\'') {
-            let quotechar = v.chars().next().unwrap();
-            let testv: Vec<&str> = v[1..].split(quotechar).collect();
-            let mut triplequote = false;
-            let mut triplequotecount = 0;
-            let mut closed = false;
-
-            let mut i = 0;
-            while i < testv.len() {
-                if testv[i].is_empty() {
-                    if triplequote {
-                        triplequotecount += 1;
-                    } else {
-                        closed = true;
-                    }
-                } else {
-                    let mut oddbackslash = false;
-                    let mut j = testv[i].len() as isize - 1;
-                    while j >= 0 {
-                        if testv[i].chars().nth(j as usize).unwrap() == '\\' {
-                            oddbackslash = !oddbackslash;
-                        }
-                        j -= 1;
-                    }
-                    if !oddbackslash {
-                        if closed {
-                            return Err("Found tokens after a closed string. Invalid TOML.".to_string());
-                        } else {
-                            if !triplequote || triplequotecount > 1 {
-                                closed = true;
-                            } else {
-                                triplequotecount = 0;
-                            }
-                        }
-                    }
-                }
-                i += 1;
+            fn visit_some<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+                serde::Deserialize::deserialize(deserializer)
             }
 
-            let mut v = v.to_string();
-            if quotechar == '"' {
-                let escapeseqs: Vec<&str> = v.split('\\').collect();
-                let mut backslash = false;
-                for i in escapeseqs.iter().skip(1) {
-                    if i.is_empty() {
-                        backslash = !backslash;
-                    } else {
-                        if !_escapes.contains(&i.chars().next().unwrap()) && (i.chars().next().unwrap() != 'u' && i.chars().next().unwrap() != 'U' && !backslash) {
-                            return Err("Reserved escape sequence used".to_string());
-                        }
-                        if backslash {
-                            backslash = false;
-                        }
-                    }
-                }
-                for prefix in ["\\u", "\\U"].iter() {
-                    if v.contains(*prefix) {
-                        let hexbytes: Vec<&str> = v.split(*prefix).collect();
-                        v = _load_unicode_escapes(hexbytes[0], hexbytes[1..].to_vec(), *prefix);
-                    }
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+                let mut vec = Vec::new();
+                while let Some(elem) = seq.next_element()? {
+                    vec.push(elem);
                 }
-                v = _unescape(&v);
-            }
-            if v.len() > 1 && v.chars().nth(1).unwrap() == quotechar && (v.len() < 3 || v.chars().nth(1).unwrap() == v.chars().nth(2).unwrap()) {
-                v = v[2..v.len()-2].to_string();
-            }
-            return Ok(Box::new(v[1..v.len()-1].to_string()));
-        } else if v.starts_with('[') {
-            return Ok(Box::new(self.load_array(v)?));
-        } else if v.starts_with('{') {
-            let inline_object = self.get_empty_inline_table();
-            self.load_inline_object(v, inline_object);
-            return Ok(Box::new(inline_object));
-        } else if let Some(captures) = TIME_RE.captures(v) {
-            let h: i32 = captures[1].parse().unwrap();
-            let m: i32 = captures[2].parse().unwrap();
-            let s: i32 = captures[3].parse().unwrap();
-            let ms: i32 = captures[4].parse().unwrap_or(0);
-            let time = chrono::NaiveTime::from_hms_milli(h, m, s, ms);
-            return Ok(Box::new(time));
-        } else {
-            if let Some(parsed_date) = _load_date(v) {
-                return Ok(Box::new(parsed_date));
-            }
-            if !strictly_valid {
-                return Err("Weirdness with leading zeroes or underscores in your number.".to_string());
+                Ok(Value::Array(vec))
             }
-            let mut itype = "int";
-            let mut neg = false;
-            let mut v = v.to_string();
-            if v.starts_with('-') {
-                neg = true;
-                v = v[1..].to_string();
-            } else if v.starts_with('+') {
-                v = v[1..].to_string();
-            }
-            v = v.replace('_', "");
-            let lowerv = v.to_lowercase();
-            if v.contains('.') || (!v.contains('x') && (v.contains('e') || v.contains('E'))) {
-                if v.contains('.') && v.split('.').nth(1).unwrap().is_empty() {
-                    return Err("This float is missing digits after the point".to_string());
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+                let mut table = Table::ordered();
+                let first: Option<String> = map.next_key()?;
+                if first.as_deref() == Some(DATETIME_FIELD) {
+                    let text: String = map.next_value()?;
+                    return text.parse().map(Value::Datetime).map_err(serde::de::Error::custom);
                 }
-                if !v.chars().next().unwrap().is_digit(10) {
-                    return Err("This float doesn't have a leading digit".to_string());
+                if let Some(key) = first {
+                    table.insert(key, map.next_value()?);
                 }
-                let v = v.parse::<f64>().unwrap();
-                itype = "float";
-            } else if lowerv.len() == 3 && (lowerv == "inf" || lowerv == "nan") {
-                let v = v.parse::<f64>().unwrap();
-                itype = "float";
-            }
-            if itype == "int" {
-                let v = i64::from_str_radix(&v, 0).unwrap();
-                if neg {
-                    return Ok(Box::new(-v));
+                while let Some((key, value)) = map.next_entry()? {
+                    table.insert(key, value);
                 }
-                return Ok(Box::new(v));
+                Ok(Value::Table(table))
             }
         }
-        Err("Invalid value".to_string())
+
+        deserializer.deserialize_any(ValueVisitor)
     }
+}
 
-    /// Checks if a string is bounded.
-    pub fn bounded_string(&self, s: &str) -> bool {
-        if s.is_empty() {
-            return true;
-        }
-        if s.chars().last().unwrap() != s.chars().next().unwrap() {
-            return false;
-        }
-        let mut i = -2;
-        let mut backslash = false;
-        while s.len() as isize + i > 0 {
-            if s.chars().nth((s.len() as isize + i) as usize).unwrap() == '\\' {
-                backslash = !backslash;
-                i -= 1;
-            } else {
-                break;
-            }
+/// Error returned when serializing a typed value to TOML.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerError {
+    /// The type has no TOML representation (unit, bytes of a non-table root, ...).
+    UnsupportedType(&'static str),
+    /// A map key serialized to something other than a string.
+    KeyNotString,
+    /// `None` appeared somewhere other than a table field, e.g. in an
+    /// array. `key` is the path to it, outermost key first; it is empty
+    /// while the `None` is still the value being serialized.
+    UnsupportedNone { key: Vec<String> },
+    /// An unsigned integer did not fit into TOML's 64-bit signed integers.
+    IntegerOutOfRange(u64),
+    /// A custom error raised by a `Serialize` implementation.
+    Custom(String),
+}
+
+impl std::fmt::Display for SerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SerError::UnsupportedType(t) => write!(f, "unsupported {} type", t),
+            SerError::KeyNotString => write!(f, "map key was not a string"),
+            SerError::UnsupportedNone { key } if key.is_empty() => write!(f, "unsupported None value"),
+            SerError::UnsupportedNone { key } => write!(f, "unsupported None value for key `{}`", key.join(".")),
+            SerError::IntegerOutOfRange(v) => write!(f, "integer {} does not fit in an i64", v),
+            SerError::Custom(msg) => write!(f, "{}", msg),
         }
-        !backslash
     }
+}
 
-    /// Checks if a string array is a string array.
-    pub fn _load_array_isstrarray(&self, a: &str) -> bool {
-        let a = a[1..a.len()-1].trim();
-        if !a.is_empty() && (a.chars().next().unwrap() == '"' || a.chars().next().unwrap() == '\'') {
-            return true;
+impl SerError {
+    fn prepend_key(mut self, key: &str) -> SerError {
+        if let SerError::UnsupportedNone { key: path } = &mut self {
+            path.insert(0, key.to_string());
         }
-        false
+        self
     }
+}
 
-    /// Loads an array from a string.
-    pub fn load_array(&self, a: &str) -> Vec<Option<Box<dyn Any>>> {
-        let mut retval: Vec<Option<Box<dyn Any>>> = Vec::new();
-        let a = a.trim();
-        if !a[1..a.len()-1].contains('[') || a[1..a.len()-1].split('[').next().unwrap().trim() != "" {
-            let strarray = self._load_array_isstrarray(a);
-            let mut a = if !a[1..a.len()-1].trim().starts_with('{') {
-                a[1..a.len()-1].split(',').map(|s| s.to_string()).collect::<Vec<String>>()
-            } else {
-                let mut new_a: Vec<String> = Vec::new();
-                let mut start_group_index = 1;
-                let mut end_group_index = 2;
-                let mut open_bracket_count = if a.chars().nth(start_group_index).unwrap() == '{' { 1 } else { 0 };
-                let mut in_str = false;
-
-                while end_group_index < a.len() - 1 {
-                    if a.chars().nth(end_group_index).unwrap() == '"' || a.chars().nth(end_group_index).unwrap() == '\'' {
-                        if in_str {
-                            let mut backslash_index = end_group_index - 1;
-                            while backslash_index > 0 && a.chars().nth(backslash_index).unwrap() == '\\' {
-                                in_str = !in_str;
-                                backslash_index -= 1;
-                            }
-                        }
-                        in_str = !in_str;
-                    }
-                    if !in_str && a.chars().nth(end_group_index).unwrap() == '{' {
-                        open_bracket_count += 1;
-                    }
-                    if in_str || a.chars().nth(end_group_index).unwrap() != '}' {
-                        end_group_index += 1;
-                        continue;
-                    } else if a.chars().nth(end_group_index).unwrap() == '}' && open_bracket_count > 1 {
-                        open_bracket_count -= 1;
-                        end_group_index += 1;
-                        continue;
-                    }
+impl std::error::Error for SerError {}
 
-                    end_group_index += 1;
-                    new_a.push(a[start_group_index..end_group_index].to_string());
-                    start_group_index = end_group_index + 1;
-                    while start_group_index < a.len() - 1 && a.chars().nth(start_group_index).unwrap() != '{' {
-                        start_group_index += 1;
-                    }
-                    end_group_index = start_group_index + 1;
-                }
-                new_a
-            };
+impl serde::ser::Error for SerError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        SerError::Custom(msg.to_string())
+    }
+}
 
-            let mut b = 0;
-            if strarray {
-                while b < a.len() - 1 {
-                    let mut ab = a[b].trim().to_string();
-                    while !self.bounded_string(&ab) || (ab.len() > 2 && ab.chars().nth(0).unwrap() == ab.chars().nth(1).unwrap() && ab.chars().nth(2).unwrap() == ab.chars().nth(0).unwrap() && ab.chars().nth(ab.len() - 2).unwrap() != ab.chars().nth(0).unwrap() && ab.chars().nth(ab.len() - 3).unwrap() != ab.chars().nth(0).unwrap()) {
-                        a[b] = format!("{}{}", a[b], a[b + 1]);
-                        ab = a[b].trim().to_string();
-                        if b < a.len() - 2 {
-                            a.remove(b + 1);
-                        } else {
-                            a.pop();
-                        }
-                    }
-                    b += 1;
-                }
-            }
-        } else {
-            let al: Vec<char> = a[1..a.len()-1].chars().collect();
-            let mut a: Vec<String> = Vec::new();
-            let mut openarr = 0;
-            let mut j = 0;
-            for i in 0..al.len() {
-                if al[i] == '[' {
-                    openarr += 1;
-                } else if al[i] == ']' {
-                    openarr -= 1;
-                } else if al[i] == ',' && openarr == 0 {
-                    a.push(al[j..i].iter().collect());
-                    j = i + 1;
-                }
-            }
-            a.push(al[j..].iter().collect());
-        }
-        for i in 0..a.len() {
-            let trimmed = a[i].trim();
-            if !trimmed.is_empty() {
-                let (nval, _ntype) = self.load_value(trimmed);
-                retval.push(nval);
-            }
-        }
-        retval
+/// Serializes `value` into a `Value` tree.
+pub fn to_value<T: serde::Serialize + ?Sized>(value: &T) -> Result<Value, SerError> {
+    value.serialize(ValueSerializer)
+}
+
+/// Serializes `value` as a TOML document. `value` must serialize to a
+/// table, i.e. be a struct or a map.
+pub fn to_string<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, SerError> {
+    match to_value(value)? {
+        table @ Value::Table(_) => Ok(dumps(&table, None)),
+        _ => Err(SerError::UnsupportedType("non-table root")),
+    }
+}
+
+/// A serde `Serializer` producing a `Value` tree.
+///
+/// Structs and maps become tables, sequences become arrays, `None` fields
+/// are left out of their table, and enums use the same external tagging
+/// as `ValueDeserializer`: unit variants are strings, every other variant
+/// is a single-key table.
+pub struct ValueSerializer;
+
+impl serde::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = SerError;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeTable;
+    type SerializeStruct = SerializeTable;
+    type SerializeStructVariant = SerializeVariant<SerializeTable>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, SerError> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, SerError> {
+        Ok(Value::Integer(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, SerError> {
+        Ok(Value::Integer(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, SerError> {
+        Ok(Value::Integer(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, SerError> {
+        Ok(Value::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, SerError> {
+        Ok(Value::Integer(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, SerError> {
+        Ok(Value::Integer(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, SerError> {
+        Ok(Value::Integer(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, SerError> {
+        i64::try_from(v).map(Value::Integer).map_err(|_| SerError::IntegerOutOfRange(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, SerError> {
+        Ok(Value::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, SerError> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, SerError> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, SerError> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, SerError> {
+        Ok(Value::Array(v.iter().map(|&b| Value::Integer(b.into())).collect()))
     }
 
-    /// Preserves a comment associated with a key.
-    pub fn preserve_comment(&self, line_no: i32, key: &str, comment: &str, beginline: bool) {
-        // Implementation omitted
+    fn serialize_none(self) -> Result<Value, SerError> {
+        Err(SerError::UnsupportedNone { key: Vec::new() })
     }
 
-    /// Embeds comments into the current level.
-    pub fn embed_comments(&self, idx: i32, currentlevel: &mut HashMap<String, Box<dyn Any>>) {
-        // Implementation omitted
+    fn serialize_some<T: serde::Serialize + ?Sized>(self, value: &T) -> Result<Value, SerError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, SerError> {
+        Err(SerError::UnsupportedType("unit"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, SerError> {
+        Err(SerError::UnsupportedType("unit struct"))
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str) -> Result<Value, SerError> {
+        Ok(Value::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: serde::Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Value, SerError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: serde::Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, SerError> {
+        let mut table = Table::new();
+        table.insert(variant.to_string(), value.serialize(self).map_err(|e| e.prepend_key(variant))?);
+        Ok(Value::Table(table))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, SerError> {
+        Ok(SerializeArray {
+            array: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, SerError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray, SerError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeArray>, SerError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeTable, SerError> {
+        Ok(SerializeTable {
+            table: Table::ordered(),
+            next_key: None,
+            datetime: false,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<SerializeTable, SerError> {
+        let mut table = self.serialize_map(Some(len))?;
+        table.datetime = name == DATETIME_NAME;
+        Ok(table)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeTable>, SerError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct TomlTz {
-    _raw_offset: String,
-    _sign: i32,
-    _hours: i32,
-    _minutes: i32,
+/// Collects the elements of a sequence or tuple into a `Value::Array`.
+pub struct SerializeArray {
+    array: Vec<Value>,
 }
 
-impl TomlTz {
-    /// Creates a new TomlTz instance from a toml_offset string.
-    pub fn new(toml_offset: &str) -> Self {
-        let mut raw_offset = String::new();
-        if toml_offset == "Z" {
-            raw_offset = "+00:00".to_string();
-        } else {
-            raw_offset = toml_offset.to_string();
+impl serde::ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = SerError;
+
+    fn serialize_element<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerError> {
+        let index = self.array.len();
+        self.array.push(value.serialize(ValueSerializer).map_err(|e| e.prepend_key(&index.to_string()))?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, SerError> {
+        Ok(Value::Array(self.array))
+    }
+}
+
+impl serde::ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = SerError;
+
+    fn serialize_element<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerError> {
+        serde::ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, SerError> {
+        serde::ser::SerializeSeq::end(self)
+    }
+}
+
+impl serde::ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = SerError;
+
+    fn serialize_field<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerError> {
+        serde::ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, SerError> {
+        serde::ser::SerializeSeq::end(self)
+    }
+}
+
+/// Collects the entries of a map or struct into an ordered `Value::Table`,
+/// so that fields are dumped in declaration order. The private datetime
+/// struct becomes a `Value::Datetime` instead.
+pub struct SerializeTable {
+    table: Table,
+    next_key: Option<String>,
+    datetime: bool,
+}
+
+impl SerializeTable {
+    fn insert<T: serde::Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), SerError> {
+        match value.serialize(ValueSerializer) {
+            Ok(value) => {
+                self.table.insert(key, value);
+                Ok(())
+            }
+            // Only a field that is itself `None` is left out; a `None`
+            // nested further down already carries part of its key path.
+            Err(SerError::UnsupportedNone { key: path }) if path.is_empty() => Ok(()),
+            Err(e) => Err(e.prepend_key(&key)),
         }
-        let sign = if raw_offset.chars().next().unwrap() == '-' { -1 } else { 1 };
-        let hours = raw_offset[1..3].parse::<i32>().unwrap();
-        let minutes = raw_offset[4..6].parse::<i32>().unwrap();
+    }
+}
 
-        TomlTz {
-            _raw_offset: raw_offset,
-            _sign: sign,
-            _hours: hours,
-            _minutes: minutes,
+impl serde::ser::SerializeMap for SerializeTable {
+    type Ok = Value;
+    type Error = SerError;
+
+    fn serialize_key<T: serde::Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerError> {
+        match key.serialize(ValueSerializer)? {
+            Value::String(key) => {
+                self.next_key = Some(key);
+                Ok(())
+            }
+            _ => Err(SerError::KeyNotString),
         }
     }
 
-    /// Returns the raw offset as a string.
-    pub fn get_raw_offset(&self) -> &String {
-        &self._raw_offset
+    fn serialize_value<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerError> {
+        let key = self.next_key.take().ok_or_else(|| SerError::Custom("serialize_value called before serialize_key".to_string()))?;
+        self.insert(key, value)
     }
 
-    /// Returns the initialization arguments for the TomlTz instance.
-    pub fn get_init_args(&self) -> (String,) {
-        (self._raw_offset.clone(),)
+    fn end(self) -> Result<Value, SerError> {
+        Ok(Value::Table(self.table))
     }
+}
 
-    /// Creates a deep copy of the TomlTz instance.
-    pub fn deepcopy(&self) -> TomlTz {
-        TomlTz {
-            _raw_offset: self._raw_offset.clone(),
-            _sign: self._sign,
-            _hours: self._hours,
-            _minutes: self._minutes,
+impl serde::ser::SerializeStruct for SerializeTable {
+    type Ok = Value;
+    type Error = SerError;
+
+    fn serialize_field<T: serde::Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerError> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(mut self) -> Result<Value, SerError> {
+        if !self.datetime {
+            return Ok(Value::Table(self.table));
+        }
+        match self.table.remove(DATETIME_FIELD) {
+            Some(Value::String(text)) => text.parse().map(Value::Datetime).map_err(|e: TomlDecodeError| SerError::Custom(e.to_string())),
+            _ => Err(SerError::Custom("datetime is missing its text".to_string())),
         }
     }
+}
 
-    /// Returns the timezone name in UTC format.
-    pub fn tzname(&self) -> String {
-        format!("UTC{}", self._raw_offset)
+/// Wraps the serialized fields of a tuple or struct variant in a
+/// single-key table named after the variant.
+pub struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl SerializeVariant<SerializeArray> {
+    fn wrap(variant: &'static str, value: Value) -> Value {
+        let mut table = Table::new();
+        table.insert(variant.to_string(), value);
+        Value::Table(table)
     }
+}
 
-    /// Returns the UTC offset as a Duration.
-    pub fn utcoffset(&self) -> chrono::Duration {
-        chrono::Duration::hours(self._sign as i64 * self._hours as i64) +
-        chrono::Duration::minutes(self._sign as i64 * self._minutes as i64)
+impl serde::ser::SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Value;
+    type Error = SerError;
+
+    fn serialize_field<T: serde::Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerError> {
+        serde::ser::SerializeSeq::serialize_element(&mut self.inner, value).map_err(|e| e.prepend_key(self.variant))
     }
 
-    /// Returns the daylight saving time offset, which is always zero.
-    pub fn dst(&self) -> chrono::Duration {
-        chrono::Duration::zero()
+    fn end(self) -> Result<Value, SerError> {
+        let value = serde::ser::SerializeSeq::end(self.inner)?;
+        Ok(SerializeVariant::wrap(self.variant, value))
     }
 }
 
-pub fn _load_date(val: &str) -> Option<chrono::NaiveDateTime> {
-    let mut microsecond = 0;
-    let mut tz = None;
-    if val.len() > 19 {
-        if val.chars().nth(19) == Some('.') {
-            let (subsecondval, tzval) = if val.ends_with('Z') {
-                let subsecondval = &val[20..val.len()-1];
-                (subsecondval, "Z")
-            } else {
-                let subsecondvalandtz = &val[20..];
-                if let Some(splitpoint) = subsecondvalandtz.find('+') {
-                    let subsecondval = &subsecondvalandtz[..splitpoint];
-                    let tzval = &subsecondvalandtz[splitpoint..];
-                    (subsecondval, tzval)
-                } else if let Some(splitpoint) = subsecondvalandtz.find('-') {
-                    let subsecondval = &subsecondvalandtz[..splitpoint];
-                    let tzval = &subsecondvalandtz[splitpoint..];
-                    (subsecondval, tzval)
-                } else {
-                    (subsecondvalandtz, "")
+impl serde::ser::SerializeStructVariant for SerializeVariant<SerializeTable> {
+    type Ok = Value;
+    type Error = SerError;
+
+    fn serialize_field<T: serde::Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), SerError> {
+        self.inner.insert(key.to_string(), value).map_err(|e| e.prepend_key(self.variant))
+    }
+
+    fn end(self) -> Result<Value, SerError> {
+        let value = serde::ser::SerializeStruct::end(self.inner)?;
+        Ok(SerializeVariant::wrap(self.variant, value))
+    }
+}
+
+impl serde::Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+        match self {
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Integer(i) => serializer.serialize_i64(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::Boolean(b) => serializer.serialize_bool(*b),
+            Value::Datetime(dt) => serde::Serialize::serialize(dt, serializer),
+            Value::Array(a) => {
+                let mut seq = serializer.serialize_seq(Some(a.len()))?;
+                for v in a {
+                    seq.serialize_element(v)?;
                 }
-            };
-            if !tzval.is_empty() {
-                tz = Some(TomlTz::new(tzval));
+                seq.end()
+            }
+            Value::Table(t) => {
+                let mut map = serializer.serialize_map(Some(t.len()))?;
+                for (k, v) in t {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
             }
-            microsecond = (subsecondval.parse::<i64>().ok()? * 10_i64.pow(6 - subsecondval.len() as u32)) as u32;
-        } else {
-            tz = Some(TomlTz::new(&val[19..].to_uppercase()));
         }
     }
-    if !val[1..].contains('-') {
-        return None;
-    }
-    if val.len() == 10 {
-        let d = chrono::NaiveDate::from_ymd(
-            val[..4].parse().ok()?,
-            val[5..7].parse().ok()?,
-            val[8..10].parse().ok()?,
-        );
-        return Some(d.and_hms(0, 0, 0));
-    } else {
-        let d = chrono::NaiveDateTime::parse_from_str(val, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
-        return Some(d);
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,68 @@
+//! Zero-copy decoding with `loads_borrowed` and borrowing serde fields.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use toml_rust::{from_bytes, from_str, loads, loads_borrowed, BorrowedValue, Value};
+
+#[test]
+fn keys_and_plain_strings_are_borrowed() {
+    let src = "a = \"plain\"\nb = 'lit'\nc = \"esc\\n\"\nd = \"\"\"\r\nx\r\ny\"\"\"\n\"q\" = 1\n[t.u]\nv = [\"in\", 2]\n";
+    let v = loads_borrowed(src).unwrap();
+    let BorrowedValue::Table(t) = &v else { panic!("the root is a table") };
+    assert!(matches!(t.get("a"), Some(BorrowedValue::String(Cow::Borrowed("plain")))));
+    assert!(matches!(t.get("b"), Some(BorrowedValue::String(Cow::Borrowed("lit")))));
+    assert!(matches!(t.get("c"), Some(BorrowedValue::String(Cow::Owned(s))) if s == "esc\n"));
+    assert!(matches!(t.get("d"), Some(BorrowedValue::String(Cow::Owned(s))) if s == "x\ny"));
+    assert!(t.keys().all(|k| matches!(k, Cow::Borrowed(_))));
+    let array = v.get("t").and_then(|t| t.get("u")).and_then(|u| u.get("v")).and_then(|a| a.as_array()).unwrap();
+    assert!(matches!(array[0], BorrowedValue::String(Cow::Borrowed("in"))));
+    assert_eq!(v.into_owned(), Value::Table(loads(src, None, None).unwrap()));
+}
+
+#[test]
+fn borrowed_decoding_checks_the_document() {
+    assert!(loads_borrowed("a = 1\na = 2").is_err());
+    assert!(loads_borrowed("[t]\n[t]\n").is_err());
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+enum Kind {
+    A,
+    B(i64),
+}
+
+#[derive(Deserialize, Debug)]
+struct Config<'a> {
+    name: &'a str,
+    #[serde(borrow)]
+    map: HashMap<&'a str, &'a str>,
+    kind: Kind,
+    n: i64,
+}
+
+#[test]
+fn serde_fields_borrow_from_the_input() {
+    let src = "name = \"x\"\nkind = \"A\"\nn = 3\n[map]\nk = \"v\"\n";
+    let config: Config = from_str(src).unwrap();
+    assert_eq!(config.name, "x");
+    assert_eq!(config.map["k"], "v");
+    assert_eq!(config.kind, Kind::A);
+    assert_eq!(config.n, 3);
+    let config: Config = from_bytes(src.as_bytes()).unwrap();
+    assert_eq!(config.name, "x");
+
+    #[derive(Deserialize)]
+    struct Tagged {
+        kind: Kind,
+    }
+    assert_eq!(from_str::<Tagged>("[kind]\nB = 5\n").unwrap().kind, Kind::B(5));
+}
+
+#[test]
+fn escaped_strings_cannot_fill_borrowed_fields() {
+    let err = from_str::<Config>("name = \"a\\tb\"\nkind = \"A\"\nn = 1\n[map]\n").unwrap_err();
+    assert!(err.to_string().contains("for key `name`"), "{}", err);
+    assert_eq!(err.line_col(), Some((1, 8)));
+}
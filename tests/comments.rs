@@ -0,0 +1,101 @@
+//! Keeping comments with `TomlPreserveCommentDecoder` and writing them
+//! back with `TomlPreserveCommentEncoder`.
+//!
+//! Both are deprecated in favour of `Document`, but still supported.
+#![allow(deprecated)]
+
+use toml_rust::{dumps, loads, CommentValue, Table, TomlEncoder, TomlPreserveCommentDecoder, TomlPreserveCommentEncoder, Value};
+
+const SRC: &str = r#"# Leading for title
+title = "x" # trailing title
+
+# about owner
+[owner] # owner header
+name = "Tom"
+nums = [ # first
+  1, # one
+  # before two
+  2,
+  3 # three
+  # closing
+]
+
+[[fruit]]
+# apple name
+name = "apple"
+
+[[fruit]] # second fruit
+name = "banana"
+
+[a.b]
+c = 1 # c
+
+# the end
+"#;
+
+fn path(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn comments_are_attached_to_keys_headers_and_elements() {
+    let dec = TomlPreserveCommentDecoder::new(Some(Table::ordered));
+    loads(SRC, None, Some(&dec)).unwrap();
+    let c = dec.comments();
+    assert_eq!(c[&path(&["title"])].leading(), &["# Leading for title".to_string()]);
+    assert_eq!(c[&path(&["title"])].trailing(), Some("# trailing title"));
+    assert_eq!(c[&path(&["title"])].value(), &Value::String("x".into()));
+    assert_eq!(c[&path(&["owner"])].leading(), &["# about owner".to_string()]);
+    assert_eq!(c[&path(&["owner"])].trailing(), Some("# owner header"));
+    assert_eq!(c[&path(&["owner", "nums", "0"])].leading(), &["# first".to_string()]);
+    assert_eq!(c[&path(&["owner", "nums", "0"])].trailing(), Some("# one"));
+    assert_eq!(c[&path(&["owner", "nums", "1"])].leading(), &["# before two".to_string()]);
+    assert_eq!(c[&path(&["owner", "nums", "2"])].trailing(), Some("# three"));
+    assert_eq!(c[&path(&["owner", "nums"])].closing(), &["# closing".to_string()]);
+    assert_eq!(c[&path(&["fruit", "0", "name"])].leading(), &["# apple name".to_string()]);
+    assert_eq!(c[&path(&["fruit", "1"])].trailing(), Some("# second fruit"));
+    assert_eq!(c[&path(&["a", "b", "c"])].trailing(), Some("# c"));
+    assert_eq!(c[&path(&[])].closing(), &["# the end".to_string()]);
+}
+
+/// The comments of a path: leading, trailing and closing.
+type Comments = (Vec<String>, Option<String>, Vec<String>);
+
+fn sorted_comments(dec: &TomlPreserveCommentDecoder) -> Vec<(Vec<String>, Comments)> {
+    let mut comments: Vec<_> = dec
+        .comments()
+        .into_iter()
+        .map(|(k, v)| (k, (v.leading().to_vec(), v.trailing().map(str::to_string), v.closing().to_vec())))
+        .collect();
+    comments.sort();
+    comments
+}
+
+#[test]
+fn encoder_writes_comments_back() {
+    let dec = TomlPreserveCommentDecoder::new(Some(Table::ordered));
+    let t = loads(SRC, None, Some(&dec)).unwrap();
+    let enc = TomlPreserveCommentEncoder::new(Some(Table::ordered), false, dec.comments());
+    let out = dumps(&Value::Table(t.clone()), Some(&enc));
+    let dec2 = TomlPreserveCommentDecoder::new(Some(Table::ordered));
+    assert_eq!(loads(&out, None, Some(&dec2)).unwrap(), t);
+    assert_eq!(sorted_comments(&dec2), sorted_comments(&dec), "{}", out);
+    assert_eq!(dumps(&Value::Table(t), None).matches('#').count(), 0);
+}
+
+#[test]
+fn decoder_forgets_comments_of_the_previous_document() {
+    let dec = TomlPreserveCommentDecoder::new(None);
+    loads(SRC, None, Some(&dec)).unwrap();
+    assert!(!dec.comments().is_empty());
+    loads("x = 1\n", None, Some(&dec)).unwrap();
+    assert!(dec.comments().is_empty());
+}
+
+#[test]
+fn comment_value_dumps_its_comment() {
+    let cv = CommentValue::new(Value::Integer(1), "# hi".into(), false);
+    assert_eq!(cv.dump(&|v| TomlEncoder::default().dump_value(v)), "1 # hi");
+    let cv = CommentValue::new(Value::Integer(1), "hi".into(), true);
+    assert_eq!(cv.dump(&|v| TomlEncoder::default().dump_value(v)), "# hi\n1");
+}
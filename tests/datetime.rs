@@ -0,0 +1,83 @@
+//! The `Datetime` type: exact text round-trips, chrono conversions and
+//! serde.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, Timelike};
+use serde::{Deserialize, Serialize};
+use toml_rust::{dumps, from_str, loads, to_string, to_value, Datetime, DatetimeFormat, Table, Value};
+
+#[test]
+fn text_round_trips() {
+    for s in [
+        "1979-05-27T07:32:00Z",
+        "1979-05-27T00:32:00.999999999-07:00",
+        "1979-05-27T00:32:00.5",
+        "1979-05-27",
+        "07:32:00",
+        "00:32:00.000000001",
+        "1979-05-27T07:32:00+05:30",
+    ] {
+        assert_eq!(s.parse::<Datetime>().unwrap().to_string(), s);
+    }
+    assert!("1979-13-27".parse::<Datetime>().is_err());
+}
+
+#[test]
+fn non_canonical_text_round_trips() {
+    for s in [
+        "1979-05-27T07:32:00+00:00",
+        "1979-05-27T07:32:00-00:00",
+        "1979-05-27T07:32:00z",
+        "1979-05-27 07:32:00z",
+        "1979-05-27t07:32:00Z",
+        "1979-05-27T07:32:00.500Z",
+        "1979-05-27 07:32:00.000",
+        "07:32:00.10",
+        "1979-05-27T07:32:00.123456789Z",
+    ] {
+        assert_eq!(s.parse::<Datetime>().unwrap().to_string(), s);
+        let t = loads(&format!("a = {}\n", s), None, None).unwrap();
+        assert_eq!(dumps(&Value::Table(t), None), format!("a = {}\n", s));
+    }
+}
+
+#[test]
+fn formats_do_not_change_the_value() {
+    let parse = |s: &str| s.parse::<Datetime>().unwrap();
+    assert_eq!(parse("1979-05-27T07:32:00+00:00"), parse("1979-05-27 07:32:00.000z"));
+    assert_eq!(parse("1979-05-27T07:32:00.5-00:00"), parse("1979-05-27T07:32:00.500Z"));
+    assert_eq!(parse("07:32:00.5"), parse("07:32:00.50"));
+    assert_ne!(parse("1979-05-27T07:32:00"), parse("1979-05-27T07:32:00Z"));
+    assert_eq!(parse("1979-05-27 07:32:00.50z").format(), DatetimeFormat { separator: ' ', fraction_digits: Some(2) });
+
+    let dt: DateTime<FixedOffset> = parse("1979-05-27t07:32:00.500+00:00").try_into().unwrap();
+    assert_eq!(Datetime::from(dt).to_string(), "1979-05-27T07:32:00.5Z");
+}
+
+#[test]
+fn decoded_kinds_and_precision() {
+    let t = loads("a = 1979-05-27\nb = 1979-05-27T07:32:00.1234567891Z", Some(Table::ordered), None).unwrap();
+    assert_eq!(t["a"].as_datetime().unwrap().type_str(), "local date");
+    let b: DateTime<FixedOffset> = (*t["b"].as_datetime().unwrap()).try_into().unwrap();
+    assert_eq!(b.nanosecond(), 123456789);
+    assert!(NaiveDate::try_from(Datetime::from(b)).is_err());
+    assert_eq!(dumps(&Value::Table(t), None), "a = 1979-05-27\nb = 1979-05-27T07:32:00.123456789Z\n");
+}
+
+#[test]
+fn serde_round_trips() {
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Release {
+        date: Datetime,
+        at: Datetime,
+        times: Vec<Datetime>,
+    }
+    let doc = "date = 1979-05-27\nat = 1979-05-27T07:32:00-07:00\ntimes = [07:32:00, 00:32:00.5]\n";
+    let release: Release = from_str(doc).unwrap();
+    assert_eq!(release.date, "1979-05-27".parse().unwrap());
+    assert!(matches!(to_value(&release.at).unwrap(), Value::Datetime(_)));
+    assert_eq!(from_str::<Release>(&to_string(&release).unwrap()).unwrap(), release);
+
+    assert!(from_str::<Release>("date = 1\nat = 1979-05-27\ntimes = []\n").is_err());
+    let json = serde_json::to_string(&release.at).unwrap();
+    assert_eq!(serde_json::from_str::<Datetime>(&json).unwrap(), release.at);
+}
@@ -0,0 +1,116 @@
+//! Deserializing typed values with `from_str` and friends.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use toml_rust::{from_str, DeError, Value};
+
+#[derive(Debug, Deserialize, PartialEq)]
+enum Mode {
+    Fast,
+    Slow,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+enum Shape {
+    Circle { r: f64 },
+    Square(i64),
+    Point,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Server {
+    host: String,
+    port: u16,
+    tags: Vec<String>,
+    mode: Mode,
+    shape: Shape,
+    opt: Option<i32>,
+    missing: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Config {
+    title: String,
+    server: Server,
+    shapes: Vec<Shape>,
+    map: HashMap<String, i64>,
+    when: chrono::NaiveDate,
+}
+
+const CONFIG: &str = r#"
+title = "x"
+when = 1979-05-27
+[server]
+host = "h"
+port = 80
+tags = ["a", "b"]
+mode = "Fast"
+opt = 3
+[server.shape.Circle]
+r = 1.5
+[map]
+a = 1
+[[shapes]]
+Square = 4
+[[shapes]]
+Point = {}
+"#;
+
+#[test]
+fn struct_with_enums_and_options() {
+    let config: Config = from_str(CONFIG).unwrap();
+    assert_eq!(config.title, "x");
+    assert_eq!(config.when, chrono::NaiveDate::from_ymd_opt(1979, 5, 27).unwrap());
+    assert_eq!(config.server.mode, Mode::Fast);
+    assert_eq!(config.server.tags, ["a", "b"]);
+    assert_eq!(config.server.shape, Shape::Circle { r: 1.5 });
+    assert_eq!(config.shapes, vec![Shape::Square(4), Shape::Point]);
+    assert_eq!(config.server.opt, Some(3));
+    assert_eq!(config.server.missing, None);
+    assert_eq!(config.map["a"], 1);
+}
+
+#[test]
+fn field_errors_name_their_key() {
+    let err = from_str::<Config>("title = \"x\"\nwhen = 1979-05-27\n[server]\nport = \"a\"").unwrap_err();
+    assert!(matches!(&err, DeError::Custom { key, .. } if key == &["server", "port"]));
+    assert!(err.to_string().contains("for key `server.port`"), "{}", err);
+}
+
+#[test]
+fn sequence_errors_name_their_index() {
+    #[derive(Debug, Deserialize)]
+    struct Doc {
+        #[allow(dead_code)]
+        a: Vec<i64>,
+    }
+    let err = from_str::<Doc>("a = [1, 2, \"x\"]").unwrap_err();
+    assert!(matches!(&err, DeError::Custom { key, .. } if key == &["a", "2"]), "{:?}", err);
+    assert_eq!(err.line_col(), Some((1, 12)));
+}
+
+#[test]
+fn type_errors_report_line_and_column() {
+    let doc = "title = \"x\"\nwhen = 1979-05-27\n[server]\nhost = \"h\"\nport = \"a\"\n";
+    let err = from_str::<Config>(doc).unwrap_err();
+    assert_eq!(err.line_col(), Some((5, 8)));
+    assert!(err.to_string().ends_with("for key `server.port` at line 5 column 8"), "{}", err);
+
+    // A missing field is reported at the table it is missing from.
+    let err = toml_rust::from_bytes::<Config>(b"title = \"x\"\nwhen = 1979-05-27\n\n[server]\nhost = \"h\"\n").unwrap_err();
+    assert_eq!(err.line_col(), Some((4, 2)));
+}
+
+#[test]
+fn decode_errors_keep_their_position() {
+    let err = from_str::<Config>("a = \n").unwrap_err();
+    assert!(matches!(err, DeError::Decode(_)));
+    assert_eq!(err.line_col(), Some((1, 5)));
+}
+
+#[test]
+fn into_value() {
+    let v: Value = from_str("a = [1, 2]").unwrap();
+    assert_eq!(v.get_path("a.1").unwrap().as_integer(), Some(2));
+}
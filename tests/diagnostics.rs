@@ -0,0 +1,89 @@
+//! Collecting every error of a document with `loads_with_diagnostics`.
+
+use std::time::Instant;
+
+use toml_rust::{loads_with_diagnostics, ErrorKind, Value};
+
+#[test]
+fn reports_each_bad_line_and_keeps_the_rest() {
+    let src = "a = 1\nb = \"x\\qy\"\nc = 0_1\nd = 4\n[t]\nx = 1\n[t]\ny = 2\n[u]\nz = tru\nw = 3\ns = \"never closed\n";
+    let (t, errs) = loads_with_diagnostics(src, None, None);
+    let kinds: Vec<ErrorKind> = errs.iter().map(|e| e.kind()).collect();
+    assert_eq!(
+        kinds,
+        [
+            ErrorKind::InvalidEscape,
+            ErrorKind::InvalidNumber,
+            ErrorKind::DuplicateTable,
+            ErrorKind::InvalidValue,
+            ErrorKind::UnterminatedString,
+        ]
+    );
+    let lines: Vec<usize> = errs.iter().map(|e| e.lineno()).collect();
+    assert_eq!(lines, [2, 3, 7, 10, 12]);
+    assert_eq!(t["a"], Value::Integer(1));
+    assert_eq!(t["d"], Value::Integer(4));
+    assert_eq!(t["u"].get("w"), Some(&Value::Integer(3)));
+}
+
+#[test]
+fn valid_document_has_no_diagnostics() {
+    let (t, errs) = loads_with_diagnostics("a = 1", None, None);
+    assert!(errs.is_empty());
+    assert_eq!(t.len(), 1);
+}
+
+#[test]
+fn recovery_skips_the_rest_of_a_multi_line_array() {
+    let src = "a = [\n  1,\n  oops,\n  3]\nb = 2\nc = { x = 1,\n  y = ]\n}\nd = 4\n";
+    let (t, errs) = loads_with_diagnostics(src, None, None);
+    assert_eq!(errs.len(), 2, "{:?}", errs);
+    assert_eq!(errs[0].lineno(), 3);
+    assert_eq!(errs[1].lineno(), 6);
+    assert_eq!(t["b"], Value::Integer(2));
+    assert_eq!(t["d"], Value::Integer(4));
+}
+
+#[test]
+fn recovery_resumes_at_the_next_statement_of_an_unclosed_array() {
+    let src = "a = [1, \"]\", # ]\n  oops\nb = 2\n[t]\nc = 3\n";
+    let (t, errs) = loads_with_diagnostics(src, None, None);
+    assert_eq!(errs.len(), 1, "{:?}", errs);
+    assert_eq!(t["b"], Value::Integer(2));
+    assert_eq!(t["t"].get("c"), Some(&Value::Integer(3)));
+}
+
+/// A document of `n` lines, every other one an error: a missing value or
+/// a key redefining the first line's.
+fn broken(n: usize) -> String {
+    (0..n)
+        .map(|i| match i % 4 {
+            1 => format!("k{} = \n", i),
+            3 => "k0 = 2\n".to_string(),
+            _ => format!("k{} = {}\n", i, i),
+        })
+        .collect()
+}
+
+#[test]
+fn many_errors_take_linear_time() {
+    // The best of a few runs, in nanoseconds per byte.
+    let per_byte = |n: usize| {
+        let doc = broken(n);
+        let best = (0..5)
+            .map(|_| {
+                let start = Instant::now();
+                let (_, errs) = loads_with_diagnostics(&doc, None, None);
+                let elapsed = start.elapsed();
+                assert_eq!(errs.len(), n / 2);
+                assert_eq!(errs.last().map(|e| (e.lineno(), e.original().unwrap().lineno)), Some((n, 1)));
+                elapsed
+            })
+            .min()
+            .unwrap();
+        best.as_nanos() as f64 / doc.len() as f64
+    };
+    let small = per_byte(2_000);
+    let large = per_byte(32_000);
+    assert!(large < small * 4.0, "{:.1}ns per byte for 2000 lines but {:.1}ns for 32000", small, large);
+}
@@ -0,0 +1,145 @@
+//! Editing documents in place with `Document`.
+
+use std::path::{Path, PathBuf};
+
+use toml_rust::{loads, Document, DocumentError, Table, Value};
+
+const SRC: &str = r#"# top comment
+title = 'Example'   # trailing
+count = 0x1F
+
+# owner section
+[owner]
+name = "Tom"
+  dob = 1979-05-27T07:32:00-08:00 # first class dates
+
+[servers.alpha]
+ip = "10.0.0.1"
+point = { x = 1, y = 2 }
+
+[[fruit]]
+name = "apple"
+physical.color = "red"
+
+[[fruit]]
+name = "banana"
+"#;
+
+#[test]
+fn unedited_document_is_unchanged() {
+    let d = Document::parse(SRC).unwrap();
+    assert_eq!(d.to_string(), SRC);
+    assert_eq!(d.get(&["owner", "name"]), Some(&Value::String("Tom".into())));
+    assert_eq!(d.get(&["fruit", "1", "name"]), Some(&Value::String("banana".into())));
+}
+
+#[test]
+fn insert_replaces_only_the_value() {
+    let mut d = Document::parse(SRC).unwrap();
+    let old = d.insert(&["title"], Value::String("New".into())).unwrap();
+    assert_eq!(old, Some(Value::String("Example".into())));
+    assert_eq!(d.to_string(), SRC.replace("'Example'", "\"New\""));
+    d.insert(&["servers", "alpha", "point", "x"], Value::Integer(5)).unwrap();
+    assert!(d.to_string().contains("point = { x = 5, y = 2 }"), "{}", d);
+}
+
+#[test]
+fn insert_adds_keys_next_to_their_table() {
+    let mut d = Document::parse(SRC).unwrap();
+    d.insert(&["owner", "age"], Value::Integer(3)).unwrap();
+    assert!(d.to_string().contains("  dob = 1979-05-27T07:32:00-08:00 # first class dates\n  age = 3\n\n[servers"), "{}", d);
+    d.insert(&["fruit", "0", "physical", "shape"], Value::String("round".into())).unwrap();
+    assert!(d.to_string().contains("physical.color = \"red\"\nphysical.shape = \"round\"\n"), "{}", d);
+    d.insert(&["extra"], Value::Boolean(true)).unwrap();
+    assert!(d.to_string().contains("count = 0x1F\nextra = true\n"), "{}", d);
+    let mut t = Table::ordered();
+    t.insert("a".into(), Value::Integer(1));
+    d.insert(&["new", "tbl"], Value::Table(t)).unwrap();
+    assert!(d.to_string().ends_with("name = \"banana\"\n\n[new.tbl]\na = 1\n"), "{}", d);
+    d.insert(&["servers", "beta", "ip"], Value::String("x".into())).unwrap();
+    assert_eq!(d.get(&["servers", "beta", "ip"]), Some(&Value::String("x".into())));
+}
+
+#[test]
+fn insert_at_the_root_goes_before_the_first_header() {
+    let mut d = Document::parse("# c\n[a]\nb = 1\n").unwrap();
+    d.insert(&["x"], Value::Integer(1)).unwrap();
+    assert_eq!(d.to_string(), "x = 1\n\n# c\n[a]\nb = 1\n");
+    let mut d = Document::parse("").unwrap();
+    d.insert(&["x"], Value::Integer(1)).unwrap();
+    assert_eq!(d.to_string(), "x = 1\n");
+}
+
+#[test]
+fn remove_drops_the_lines_of_keys_and_tables() {
+    let mut d = Document::parse(SRC).unwrap();
+    assert!(d.remove(&["count"]).unwrap().is_some());
+    assert!(!d.to_string().contains("count"));
+    d.remove(&["owner"]).unwrap();
+    assert!(!d.to_string().contains("[owner]") && !d.to_string().contains("dob"), "{}", d);
+    d.remove(&["fruit", "0", "physical", "color"]).unwrap();
+    assert!(!d.to_string().contains("physical"), "{}", d);
+    d.remove(&["servers", "alpha", "point", "y"]).unwrap();
+    assert!(d.to_string().contains("point = { x = 1 }"), "{}", d);
+    d.remove(&["fruit"]).unwrap();
+    assert!(!d.to_string().contains("fruit"), "{}", d);
+    assert_eq!(d.remove(&["nope"]).unwrap(), None);
+}
+
+#[test]
+fn rename_rewrites_only_the_key() {
+    let mut d = Document::parse(SRC).unwrap();
+    d.rename(&["servers"], "hosts").unwrap();
+    assert!(d.to_string().contains("[hosts.alpha]"));
+    d.rename(&["fruit", "0", "physical"], "look").unwrap();
+    assert!(d.to_string().contains("look.color"));
+    d.rename(&["owner", "name"], "full name").unwrap();
+    assert!(d.to_string().contains("\"full name\" = \"Tom\""));
+    assert!(matches!(d.rename(&["title"], "count"), Err(DocumentError::KeyExists(_))));
+    assert!(matches!(d.rename(&["zzz"], "a"), Err(DocumentError::NotFound(_))));
+    d.rename(&["hosts", "alpha", "point", "x"], "z").unwrap();
+    assert!(d.to_string().contains("point = { y = 2, z = 1 }"), "{}", d);
+}
+
+#[test]
+fn failed_edits_leave_the_document_alone_and_crlf_is_kept() {
+    let mut d = Document::parse(SRC).unwrap();
+    assert!(matches!(d.insert(&["title", "x"], Value::Integer(1)), Err(DocumentError::NotATable(_))));
+    assert_eq!(d.to_string(), SRC);
+    let crlf = SRC.replace('\n', "\r\n");
+    let mut d = Document::parse(&crlf).unwrap();
+    d.insert(&["owner", "age"], Value::Integer(3)).unwrap();
+    d.insert(&["z", "q"], Value::Integer(3)).unwrap();
+    assert!(!d.to_string().replace("\r\n", "").contains('\n'), "{:?}", d.to_string());
+}
+
+fn valid_fixtures() -> Vec<PathBuf> {
+    fn walk(dir: &Path, found: &mut Vec<PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(&path, found);
+            } else if path.extension().is_some_and(|ext| ext == "toml") {
+                found.push(path);
+            }
+        }
+    }
+    let mut found = Vec::new();
+    walk(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/toml-test/valid"), &mut found);
+    found
+}
+
+#[test]
+fn valid_fixtures_round_trip() {
+    for path in valid_fixtures() {
+        let s = std::fs::read_to_string(&path).unwrap();
+        // A byte order mark belongs to the file's encoding, not its text.
+        let s = s.strip_prefix('\u{feff}').unwrap_or(&s);
+        let d = Document::parse(s).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        assert_eq!(d.to_string(), s, "{}", path.display());
+        // NaN never compares equal, so only the text is checked for it.
+        if !s.contains("nan") {
+            assert_eq!(d.as_table(), &loads(s, None, None).unwrap(), "{}", path.display());
+        }
+    }
+}
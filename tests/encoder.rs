@@ -0,0 +1,69 @@
+//! Encoding `Value` trees with `dumps` and `dump`.
+
+use std::path::Path;
+
+use toml_rust::{dump, dumps, loads, Table, TomlEncoder, Value};
+
+fn example() -> Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("example-v0.4.0.toml");
+    // TOML 1.0 floats are binary64, so the 0.4.0 example's `1e1_000` is
+    // out of range.
+    let src = std::fs::read_to_string(path).unwrap().replace("1e1_000", "1e1_00");
+    Value::Table(loads(&src, None, None).unwrap())
+}
+
+fn round_trip(v: &Value, encoder: Option<&TomlEncoder>) {
+    let out = dumps(v, encoder);
+    assert_eq!(Value::Table(loads(&out, None, None).unwrap()), *v, "{}", out);
+}
+
+#[test]
+fn example_round_trips() {
+    round_trip(&example(), None);
+}
+
+#[test]
+fn strings_floats_and_empty_values_round_trip() {
+    let mut t = Table::new();
+    t.insert("a b".into(), Value::from("quote \" back \\ tab\t nl\n ctl\u{1} é"));
+    t.insert("f".into(), Value::from(1.0));
+    t.insert("g".into(), Value::from(-2.5e-10));
+    t.insert("inf".into(), Value::from(f64::NEG_INFINITY));
+    t.insert("arr".into(), Value::Array(vec!["a,b".into(), "c]".into()]));
+    t.insert("empty".into(), Value::Array(vec![]));
+    t.insert("et".into(), Value::Table(Table::new()));
+    round_trip(&Value::Table(t), None);
+}
+
+#[test]
+fn sections_and_arrays_of_tables() {
+    let src = "title = \"x\"\n\n[a.b]\nc = 1\n\n[[fruit]]\nname = \"apple\"\n\n[[fruit]]\nname = \"banana\"\n";
+    let v = Value::Table(loads(src, None, None).unwrap());
+    let out = dumps(&v, None);
+    assert!(out.contains("[a.b]\nc = 1\n"), "{}", out);
+    assert_eq!(out.matches("[[fruit]]").count(), 2, "{}", out);
+    round_trip(&v, None);
+}
+
+#[test]
+fn datetimes_are_written_bare() {
+    let v = Value::Table(loads("d = 1979-05-27\nt = 1979-05-27T07:32:00.5-07:00\n", Some(Table::ordered), None).unwrap());
+    assert_eq!(dumps(&v, None), "d = 1979-05-27\nt = 1979-05-27T07:32:00.5-07:00\n");
+}
+
+#[test]
+fn preserve_writes_nested_tables_inline() {
+    let v = example();
+    round_trip(&v, Some(&TomlEncoder::new(None, true)));
+    let v = Value::Table(loads("[a]\nb = 1\n", None, None).unwrap());
+    assert_eq!(dumps(&v, Some(&TomlEncoder::new(None, true))), "a = { b = 1 }\n");
+}
+
+#[test]
+fn dump_writes_what_dumps_returns() {
+    let v = example();
+    let mut out = Vec::new();
+    let written = dump(&v, &mut out, None).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), written);
+    assert_eq!(written, dumps(&v, None));
+}
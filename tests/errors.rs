@@ -0,0 +1,56 @@
+//! Decode errors: their kind, position and rendered snippet.
+
+use toml_rust::{loads, ErrorKind, TomlDecodeError};
+
+fn error(src: &str) -> TomlDecodeError {
+    loads(src, None, None).unwrap_err()
+}
+
+#[test]
+fn kinds_and_spans() {
+    let e = error("a = 1\nb = \"x\\qy\"\n");
+    assert_eq!(e.kind(), ErrorKind::InvalidEscape);
+    assert_eq!(&e.doc()[e.span()], "\\q");
+    let e = error("a = 1\n a = 2\n");
+    assert_eq!(e.kind(), ErrorKind::DuplicateKey);
+    assert_eq!((e.lineno(), e.colno()), (2, 2));
+    assert_eq!(error("[t]\nx = 0_1\n").kind(), ErrorKind::InvalidNumber);
+    assert_eq!(error("k = \"abc\ny = 1\n").kind(), ErrorKind::UnterminatedString);
+    assert_eq!(error("[t]\n[t]\n").kind(), ErrorKind::DuplicateTable);
+    let e = error("p = { x = 1, y = \"\\z\" }\n");
+    assert_eq!(&e.doc()[e.span()], "\\z");
+    let e = error("arr = [ 1, 2, 0x ]\n");
+    assert_eq!(&e.doc()[e.span()], "0x");
+    assert_eq!(error("s = \"\\uZZZZ\"").kind(), ErrorKind::InvalidEscape);
+    assert_eq!(error("k = \"\"\"\nabc\\q\n\"\"\"\n").kind(), ErrorKind::InvalidEscape);
+}
+
+#[test]
+fn snippet_underlines_the_span() {
+    let e = error("a = 1\nb = \"x\\qy\"\n");
+    assert_eq!(e.snippet(), "error: Reserved escape sequence used\n --> line 2, column 7\n  |\n2 | b = \"x\\qy\"\n  |       ^^\n");
+}
+
+#[test]
+fn snippet_keeps_tabs_in_the_caret_indent() {
+    let e = error("[t]\n\tx = 1\n\tx = 2\n");
+    assert_eq!((e.lineno(), e.colno()), (3, 2));
+    assert!(e.snippet().starts_with("error: "));
+    assert!(e.snippet().contains("\n3 | \tx = 2\n  | \t^\n"), "{}", e.snippet());
+
+    let e = error("a = [\t1,\t0x ]\n");
+    assert!(e.snippet().contains("\n  |      \t  \t^^\n"), "{}", e.snippet());
+}
+
+#[test]
+fn snippet_notes_the_first_definition() {
+    let e = error("[a]\nx = 1\n[a]\n");
+    assert_eq!(e.kind(), ErrorKind::DuplicateTable);
+    assert_eq!(e.original().map(|o| (o.lineno, o.colno, o.bytes.clone())), Some((1, 1, 0..3)));
+    let e = error("a.b = 1\na.b = 2\n");
+    assert_eq!(e.original().unwrap().bytes, 0..3);
+    assert!(e.snippet().contains("note: first defined here"));
+    let snippet = error("x = 1\n\n\n\n\n\n\n\n\n\nx = 2\n").snippet();
+    assert!(snippet.contains("\n 1 | x = 1\n"), "{}", snippet);
+    assert!(snippet.contains("\n11 | x = 2\n"), "{}", snippet);
+}
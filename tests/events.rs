@@ -0,0 +1,108 @@
+//! Streaming a document with `EventReader`.
+
+use std::io::Read;
+
+use toml_rust::{ErrorKind, Event, EventReader, Value};
+
+/// A reader that hands out one byte per `read`, to exercise statements
+/// split across reads.
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match (self.0.split_first(), buf.first_mut()) {
+            (Some((&b, rest)), Some(out)) => {
+                *out = b;
+                self.0 = rest;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+const SRC: &str = "# head\r\ntitle = \"x\" # t\n\n[a.b]\nc = [\n  1, # one\n  2,\n]\ns = '''\nline\n'''\n[[fruit]]\nname = 'apple'\nlast = 1";
+
+#[test]
+fn event_sequence_and_spans() {
+    let events: Vec<(Event, std::ops::Range<usize>)> = EventReader::new(Trickle(SRC.as_bytes())).collect::<Result<_, _>>().unwrap();
+    let kinds: Vec<Event> = events.iter().map(|(e, _)| e.clone()).collect();
+    assert_eq!(
+        kinds,
+        [
+            Event::Comment("# head".into()),
+            Event::KeyValue(vec!["title".into()], Value::String("x".into())),
+            Event::Comment("# t".into()),
+            Event::TableHeader(vec!["a".into(), "b".into()]),
+            Event::KeyValue(vec!["c".into()], Value::Array(vec![Value::Integer(1), Value::Integer(2)])),
+            Event::KeyValue(vec!["s".into()], Value::String("line\n".into())),
+            Event::ArrayTableHeader(vec!["fruit".into()]),
+            Event::KeyValue(vec!["name".into()], Value::String("apple".into())),
+            Event::KeyValue(vec!["last".into()], Value::Integer(1)),
+        ]
+    );
+    let texts: Vec<&str> = events.iter().map(|(_, span)| &SRC[span.clone()]).collect();
+    assert_eq!(texts[1], "title = \"x\"");
+    assert_eq!(texts[3], "[a.b]");
+    assert_eq!(texts[4], "c = [\n  1, # one\n  2,\n]");
+    assert_eq!(texts[8], "last = 1");
+}
+
+#[test]
+fn byte_order_mark_is_skipped() {
+    let src = "\u{feff}a = 1\n";
+    let events: Vec<_> = EventReader::new(src.as_bytes()).collect::<Result<_, _>>().unwrap();
+    assert_eq!(events, [(Event::KeyValue(vec!["a".into()], Value::Integer(1)), 3..8)]);
+}
+
+#[test]
+fn errors_stop_iteration_with_their_position_in_the_input() {
+    let src = "a = 1\nb = 2\n\nc = = 3\nd = 4\n";
+    let results: Vec<_> = EventReader::new(src.as_bytes()).collect();
+    assert_eq!(results.len(), 3);
+    let err = results[2].as_ref().unwrap_err();
+    assert_eq!((err.lineno(), err.colno()), (4, 5));
+    assert_eq!(err.pos(), src.find("= 3").unwrap());
+    assert_eq!(&err.doc()[err.span()], "=");
+    assert!(err.to_string().ends_with(&format!("char {})", err.pos())), "{}", err);
+
+    assert!(EventReader::new("a = [1,\n2\n".as_bytes()).next().unwrap().is_err());
+    assert!(EventReader::new("a = \"x\n".as_bytes()).next().unwrap().is_err());
+    assert_eq!(EventReader::new("".as_bytes()).count(), 0);
+}
+
+#[test]
+fn encoding_errors_have_their_position() {
+    let src = b"a = 1\nb = \"\xff\"\n";
+    let results: Vec<_> = EventReader::new(&src[..]).collect();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].is_ok());
+    let err = results[1].as_ref().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidCharacter);
+    assert_eq!((err.lineno(), err.colno(), err.pos()), (2, 6, 11));
+
+    let results: Vec<_> = EventReader::new(Trickle(b"x = [\n  1,\n  2]\ny = 'a\rb'\n")).collect();
+    assert_eq!(results.len(), 2);
+    let err = results[1].as_ref().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidCharacter);
+    assert_eq!(err.message(), "Carriage return is not followed by a newline");
+    assert_eq!((err.lineno(), err.colno(), err.pos()), (4, 7, 22));
+
+    let err = EventReader::new(&b"\xef\xbb\xbfa = \"\x01\"\n"[..]).next().unwrap().unwrap_err();
+    assert_eq!((err.lineno(), err.colno(), err.pos()), (1, 6, 8));
+    let err = EventReader::new(&b"a\x00=\x001\x00"[..]).next().unwrap().unwrap_err();
+    assert!(err.message().starts_with("Found UTF-16"), "{}", err);
+}
+
+#[test]
+fn large_documents_stream() {
+    let mut s = String::from("a = [\n");
+    for i in 0..20_000 {
+        s.push_str(&format!("  {},\n", i));
+    }
+    s.push_str("]\n");
+    for i in 0..10_000 {
+        s.push_str(&format!("k{} = {}\n", i, i));
+    }
+    assert_eq!(EventReader::new(s.as_bytes()).count(), 10_001);
+}
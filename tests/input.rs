@@ -0,0 +1,104 @@
+//! Reading TOML from files and readers, and the shape of the errors that
+//! come back from each step.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use toml_rust::{from_bytes, from_path, from_reader, load, DeError, Value};
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Config {
+    name: String,
+    port: u16,
+}
+
+/// A fresh temporary directory named after the test.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("toml-rust-input-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A reader that fails after handing out part of a document.
+struct Broken<'a>(&'a [u8]);
+
+impl Read for Broken<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::ConnectionReset, "gone"));
+        }
+        self.0.read(buf)
+    }
+}
+
+#[test]
+fn from_reader_decodes_the_whole_input() {
+    let src = "\u{feff}name = \"x\"\nport = 80\n";
+    let config: Config = from_reader(src.as_bytes()).unwrap();
+    assert_eq!(config, Config { name: "x".into(), port: 80 });
+    let value: Value = from_reader(io::Cursor::new(src.as_bytes().to_vec())).unwrap();
+    assert_eq!(value.get_path("port"), Some(&Value::Integer(80)));
+}
+
+#[test]
+fn reader_errors_keep_their_kind() {
+    let err = from_reader::<Config, _>(Broken(b"name = \"x\"\n")).unwrap_err();
+    assert!(matches!(&err, DeError::Io(e) if e.kind() == io::ErrorKind::ConnectionReset), "{}", err);
+    assert!(err.path().is_none());
+
+    let err = from_reader::<Config, _>(&b"name = \"x\"\nport = \n"[..]).unwrap_err();
+    assert!(matches!(&err, DeError::Decode(e) if e.lineno() == 2), "{}", err);
+    let err = from_bytes::<Config>(b"name = \"\xff\"\nport = 1\n").unwrap_err();
+    assert!(matches!(&err, DeError::Decode(_)), "{}", err);
+    assert_eq!(err.line_col(), Some((1, 9)));
+
+    let err = from_reader::<Config, _>(&b"name = \"x\"\nport = -1\n"[..]).unwrap_err();
+    assert!(matches!(&err, DeError::Custom { .. }), "{}", err);
+}
+
+#[test]
+fn file_errors_name_the_file() {
+    let dir = scratch("files");
+    fs::write(dir.join("good.toml"), "name = \"x\"\nport = 80\n").unwrap();
+    fs::write(dir.join("bad.toml"), "name = \"x\"\nport = \n").unwrap();
+    assert_eq!(from_path::<Config>(dir.join("good.toml")).unwrap().port, 80);
+    assert_eq!(load(dir.join("good.toml"), None, None).unwrap()["port"], Value::Integer(80));
+
+    let missing = dir.join("missing.toml");
+    let err = from_path::<Config>(&missing).unwrap_err();
+    assert!(matches!(&err, DeError::File { path, error } if path == &missing && matches!(**error, DeError::Io(_))), "{}", err);
+    let err = load(&missing, None, None).unwrap_err();
+    assert!(matches!(err.inner(), DeError::Io(e) if e.kind() == io::ErrorKind::NotFound));
+    assert_eq!(err.path(), Some(missing.as_path()));
+    assert!(err.to_string().starts_with(&format!("{}: ", missing.display())), "{}", err);
+
+    for err in [from_path::<Config>(dir.join("bad.toml")).unwrap_err(), load(dir.join("bad.toml"), None, None).unwrap_err()] {
+        assert!(matches!(&err, DeError::File { .. }), "{}", err);
+        assert!(matches!(err.inner(), DeError::Decode(e) if e.lineno() == 2), "{}", err);
+        assert_eq!(err.line_col(), Some((2, 8)));
+        assert!(err.path().unwrap().ends_with("bad.toml"));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn paths_need_not_be_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = scratch("non-utf8");
+    let path = dir.join(OsStr::from_bytes(b"caf\xe9.toml"));
+    assert!(path.to_str().is_none());
+    fs::write(&path, "name = \"x\"\nport = 80\n").unwrap();
+    assert_eq!(from_path::<Config>(&path).unwrap().port, 80);
+    assert_eq!(load(&path, None, None).unwrap()["name"], Value::String("x".into()));
+
+    fs::write(&path, "port = 80\n").unwrap();
+    let err = from_path::<Config>(&path).unwrap_err();
+    assert_eq!(err.path(), Some(path.as_path()));
+    assert!(matches!(err.inner(), DeError::Custom { .. }), "{}", err);
+    assert!(err.to_string().contains("caf\u{fffd}.toml: "), "{}", err);
+}
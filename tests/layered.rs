@@ -0,0 +1,85 @@
+//! Deep-merging several files with `LayeredLoader` and `from_paths`.
+
+use std::fs;
+use std::path::PathBuf;
+
+use toml_rust::{from_paths, DeError, LayeredLoader, MergeError, MergePolicy, Value};
+
+/// Writes a base file and a `conf.d` directory under a fresh temporary
+/// directory named after the test.
+fn layers(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("toml-rust-layered-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("conf.d")).unwrap();
+    fs::write(dir.join("base.toml"), "[server]\nhost = \"a\"\nport = 1\nlist = [1]\n[[srv]]\nx = 1\n").unwrap();
+    fs::write(dir.join("conf.d/20-b.toml"), "[server]\nport = 3\n").unwrap();
+    fs::write(dir.join("conf.d/10-a.toml"), "[server]\nport = 2\nlist = [2]\n[[srv]]\nx = 2\n").unwrap();
+    fs::write(dir.join("conf.d/ignore.txt"), "junk").unwrap();
+    dir
+}
+
+fn loader(dir: &std::path::Path) -> LayeredLoader {
+    LayeredLoader::new(None).file(dir.join("base.toml")).dir(dir.join("conf.d"))
+}
+
+#[test]
+fn tables_are_merged_key_by_key() {
+    let dir = layers("deep");
+    let (t, report) = loader(&dir).dir(dir.join("missing")).load().unwrap();
+    let server = t["server"].as_table().unwrap();
+    assert_eq!(server.get("host"), Some(&Value::String("a".into())));
+    assert_eq!(server.get("port"), Some(&Value::Integer(3)));
+    assert_eq!(server.get("list"), Some(&Value::Array(vec![Value::Integer(2)])));
+
+    assert_eq!(report.layers.len(), 3);
+    assert!(report.layers[1].path.ends_with("10-a.toml"));
+    assert!(report.layers[2].path.ends_with("20-b.toml"));
+    assert_eq!(report.layers[0].overridden, vec![]);
+    let overridden = &report.layers[2].overridden;
+    assert_eq!(overridden.len(), 1);
+    assert_eq!(overridden[0].key, ["server", "port"]);
+    assert_eq!(overridden[0].layer, 1);
+    assert_eq!(overridden[0].previous, Value::Integer(2));
+    assert!(!overridden[0].appended);
+}
+
+#[test]
+fn append_policy_concatenates_arrays() {
+    let dir = layers("append");
+    let (t, report) = loader(&dir).arrays(MergePolicy::Append).load().unwrap();
+    let server = t["server"].as_table().unwrap();
+    assert_eq!(server.get("list"), Some(&Value::Array(vec![Value::Integer(1), Value::Integer(2)])));
+    assert!(matches!(t.get("srv"), Some(Value::Array(a)) if a.len() == 2));
+    assert!(report.layers[1].overridden.iter().any(|o| o.appended && o.key == ["server", "list"]));
+}
+
+#[test]
+fn error_policy_reports_the_conflict() {
+    let dir = layers("error");
+    let err = loader(&dir).scalars(MergePolicy::Error).load().unwrap_err();
+    assert!(matches!(&err, MergeError::Conflict { key, path } if key == &["server", "port"] && path.ends_with("10-a.toml")), "{}", err);
+    assert!(err.to_string().contains("server.port"), "{}", err);
+}
+
+#[test]
+fn unreadable_and_invalid_files_are_errors() {
+    let dir = layers("io");
+    let err = LayeredLoader::new(None).file(dir.join("nope.toml")).load().unwrap_err();
+    assert!(matches!(err, MergeError::Io { .. }));
+    fs::write(dir.join("bad.toml"), "a = \n").unwrap();
+    let err = LayeredLoader::new(None).file(dir.join("bad.toml")).load().unwrap_err();
+    assert!(matches!(&err, MergeError::Decode { path, error } if path.ends_with("bad.toml") && error.lineno() == 1));
+}
+
+#[test]
+fn from_paths_deep_merges() {
+    let dir = layers("paths");
+    fs::write(dir.join("a.toml"), "[t]\na = 1\n").unwrap();
+    fs::write(dir.join("b.toml"), "[t]\nb = 2\n").unwrap();
+    let v: Value = from_paths([dir.join("a.toml"), dir.join("b.toml")]).unwrap();
+    assert_eq!(v.get_path("t.a"), Some(&Value::Integer(1)));
+    assert_eq!(v.get_path("t.b"), Some(&Value::Integer(2)));
+    let err = from_paths::<Value, _>([dir.join("a.toml"), dir.join("zz.toml")]).unwrap_err();
+    assert!(err.path().unwrap().ends_with("zz.toml"));
+    assert!(matches!(err.inner(), DeError::Io(_)));
+}
@@ -0,0 +1,50 @@
+//! Keeping keys in document order with `TomlOrderedDecoder` and
+//! `TomlOrderedEncoder`.
+
+use toml_rust::{dumps, loads, TomlOrderedDecoder, TomlOrderedEncoder, Value};
+
+const SRC: &str = "zebra = 1
+apple = \"a\"
+mango = [ 3, 1, 2,]
+
+[server]
+port = 80
+host = \"h\"
+
+[server.tls]
+key = \"k\"
+cert = \"c\"
+
+[[fruit]]
+name = \"b\"
+color = \"yellow\"
+
+[fruit.physical]
+shape = \"long\"
+
+[[fruit.variety]]
+name = \"plantain\"
+
+[[fruit]]
+name = \"a\"
+color = \"red\"
+";
+
+#[test]
+fn decoded_tables_keep_document_order() {
+    let t = loads(SRC, None, Some(&TomlOrderedDecoder::new())).unwrap();
+    assert!(t.is_ordered());
+    assert_eq!(t.keys().collect::<Vec<_>>(), ["zebra", "apple", "mango", "server", "fruit"]);
+    let server = t["server"].as_table().unwrap();
+    assert_eq!(server.keys().collect::<Vec<_>>(), ["port", "host", "tls"]);
+    assert_eq!(server["tls"].as_table().unwrap().keys().collect::<Vec<_>>(), ["key", "cert"]);
+    let Value::Array(fruit) = &t["fruit"] else { panic!("fruit is an array") };
+    assert_eq!(fruit[0].as_table().unwrap().keys().collect::<Vec<_>>(), ["name", "color", "physical", "variety"]);
+    assert_eq!(fruit[1].as_table().unwrap().keys().collect::<Vec<_>>(), ["name", "color"]);
+}
+
+#[test]
+fn encoding_keeps_document_order() {
+    let t = loads(SRC, None, Some(&TomlOrderedDecoder::new())).unwrap();
+    assert_eq!(dumps(&Value::Table(t), Some(&TomlOrderedEncoder::new())), SRC);
+}
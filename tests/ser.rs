@@ -0,0 +1,95 @@
+//! Serializing typed values with `to_string` and `to_value`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use toml_rust::{from_str, loads, to_string, to_value, SerError, Value};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+enum Shape {
+    Circle { r: f64 },
+    Square(i64),
+    Point,
+    Pair(i64, String),
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Sub {
+    x: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Item {
+    name: String,
+    sub: Option<Sub>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Config {
+    server: Sub,
+    title: String,
+    items: Vec<Item>,
+    shapes: Vec<Shape>,
+    map: BTreeMap<String, u8>,
+    none: Option<i32>,
+    nested: Vec<Vec<i64>>,
+    t: (i64, String),
+}
+
+#[test]
+fn struct_round_trips() {
+    let config = Config {
+        server: Sub { x: 1 },
+        title: "t".into(),
+        items: vec![Item { name: "a".into(), sub: Some(Sub { x: 2 }) }, Item { name: "b".into(), sub: None }],
+        shapes: vec![Shape::Circle { r: 1.5 }, Shape::Square(2), Shape::Point, Shape::Pair(1, "x".into())],
+        map: [("k".to_string(), 3u8)].into_iter().collect(),
+        none: None,
+        nested: vec![vec![1], vec![2, 3]],
+        t: (1, "y".into()),
+    };
+    let s = to_string(&config).unwrap();
+    assert!(!s.contains("none"));
+    assert_eq!(from_str::<Config>(&s).unwrap(), config);
+}
+
+#[test]
+fn non_table_root_is_rejected() {
+    assert_eq!(to_string(&5), Err(SerError::UnsupportedType("non-table root")));
+}
+
+#[test]
+fn none_field_is_skipped() {
+    #[derive(Serialize)]
+    struct Doc {
+        a: Option<i64>,
+        b: i64,
+    }
+    assert_eq!(to_string(&Doc { a: None, b: 2 }).unwrap(), "b = 2\n");
+}
+
+#[test]
+fn nested_none_reports_its_key() {
+    #[derive(Serialize)]
+    struct Doc {
+        a: Vec<Option<i64>>,
+        b: i64,
+    }
+    let err = to_string(&Doc { a: vec![Some(1), None], b: 2 }).unwrap_err();
+    assert_eq!(err, SerError::UnsupportedNone { key: vec!["a".into(), "1".into()] });
+    assert_eq!(err.to_string(), "unsupported None value for key `a.1`");
+
+    let err = to_value(&BTreeMap::from([("t", BTreeMap::from([("u", vec![None::<i64>])]))])).unwrap_err();
+    assert_eq!(err.to_string(), "unsupported None value for key `t.u.0`");
+}
+
+#[test]
+fn value_datetimes_round_trip() {
+    let doc = "odt = 1979-05-27T07:32:00Z\nldt = 1979-05-27T07:32:00.5\nld = 1979-05-27\nlt = 07:32:00\n[t]\nall = [1979-05-27]\n";
+    let table = loads(doc, None, None).unwrap();
+    assert!(matches!(table["ld"], Value::Datetime(_)));
+    let value = Value::Table(table);
+    assert_eq!(to_value(&value).unwrap(), value);
+    assert_eq!(from_str::<Value>(doc).unwrap(), value);
+    assert_eq!(from_str::<Value>(&to_string(&value).unwrap()).unwrap(), value);
+}
@@ -0,0 +1,64 @@
+//! Where each decoded key and value came from, as recorded in a
+//! `SourceMap`.
+
+use std::fs;
+
+use toml_rust::{load_with_sources, loads_with_sources, LayeredLoader, MergePolicy};
+
+const SRC: &str = "title = \"x\"\n[server]\nport = 8080 # c\nhosts = [\"a\",\n  \"b\"]\nt = { u.v = 1 }\n[[fruit]]\nname = \"é\"\n[[fruit]]\na.b = 2\n";
+
+#[test]
+fn keys_and_values_have_spans() {
+    let (t, sources) = loads_with_sources(SRC, None, None).unwrap();
+    assert_eq!(t.len(), 3);
+    let port = sources.get(&["server", "port"]).unwrap();
+    let key = port.key.as_ref().unwrap();
+    assert_eq!((key.lineno, key.colno), (3, 1));
+    assert_eq!(&SRC[port.value.as_ref().unwrap().bytes.clone()], "8080");
+    assert_eq!(sources.get(&["title"]).unwrap().to_string(), "1:1");
+}
+
+#[test]
+fn array_elements_and_inline_tables_have_spans() {
+    let (_, sources) = loads_with_sources(SRC, None, None).unwrap();
+    let b = sources.get(&["server", "hosts", "1"]).unwrap();
+    assert!(b.key.is_none());
+    let value = b.value.as_ref().unwrap();
+    assert_eq!((value.lineno, value.colno), (5, 3));
+    assert_eq!(&SRC[sources.get(&["server", "t", "u", "v"]).unwrap().value.clone().unwrap().bytes], "1");
+    assert_eq!(&SRC[sources.get(&["server", "t"]).unwrap().value.clone().unwrap().bytes], "{ u.v = 1 }");
+}
+
+#[test]
+fn arrays_of_tables_are_indexed_by_element() {
+    let (_, sources) = loads_with_sources(SRC, None, None).unwrap();
+    let second = sources.get(&["fruit", "1"]).unwrap().value.clone().unwrap();
+    assert_eq!(&SRC[second.bytes], "[[fruit]]");
+    assert_eq!(second.lineno, 9);
+    assert_eq!(sources.get(&["fruit"]).unwrap().key.as_ref().unwrap().lineno, 7);
+    assert_eq!(sources.get(&["fruit", "1", "a"]).unwrap().key.as_ref().unwrap().lineno, 10);
+    assert!(sources.get(&["fruit", "1", "a", "b"]).is_some());
+    let name = sources.get(&["fruit", "0", "name"]).unwrap().value.as_ref().unwrap();
+    assert_eq!((name.lineno, name.colno, name.bytes.len()), (8, 8, 4));
+}
+
+#[test]
+fn files_and_layers_name_their_file() {
+    let dir = std::env::temp_dir().join(format!("toml-rust-sources-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.toml"), "[server]\nport = 1\nhost = \"h\"\nl = [1]\n").unwrap();
+    fs::write(dir.join("b.toml"), "\n[server]\nport = 2\nl = [2, 3]\n").unwrap();
+
+    let (_, sources) = load_with_sources(dir.join("a.toml"), None, None).unwrap();
+    assert_eq!(sources.get(&["server", "port"]).unwrap().to_string(), format!("{}:2:1", dir.join("a.toml").display()));
+
+    let (_, report) = LayeredLoader::new(None).file(dir.join("a.toml")).file(dir.join("b.toml")).arrays(MergePolicy::Append).load().unwrap();
+    let file = |path: &[&str]| report.sources.get(path).unwrap().file.clone().unwrap();
+    assert!(file(&["server", "port"]).ends_with("b.toml"));
+    assert_eq!(report.sources.get(&["server", "port"]).unwrap().key.as_ref().unwrap().lineno, 3);
+    assert!(file(&["server", "host"]).ends_with("a.toml"));
+    assert!(file(&["server", "l", "0"]).ends_with("a.toml"));
+    assert!(file(&["server", "l", "2"]).ends_with("b.toml"));
+    assert_eq!(report.sources.get(&["server", "l", "2"]).unwrap().value.as_ref().unwrap().colno, 9);
+}
@@ -0,0 +1,76 @@
+//! Decoding into tables backed by other `TableLike` maps.
+
+use std::collections::BTreeMap;
+
+use toml_rust::{dumps, loads, Table, TableLike, TomlDecoder, Value};
+
+const SRC: &str = "b = 1\na = [{ y = 1, x = 2 }]\n[t]\nd = 2\nc.f = 3\nc.e = 4\n";
+
+/// Keys of the table at `path`, in iteration order.
+fn keys<'a>(t: &'a Table, path: &[&str]) -> Vec<&'a str> {
+    let t = path.iter().fold(t, |t, k| t[*k].as_table().unwrap());
+    t.keys().map(String::as_str).collect()
+}
+
+#[test]
+fn btree_backing_sorts_every_table() {
+    let t = loads(SRC, Some(Table::backed::<BTreeMap<String, Value>>), None).unwrap();
+    assert_eq!(keys(&t, &[]), ["a", "b", "t"]);
+    assert_eq!(keys(&t, &["t"]), ["c", "d"]);
+    assert_eq!(keys(&t, &["t", "c"]), ["e", "f"]);
+    let Value::Array(a) = &t["a"] else { panic!("a is an array") };
+    assert_eq!(a[0].as_table().unwrap().keys().collect::<Vec<_>>(), ["x", "y"]);
+    assert_eq!(dumps(&Value::Table(t.clone()), None), "a = [ { x = 2, y = 1 },]\nb = 1\n\n[t]\nd = 2\n\n[t.c]\ne = 4\nf = 3\n");
+}
+
+/// A user-defined backing: a plain list, newest entry first.
+#[derive(Debug, Clone, Default)]
+struct Stack(Vec<(String, Value)>);
+
+impl TableLike for Stack {
+    fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        let old = self.remove(&key);
+        self.0.insert(0, (key, value));
+        old
+    }
+
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        self.0.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &Value)> + '_> {
+        Box::new(self.0.iter().map(|(k, v)| (k, v)))
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        let i = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(i).1)
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (&String, &mut Value)> + '_> {
+        Box::new(self.0.iter_mut().map(|(k, v)| (&*k, v)))
+    }
+}
+
+#[test]
+fn custom_backing_receives_the_decoded_keys() {
+    let t = loads(SRC, Some(Table::backed::<Stack>), None).unwrap();
+    assert_eq!(keys(&t, &[]), ["t", "a", "b"]);
+    assert_eq!(keys(&t, &["t"]), ["c", "d"]);
+    assert_eq!(keys(&t, &["t", "c"]), ["e", "f"]);
+    let Value::Array(a) = &t["a"] else { panic!("a is an array") };
+    assert_eq!(a[0].as_table().unwrap().keys().collect::<Vec<_>>(), ["x", "y"]);
+    assert_eq!(t, loads(SRC, None, None).unwrap());
+
+    let decoder = TomlDecoder::new(Some(Table::backed::<Stack>));
+    let t = loads(SRC, None, Some(&decoder)).unwrap();
+    assert_eq!(keys(&t, &["t", "c"]), ["e", "f"]);
+}
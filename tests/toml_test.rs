@@ -0,0 +1,116 @@
+//! Runs the toml-test fixtures under `tests/toml-test` against `load`.
+//!
+//! Every `valid/**/*.toml` must decode to the value described by the
+//! `.json` file next to it, in toml-test's tagged format
+//! (`{"type": "integer", "value": "42"}`), and every `invalid/**/*.toml`
+//! must be rejected.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use serde_json::Value as Json;
+use toml_rust::{load, Datetime, Table, Value};
+
+fn fixtures(kind: &str) -> Vec<PathBuf> {
+    fn walk(dir: &Path, found: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(&path, found);
+            } else if path.extension().is_some_and(|ext| ext == "toml") {
+                found.push(path);
+            }
+        }
+    }
+    let mut found = Vec::new();
+    walk(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/toml-test").join(kind), &mut found);
+    found.sort();
+    found
+}
+
+/// Compares a decoded table with its tagged JSON description.
+fn table_matches(table: &Table, expected: &Json) -> Result<(), String> {
+    let expected = expected.as_object().ok_or("expected a table")?;
+    if table.len() != expected.len() {
+        let mut keys: Vec<_> = table.keys().collect();
+        keys.sort();
+        return Err(format!("expected keys {:?}, got {:?}", expected.keys().collect::<Vec<_>>(), keys));
+    }
+    for (key, expected) in expected {
+        let value = table.get(key).ok_or_else(|| format!("missing key {:?}", key))?;
+        value_matches(value, expected).map_err(|e| format!("{}: {}", key, e))?;
+    }
+    Ok(())
+}
+
+fn value_matches(value: &Value, expected: &Json) -> Result<(), String> {
+    if let Json::Array(expected) = expected {
+        let Value::Array(items) = value else {
+            return Err(format!("expected an array, got {:?}", value));
+        };
+        if items.len() != expected.len() {
+            return Err(format!("expected {} items, got {}", expected.len(), items.len()));
+        }
+        for (i, (item, expected)) in items.iter().zip(expected).enumerate() {
+            value_matches(item, expected).map_err(|e| format!("[{}]: {}", i, e))?;
+        }
+        return Ok(());
+    }
+    let tag = expected.get("type").and_then(Json::as_str);
+    let raw = expected.get("value").and_then(Json::as_str);
+    let (Some(tag), Some(raw), 2) = (tag, raw, expected.as_object().map_or(0, |o| o.len())) else {
+        let Value::Table(table) = value else {
+            return Err(format!("expected a table, got {:?}", value));
+        };
+        return table_matches(table, expected);
+    };
+    let matches = match (tag, value) {
+        ("string", Value::String(s)) => s == raw,
+        ("integer", Value::Integer(i)) => raw.parse() == Ok(*i),
+        ("float", Value::Float(f)) => match raw.trim_start_matches('+') {
+            "nan" => f.is_nan(),
+            "inf" => *f == f64::INFINITY,
+            "-inf" => *f == f64::NEG_INFINITY,
+            raw => raw.parse() == Ok(*f),
+        },
+        ("bool", Value::Boolean(b)) => raw.parse() == Ok(*b),
+        ("datetime", Value::Datetime(Datetime::OffsetDateTime(dt, _))) => DateTime::parse_from_rfc3339(raw).is_ok_and(|expected| expected == *dt),
+        ("datetime-local", Value::Datetime(Datetime::LocalDateTime(dt, _))) => NaiveDateTime::parse_from_str(raw, "%Y-%m-%dT%H:%M:%S%.f") == Ok(*dt),
+        ("date-local", Value::Datetime(Datetime::LocalDate(d))) => NaiveDate::parse_from_str(raw, "%Y-%m-%d") == Ok(*d),
+        ("time-local", Value::Datetime(Datetime::LocalTime(t, _))) => NaiveTime::parse_from_str(raw, "%H:%M:%S%.f") == Ok(*t),
+        _ => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(format!("expected {} {:?}, got {:?}", tag, raw, value))
+    }
+}
+
+#[test]
+fn valid() {
+    let mut failures = Vec::new();
+    for path in fixtures("valid") {
+        let expected: Json = serde_json::from_str(&fs::read_to_string(path.with_extension("json")).unwrap()).unwrap();
+        let result = match load(&path, None, None) {
+            Ok(table) => table_matches(&table, &expected),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            failures.push(format!("{}: {}", path.display(), e));
+        }
+    }
+    assert!(failures.is_empty(), "{} valid fixtures failed:\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn invalid() {
+    let mut failures = Vec::new();
+    for path in fixtures("invalid") {
+        if let Ok(table) = load(&path, None, None) {
+            failures.push(format!("{}: decoded to {:?}", path.display(), table));
+        }
+    }
+    assert!(failures.is_empty(), "{} invalid fixtures were accepted:\n{}", failures.len(), failures.join("\n"));
+}
@@ -0,0 +1,79 @@
+//! `TomlTz` as a chrono `TimeZone` and `Offset`.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, NaiveDate, Offset, TimeZone, Timelike, Utc};
+use serde::Deserialize;
+use toml_rust::{from_str, loads, Datetime, TomlTz};
+
+fn tz(s: &str) -> TomlTz {
+    s.parse().unwrap()
+}
+
+#[test]
+fn is_a_chrono_time_zone() {
+    let utc = NaiveDate::from_ymd_opt(1979, 5, 27).unwrap().and_hms_opt(14, 32, 0).unwrap();
+    let pdt = tz("-07:00");
+    assert_eq!(TomlTz::from_offset(&pdt), pdt);
+    assert_eq!(pdt.offset_from_utc_datetime(&utc), pdt);
+    assert_eq!(pdt.fix().local_minus_utc(), -7 * 3600);
+
+    let dt = pdt.from_utc_datetime(&utc);
+    assert_eq!(dt.hour(), 7);
+    assert_eq!(dt.to_rfc3339(), "1979-05-27T07:32:00-07:00");
+    assert_eq!(pdt.with_ymd_and_hms(1979, 5, 27, 7, 32, 0).unwrap(), dt);
+    assert_eq!(dt.with_timezone(&Utc).naive_utc(), utc);
+}
+
+#[test]
+fn malformed_offsets_are_errors() {
+    for s in ["+5:00", "+24:00", "+05:60", "junk", "", "+05:00:00", "05:00", "zz"] {
+        assert!(s.parse::<TomlTz>().is_err(), "{}", s);
+    }
+    assert!(loads("a = 1979-05-27T07:32:00+24:00\n", None, None).is_err());
+}
+
+#[test]
+fn displays_as_written() {
+    for s in ["+05:30", "-07:00", "+00:00", "-00:00", "Z", "z"] {
+        assert_eq!(tz(s).to_string(), s);
+    }
+    assert_eq!(TomlTz::from(chrono::FixedOffset::east_opt(0).unwrap()).to_string(), "Z");
+    assert_eq!(TomlTz::from(chrono::FixedOffset::west_opt(9000).unwrap()).to_string(), "-02:30");
+}
+
+#[test]
+fn equal_offsets_hash_alike() {
+    assert_eq!(tz("-00:00"), tz("+00:00"));
+    assert_eq!(tz("Z"), tz("-00:00"));
+    assert_ne!(tz("+01:00"), tz("-01:00"));
+    let set: HashSet<TomlTz> = ["Z", "z", "+00:00", "-00:00", "+01:00"].into_iter().map(tz).collect();
+    assert_eq!(set.len(), 2);
+}
+
+#[test]
+fn serde_round_trips() {
+    let json = serde_json::to_string(&tz("-07:00")).unwrap();
+    assert_eq!(json, "\"-07:00\"");
+    assert_eq!(serde_json::from_str::<TomlTz>(&json).unwrap(), tz("-07:00"));
+    assert!(serde_json::from_str::<TomlTz>("\"+5:00\"").is_err());
+
+    #[derive(Deserialize)]
+    struct Config {
+        tz: TomlTz,
+    }
+    assert_eq!(from_str::<Config>("tz = \"z\"\n").unwrap().tz.to_string(), "z");
+}
+
+#[test]
+fn decoded_datetimes_are_chrono_datetimes() {
+    let t = loads("a = 1979-05-27T00:32:00.5-07:00\n", None, None).unwrap();
+    let dt: &DateTime<TomlTz> = t["a"].as_offset_datetime().unwrap();
+    assert_eq!(*dt.offset(), tz("-07:00"));
+    assert_eq!(dt.with_timezone(&Utc).to_rfc3339(), "1979-05-27T07:32:00.500+00:00");
+    assert_eq!(dt.fixed_offset().to_rfc3339(), "1979-05-27T00:32:00.500-07:00");
+    let later = *dt + chrono::Duration::hours(1);
+    assert_eq!(later.hour(), 1);
+    let owned: DateTime<TomlTz> = (*t["a"].as_datetime().unwrap()).try_into().unwrap();
+    assert_eq!(Datetime::from(owned + chrono::Duration::hours(1)).to_string(), "1979-05-27T01:32:00.5-07:00");
+}